@@ -163,6 +163,23 @@ async fn launch_worker(
     Ok((handle, buffer_size, n_channels))
 }
 
+/// Whether the playback device called `friendly_name` should be captured when asked for `device_name`.
+fn device_matches(friendly_name: &str, device_name: &str) -> bool {
+    friendly_name.contains(device_name)
+}
+
+/// Returns the names of all playback devices. Passing one of them to [AudioCapturer::start] captures that device.
+pub fn available_devices() -> SimpleResult<Vec<String>> {
+    // COM may already have been initialized on this thread, which is fine
+    let _ = wasapi::initialize_mta();
+    let devices = wasapi::DeviceCollection::new(&Direction::Render)
+        .map_err(|e| SimpleError::new(format!("Could not list playback devices: {}", e)))?;
+    Ok(devices
+        .into_iter()
+        .filter_map(|device| device.ok()?.get_friendlyname().ok())
+        .collect())
+}
+
 /// All data needed to run the worker thread
 pub struct AudioCaptureData {
     capture_client: AudioCaptureClient,
@@ -182,7 +199,7 @@ impl AudioCaptureData {
                 if let Ok(dev) = device {
                     return dev
                         .get_friendlyname()
-                        .map_or(false, |name| name.contains(device_name));
+                        .map_or(false, |name| device_matches(&name, device_name));
                 }
                 return false;
             })?
@@ -4,6 +4,10 @@ use tokio_util::sync::CancellationToken;
 
 use self::intensity_gate::add_intensity_gate;
 
+#[cfg(windows)]
+mod audio_source;
+#[cfg(not(windows))]
+#[path = "unsupported_audio_source.rs"]
 mod audio_source;
 mod wave_to_intensity;
 mod intensity_gate;
@@ -22,6 +26,8 @@ pub enum IntensitySourceEvent {
 /// indicate when it is producing values.
 pub trait IntensitySource = Stream<Item = IntensitySourceEvent>;
 
+pub use audio_source::available_devices;
+
 pub fn capture_intensity_from_audio_device(
     device_name: String,
     cancel_token: CancellationToken,
@@ -0,0 +1,35 @@
+//! Stands in for the WASAPI audio source on platforms other than Windows, where audio can't be captured. Devices never
+//! produce audio, so the intensity stays at its idle value.
+use simple_error::{SimpleError, SimpleResult};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub struct AudioCapturer;
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum AudioCaptureEvent {
+    PlaybackStarted,
+    BufferProduced(Vec<f32>),
+    PlaybackStopped,
+}
+
+impl AudioCapturer {
+    pub async fn start(
+        device_name: &str,
+        _cancel_token: CancellationToken,
+    ) -> SimpleResult<(Self, mpsc::Receiver<AudioCaptureEvent>)> {
+        Err(SimpleError::new(format!("Can't capture audio from '{}', audio capture is only supported on Windows", device_name)))
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        0
+    }
+    pub fn n_channels(&self) -> u16 {
+        0
+    }
+}
+
+pub fn available_devices() -> SimpleResult<Vec<String>> {
+    Err(SimpleError::new("Audio devices can only be listed on Windows"))
+}
@@ -1,5 +1,8 @@
 #![allow(clippy::excessive_precision)]
 #![feature(trait_alias)]
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use log::debug;
 use tokio::sync::watch;
 use tokio::time;
@@ -12,12 +15,28 @@ mod intensity_source;
 
 pub type AudioIntensity = f32;
 
+/// Returns the names of all audio playback devices, any of which can be passed to
+/// [AudioCaptureController::set_audio_devices].
+pub fn available_audio_devices() -> simple_error::SimpleResult<Vec<String>> {
+    intensity_source::available_devices()
+}
+
 /// Captures audio data and converts it to a stream of intensity/loudness values.
 ///
 /// Audio stops begin captured when the controller is dropped.
 pub struct AudioCaptureController {
     intensity_tx: watch::Sender<AudioIntensity>,
     worker_thread: Option<(std::thread::JoinHandle<()>, CancellationToken)>,
+    stats: Arc<AudioStats>,
+}
+
+/// Counters describing the work done by an [AudioCaptureController] since it was created.
+#[derive(Debug, Default)]
+pub struct AudioStats {
+    /// The number of intensity values sent from audio devices.
+    pub values_sent: AtomicU64,
+    /// The number of constant values sent because no audio device was playing audio.
+    pub idle_values_sent: AtomicU64,
 }
 
 impl AudioCaptureController {
@@ -28,6 +47,7 @@ impl AudioCaptureController {
         let mut controller = AudioCaptureController {
             intensity_tx,
             worker_thread: None,
+            stats: Arc::new(AudioStats::default()),
         };
         controller.set_audio_devices(vec![]);
         (controller, intensity_rx)
@@ -41,10 +61,14 @@ impl AudioCaptureController {
     pub fn set_audio_devices(&mut self, audio_devices: Vec<String>) {
         self.stop_worker();
         let cancel_token = CancellationToken::new();
-        let handle = capture_audio(audio_devices, self.intensity_tx.clone(), cancel_token.clone());
+        let handle = capture_audio(audio_devices, self.intensity_tx.clone(), cancel_token.clone(), self.stats.clone());
         self.worker_thread = Some((handle, cancel_token));
     }
 
+    pub fn stats(&self) -> &AudioStats {
+        &self.stats
+    }
+
     fn stop_worker(&mut self) {
         if let Some((handle, cancel_token)) = self.worker_thread.take() {
             cancel_token.cancel();
@@ -65,6 +89,7 @@ fn capture_audio(
     device_names: Vec<String>,
     intensity_tx: watch::Sender<AudioIntensity>,
     cancel_token: CancellationToken,
+    stats: Arc<AudioStats>,
 ) -> std::thread::JoinHandle<()> {
     std::thread::Builder::new().name("AudioCapture".to_string()).spawn(move || {
         let task = async move {
@@ -99,6 +124,7 @@ fn capture_audio(
                                     if intensity_tx.send(intensity).is_err() {
                                         break;
                                     }
+                                    stats.values_sent.fetch_add(1, Ordering::Relaxed);
                                     timeout.as_mut().reset(time::Instant::now() + time::Duration::from_millis(200));
                                 }
                             },
@@ -111,6 +137,7 @@ fn capture_audio(
                         if intensity_tx.send(1.0).is_err() {
                             break;
                         }
+                        stats.idle_values_sent.fetch_add(1, Ordering::Relaxed);
                     }
                     _ = cancel_token.cancelled() => break,
                 }
@@ -2,16 +2,7 @@ use color::RgbU8;
 use simple_error::{SimpleResult, SimpleError, try_with};
 use windows::{Win32::{Graphics::{Direct3D11::{ID3D11Device, D3D11CreateDevice, D3D11_SDK_VERSION, D3D11_CREATE_DEVICE_FLAG, ID3D11Texture2D, ID3D11DeviceContext, ID3D11ShaderResourceView, D3D11_MAP_READ, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_RESOURCE_MISC_GENERATE_MIPS, D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_USAGE_STAGING, D3D11_CPU_ACCESS_READ}, Dxgi::{IDXGIOutputDuplication, IDXGIOutput1, IDXGIDevice, IDXGIAdapter1, DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_DEVICE_REMOVED, DXGI_ERROR_INVALID_CALL, Common::DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_ERROR_WAIT_TIMEOUT}, Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_FEATURE_LEVEL_9_1, D3D11_SRV_DIMENSION_TEXTURE2D}}, Foundation::DUPLICATE_HANDLE_OPTIONS}, core::Interface};
 
-/// A captured desktop frame.
-#[derive(Clone, Debug)]
-pub struct Frame {
-    /// The pixels
-    pub buffer: Vec<RgbU8>,
-    pub height: usize,
-    pub width: usize,
-    /// The quotient between the original monitor dimensions and the dimensions of this frame.
-    pub downscaling: u32,
-}
+use crate::{Frame, MonitorInfo};
 
 struct OutputDuplication {
     winapi_duplication: IDXGIOutputDuplication,
@@ -56,21 +47,7 @@ impl DesktopDuplicator {
     /// `mip_level` - The mip level to use for captured textures. Determines the resolution of captured frames.
     /// `timeout` - The time for [capture_frame] to wait for a new frame before failing.
     pub fn new(capture_monitor_index: u32, mip_level: u32, timeout: std::time::Duration) -> SimpleResult<Self> {
-        let device = try_with!(unsafe {
-                let mut device: Option<ID3D11Device> = None;
-                let res = D3D11CreateDevice(
-                    None,
-                    D3D_DRIVER_TYPE_HARDWARE,
-                    None,
-                    D3D11_CREATE_DEVICE_FLAG(0),
-                    &[],
-                    D3D11_SDK_VERSION,
-                    &mut device,
-                    &mut D3D_FEATURE_LEVEL_9_1,
-                    std::ptr::null_mut()
-                );
-                res.map(|_| device.unwrap())
-            }, "Could not create d3d11 device");
+        let device = create_d3d11_device()?;
         let duplication = Self::acquire_duplication(&device, capture_monitor_index)?;
 
         let resources = Self::initialize_resources(&duplication, &device, mip_level)?;
@@ -218,4 +195,45 @@ impl DesktopDuplicator {
             mip_level,
         })
     }
-}
\ No newline at end of file
+}
+
+fn create_d3d11_device() -> SimpleResult<ID3D11Device> {
+    Ok(try_with!(unsafe {
+            let mut device: Option<ID3D11Device> = None;
+            let res = D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE_HARDWARE,
+                None,
+                D3D11_CREATE_DEVICE_FLAG(0),
+                &[],
+                D3D11_SDK_VERSION,
+                &mut device,
+                &mut D3D_FEATURE_LEVEL_9_1,
+                std::ptr::null_mut()
+            );
+            res.map(|_| device.unwrap())
+        }, "Could not create d3d11 device"))
+}
+
+/// Lists the monitors that can be captured, in the order used by [DesktopDuplicator::new].
+pub fn available_monitors() -> SimpleResult<Vec<MonitorInfo>> {
+    let device = create_d3d11_device()?;
+    let dxgi_device: IDXGIDevice = try_with!(device.cast(), "Could not get dxgi device");
+    let adapter: IDXGIAdapter1 = unsafe { try_with!(dxgi_device.GetParent(), "Could not get adapter") };
+    let mut monitors = Vec::new();
+    // EnumOutputs fails once the index is past the last output
+    while let Ok(output) = unsafe { adapter.EnumOutputs(monitors.len() as u32) } {
+        let desc = unsafe { try_with!(output.GetDesc(), "Could not get output description") };
+        let name_len = desc.DeviceName.iter().position(|c| *c == 0).unwrap_or(desc.DeviceName.len());
+        let coords = desc.DesktopCoordinates;
+        monitors.push(MonitorInfo {
+            index: monitors.len() as u32,
+            name: String::from_utf16_lossy(&desc.DeviceName[..name_len]),
+            left: coords.left,
+            top: coords.top,
+            width: (coords.right - coords.left) as u32,
+            height: (coords.bottom - coords.top) as u32,
+        });
+    }
+    Ok(monitors)
+}
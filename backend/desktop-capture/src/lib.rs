@@ -1,17 +1,74 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use color::RgbU8;
 use log::debug;
 
 use tokio::sync::{watch, mpsc};
 use tokio_util::sync::CancellationToken;
 
+#[cfg(windows)]
+mod desktop_duplicator;
+#[cfg(not(windows))]
+#[path = "unsupported_duplicator.rs"]
 mod desktop_duplicator;
 
-pub use desktop_duplicator::Frame;
+/// How long to wait for a new desktop frame before reusing the last one
+const DUPLICATOR_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+pub use desktop_duplicator::available_monitors;
+
+/// A captured desktop frame.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The pixels
+    pub buffer: Vec<RgbU8>,
+    pub height: usize,
+    pub width: usize,
+    /// The quotient between the original monitor dimensions and the dimensions of this frame.
+    pub downscaling: u32,
+}
+
+/// A monitor that frames can be captured from.
+#[derive(Debug, Clone)]
+pub struct MonitorInfo {
+    /// The index to pass to [DesktopCaptureController::set_capture_monitor].
+    pub index: u32,
+    /// The name Windows uses for the monitor, e.g. `\\.\DISPLAY1`.
+    pub name: String,
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
 
 pub struct DesktopCaptureController {
     worker_thread: Option<std::thread::JoinHandle<()>>,
     cancel_token: CancellationToken,
     running: mpsc::Sender<bool>,
     monitor_select: mpsc::Sender<u32>,
+    settings: mpsc::Sender<CaptureSettings>,
+    stats: Arc<CaptureStats>,
+}
+
+/// The settings a [DesktopCaptureController] was created with, which can be changed while it is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CaptureSettings {
+    fps: f32,
+    decimation_amount: u32,
+}
+
+/// Counters describing the work done by a [DesktopCaptureController] since it was created.
+#[derive(Debug, Default)]
+pub struct CaptureStats {
+    /// The number of frames sent to receivers, including repeats of the last frame when the desktop didn't change.
+    pub frames_sent: AtomicU64,
+    /// The number of new frames captured from the desktop.
+    pub frames_captured: AtomicU64,
+    /// The total time spent capturing new frames, in nanoseconds.
+    pub capture_nanos: AtomicU64,
+    pub capture_errors: AtomicU64,
 }
 
 #[derive(Debug, Clone)]
@@ -38,15 +95,24 @@ impl DesktopCaptureController {
         let (monitor_select_tx, monitor_select_rx) = mpsc::channel(8);
         let cancel_token = CancellationToken::new();
         let (running_tx, running_rx) = mpsc::channel(2);
-        let handle = capture_desktop_frames(fps, decimation_amount, frame_tx, monitor_select_rx, cancel_token.clone(), running_rx);
+        let (settings_tx, settings_rx) = mpsc::channel(2);
+        let stats = Arc::new(CaptureStats::default());
+        let settings = CaptureSettings { fps, decimation_amount };
+        let handle = capture_desktop_frames(settings, frame_tx, monitor_select_rx, settings_rx, cancel_token.clone(), running_rx, stats.clone());
         (DesktopCaptureController{
             cancel_token,
             worker_thread: Some(handle),
             monitor_select: monitor_select_tx,
+            settings: settings_tx,
             running: running_tx,
+            stats,
         }, frame_rx)
     }
 
+    pub fn stats(&self) -> &CaptureStats {
+        &self.stats
+    }
+
     /// Starts capturing frames.
     ///
     /// Runs until [stop] is called, the struct is dropped or all receivers are closed.
@@ -63,6 +129,13 @@ impl DesktopCaptureController {
             log::error!("Failed to set captured monitor, the capture thread has probably already exited");
         }
     }
+
+    /// Changes the number of frames generated each second, and the `decimation_amount` (see [Self::new]).
+    pub async fn set_capture_settings(&self, fps: f32, decimation_amount: u32) {
+        if self.settings.send(CaptureSettings { fps, decimation_amount }).await.is_err() {
+            log::error!("Failed to change capture settings, the capture thread has probably already exited");
+        }
+    }
 }
 
 impl Drop for DesktopCaptureController {
@@ -75,12 +148,13 @@ impl Drop for DesktopCaptureController {
 }
 
 fn capture_desktop_frames(
-    fps: f32,
-    decimation_amount: u32,
+    initial_settings: CaptureSettings,
     frames_tx: watch::Sender<FrameCaptureEvent>,
     mut monitor_index: mpsc::Receiver<u32>,
+    mut settings_rx: mpsc::Receiver<CaptureSettings>,
     cancel_token: CancellationToken,
     mut running_rx: mpsc::Receiver<bool>,
+    stats: Arc<CaptureStats>,
 ) -> std::thread::JoinHandle<()> {
     // Since parts of the windows API are not Send, this cannot be run in a multi-threaded tokio runtime. Instead, we
     // spawn a new thread for it and run a single-threaded blocking runtime.
@@ -88,8 +162,10 @@ fn capture_desktop_frames(
         let task = async move {
             let mut last_frame: Option<Frame> = None;
             // let mut manager = dxgcap::DXGIManager::new(100).map_err(SimpleError::new).expect("Could not create desktop capturer");
-            let mut manager = desktop_duplicator::DesktopDuplicator::new(0, decimation_amount, std::time::Duration::from_millis(200)).expect("Could not open desktop duplicator");
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs_f32(1.0/fps));
+            let mut settings = initial_settings;
+            let mut current_monitor = 0;
+            let mut manager = desktop_duplicator::DesktopDuplicator::new(current_monitor, settings.decimation_amount, DUPLICATOR_TIMEOUT).expect("Could not open desktop duplicator");
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs_f32(1.0/settings.fps));
 
             let mut is_running = false;
 
@@ -98,6 +174,9 @@ fn capture_desktop_frames(
                 while !is_running && !cancel_token.is_cancelled() {
                     tokio::select! {
                         Some(value) = running_rx.recv() => is_running = value,
+                        Some(new_settings) = settings_rx.recv() => {
+                            apply_settings(&mut settings, new_settings, &mut manager, &mut interval, current_monitor);
+                        },
                         _ = cancel_token.cancelled() => break,
                     }
                 }
@@ -105,9 +184,12 @@ fn capture_desktop_frames(
                     tokio::select! {
                         _ = interval.tick() => {
                             // Capture a frame if needed
+                            let capture_start = Instant::now();
                             match manager.capture_frame() {
-                                Err(e) => log_capture_err(e),
+                                Err(e) => log_capture_err(e, &stats),
                                 Ok(frame_info) => {
+                                    stats.capture_nanos.fetch_add(capture_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                                    stats.frames_captured.fetch_add(1, Ordering::Relaxed);
                                     last_frame = Some(frame_info);
                                 },
                             };
@@ -117,13 +199,18 @@ fn capture_desktop_frames(
                                     // All receivers have been dropped
                                     break;
                                 }
+                                stats.frames_sent.fetch_add(1, Ordering::Relaxed);
                             }
                         }, /* interval */
                         Some(index) = monitor_index.recv() => {
-                            if let Err(e) = manager.set_capture_monitor_index(index) {
-                                log::error!("Failed to set capture monitor: {}", e);
+                            match manager.set_capture_monitor_index(index) {
+                                Ok(_) => current_monitor = index,
+                                Err(e) => log::error!("Failed to set capture monitor: {}", e),
                             }
                         },
+                        Some(new_settings) = settings_rx.recv() => {
+                            apply_settings(&mut settings, new_settings, &mut manager, &mut interval, current_monitor);
+                        },
                         _ = cancel_token.cancelled() => {
                             // We've been requested to stop, quit the loop and finish this task
                             break;
@@ -147,10 +234,33 @@ fn capture_desktop_frames(
     }).unwrap()
 }
 
-fn log_capture_err(err: desktop_duplicator::CaptureError) {
+/// Switches the capture thread over to `new_settings`. Keeps the current settings if the new ones can't be applied.
+fn apply_settings(settings: &mut CaptureSettings, new_settings: CaptureSettings, manager: &mut desktop_duplicator::DesktopDuplicator,
+                  interval: &mut tokio::time::Interval, current_monitor: u32) {
+    if new_settings.decimation_amount != settings.decimation_amount {
+        // The decimation determines the size of the textures the duplicator allocates, so it has to be recreated
+        match desktop_duplicator::DesktopDuplicator::new(current_monitor, new_settings.decimation_amount, DUPLICATOR_TIMEOUT) {
+            Ok(new_manager) => {
+                *manager = new_manager;
+                settings.decimation_amount = new_settings.decimation_amount;
+            },
+            Err(e) => log::error!("Failed to change capture decimation: {}", e),
+        }
+    }
+    if new_settings.fps != settings.fps {
+        *interval = tokio::time::interval(std::time::Duration::from_secs_f32(1.0/new_settings.fps));
+        settings.fps = new_settings.fps;
+    }
+    debug!("Capturing at {} fps with decimation {}", settings.fps, settings.decimation_amount);
+}
+
+fn log_capture_err(err: desktop_duplicator::CaptureError, stats: &CaptureStats) {
     match err {
         desktop_duplicator::CaptureError::Timeout => (),
-        desktop_duplicator::CaptureError::Other(err) => log::error!("Desktop Capture: {}", err),
+        desktop_duplicator::CaptureError::Other(err) => {
+            stats.capture_errors.fetch_add(1, Ordering::Relaxed);
+            log::error!("Desktop Capture: {}", err)
+        },
     };
 }
 
@@ -0,0 +1,33 @@
+//! Stands in for the desktop duplicator on platforms other than Windows, where the desktop can't be captured. No
+//! frames are ever captured, so only sources that don't depend on the desktop are shown.
+use simple_error::{SimpleError, SimpleResult};
+
+use crate::{Frame, MonitorInfo};
+
+pub struct DesktopDuplicator;
+
+pub enum CaptureError {
+    Timeout,
+    #[allow(dead_code)]
+    Other(SimpleError),
+}
+
+impl DesktopDuplicator {
+    pub fn new(_capture_monitor_index: u32, _mip_level: u32, _timeout: std::time::Duration) -> SimpleResult<Self> {
+        log::warn!("Desktop capture is only supported on Windows");
+        Ok(DesktopDuplicator)
+    }
+
+    /// Never captures a frame, as if the desktop never changed.
+    pub fn capture_frame(&mut self) -> Result<Frame, CaptureError> {
+        Err(CaptureError::Timeout)
+    }
+
+    pub fn set_capture_monitor_index(&mut self, _capture_monitor_index: u32) -> SimpleResult<()> {
+        Ok(())
+    }
+}
+
+pub fn available_monitors() -> SimpleResult<Vec<MonitorInfo>> {
+    Err(SimpleError::new("Monitors can only be listed on Windows"))
+}
@@ -1,8 +1,9 @@
+use serde::Deserialize;
 
 pub type RgbVec = Vec<color::RgbF32>;
 pub type HsvVec = Vec<color::HsvF32>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub struct Rect {
     pub left: isize,
     pub top: isize,
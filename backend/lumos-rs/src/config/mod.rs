@@ -0,0 +1,271 @@
+//! Command line arguments and the TOML configuration file, which allow running without the Tauri app.
+//!
+//! See `lumos.example.toml` for an example configuration file.
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+use simple_error::{SimpleError, SimpleResult, try_with};
+
+use crate::common::Rect;
+use crate::profiles;
+use crate::render_service;
+use crate::scheduler;
+use crate::websocket;
+use crate::websocket::protocol::{ConditionSpec, DeviceEntry, ProfileEntry};
+
+mod watcher;
+pub use watcher::ConfigWatcher;
+
+/// Renders the colors of your desktop and audio to LED devices.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Args {
+    /// The port to listen for websocket clients on. Overrides `websocket_port` in the configuration file.
+    #[arg(long)]
+    pub port: Option<u32>,
+    /// The TOML configuration file to use. Defaults to `lumos/lumos.toml` in the user's configuration directory, which
+    /// may not exist.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// The minimum level of log messages to show.
+    #[arg(long, default_value = "debug")]
+    pub log_level: simplelog::LevelFilter,
+    /// Also writes log messages to this file.
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+}
+
+/// The settings that are read from the configuration file.
+///
+/// The file is watched while the application is running (see [ConfigWatcher]), and changes are applied without
+/// restarting, except for the ports and addresses of the servers.
+#[derive(Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub desktop_capture_fps: f32,
+    /// How much to reduce the resolution of captured frames, to improve performance (the resolution is halved this
+    /// number of times)
+    pub desktop_capture_decimation: u32,
+    pub websocket_port: u32,
+    /// The port to serve Prometheus metrics on.
+    pub metrics_port: u32,
+    /// The port to receive colors from other programs on, over UDP (see [crate::input]).
+    pub input_port: u32,
+    /// The port to serve the Hyperion JSON API on (see [crate::hyperion]), or [None] to not serve it.
+    pub hyperion_port: Option<u32>,
+    /// The address to serve the Hyperion JSON API on. Remotes on other machines need e.g. `0.0.0.0`.
+    pub hyperion_address: IpAddr,
+    /// The position and resolution of each monitor, in the order used by the desktop capturer.
+    pub monitors: Vec<Rect>,
+    /// The region of monitor 0 to capture for horizontal samplers when no profile is active.
+    pub default_capture_region_hor: Rect,
+    /// The region of monitor 0 to capture for vertical samplers when no profile is active.
+    pub default_capture_region_ver: Rect,
+    /// Devices to start with instead of the stored ones (see [crate::store]), in the format of the websocket protocol.
+    pub devices: Option<Vec<DeviceEntry>>,
+    /// Profiles to start with instead of the stored ones (see [crate::store]), in the format of the websocket protocol.
+    pub profiles: Option<Vec<ProfileEntry>>,
+    /// Windows that never activate or deactivate profiles, e.g. overlays or the task switcher. In the format of
+    /// [ProfileEntry::condition].
+    pub ignored_windows: Vec<ConditionSpec>,
+    /// How long a window has to stay focused before the profile it matches is activated.
+    pub profile_activation_delay_ms: u64,
+    /// How long a window without a profile has to stay focused before the active profile is deactivated.
+    pub profile_deactivation_delay_ms: u64,
+    /// The brightness by time of day, interpolated between the points. Multiplies the brightness set by clients.
+    pub brightness_schedule: Vec<BrightnessPoint>,
+    /// Turns the output off after neither the focused window nor the screen changed for this long. Only applies while
+    /// the desktop is captured, i.e. while a profile is active.
+    pub idle_timeout_secs: Option<u64>,
+    /// Turns the output off after the screen was black for this long. Only applies while the desktop is captured.
+    pub black_screen_timeout_secs: Option<u64>,
+    /// Windows during which changes on screen don't count as activity, e.g. screensavers. In the format of
+    /// [ProfileEntry::condition].
+    pub idle_windows: Vec<ConditionSpec>,
+    /// How long devices take to crossfade to new colors, e.g. when capturing stops or a profile is activated.
+    pub transition_duration_ms: u64,
+    /// The priority of the colors sampled from the desktop, compared to other sources such as websocket clients (see
+    /// [render_service::SourceMux]).
+    pub capture_priority: i32,
+}
+
+/// A point of [Config::brightness_schedule].
+#[derive(Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BrightnessPoint {
+    /// The time of day, like `07:30`.
+    pub time: String,
+    pub brightness: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            desktop_capture_fps: 15.0,
+            desktop_capture_decimation: 2,
+            websocket_port: 9901,
+            metrics_port: 9902,
+            input_port: 9903,
+            hyperion_port: None,
+            hyperion_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            monitors: vec![
+                Rect{ left: 0, top: -8, width: 2560, height: 1440 },
+                Rect{ left: -1920, top: 0, width: 1920, height: 1080 },
+            ],
+            default_capture_region_hor: Rect{ left: 0, top: 840, width: 2560, height: 600 },
+            default_capture_region_ver: Rect{ left: 0, top: 0, width: 400, height: 1440 },
+            devices: None,
+            profiles: None,
+            ignored_windows: vec![
+                ConditionSpec::Title("^$".to_string()),
+                ConditionSpec::Title("^Task Switching$".to_string()),
+                ConditionSpec::Title("^Search$".to_string()),
+            ],
+            profile_activation_delay_ms: 300,
+            profile_deactivation_delay_ms: 1000,
+            brightness_schedule: Vec::new(),
+            idle_timeout_secs: None,
+            black_screen_timeout_secs: None,
+            idle_windows: vec![ConditionSpec::ProcessName(r"\.scr$".to_string())],
+            transition_duration_ms: 500,
+            capture_priority: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration for the given command line arguments.
+    ///
+    /// If no configuration file was given and the default one doesn't exist, the default configuration is used.
+    pub fn load(args: &Args) -> SimpleResult<Self> {
+        let mut config = match Self::file_path(args) {
+            Some(path) => Self::load_file(&path)?,
+            None => Config::default(),
+        };
+        if let Some(port) = args.port {
+            config.websocket_port = port;
+        }
+        Ok(config)
+    }
+
+    /// The configuration file to use for the given command line arguments, if any.
+    pub fn file_path(args: &Args) -> Option<PathBuf> {
+        match &args.config {
+            Some(path) => Some(path.clone()),
+            None => Some(dirs::config_dir()?.join("lumos").join("lumos.toml")).filter(|path| path.exists()),
+        }
+    }
+
+    fn load_file(path: &Path) -> SimpleResult<Self> {
+        let contents = try_with!(std::fs::read_to_string(path), "Could not read {}", path.display());
+        Self::parse(&contents).map_err(|e| SimpleError::new(format!("Invalid configuration in {}: {}", path.display(), e)))
+    }
+
+    fn parse(contents: &str) -> SimpleResult<Self> {
+        let config: Config = toml::from_str(contents).map_err(SimpleError::from)?;
+        if !(config.desktop_capture_fps > 0.0 && config.desktop_capture_fps.is_finite()) {
+            return Err(SimpleError::new("desktop_capture_fps must be positive"));
+        }
+        if config.monitors.is_empty() {
+            return Err(SimpleError::new("At least one monitor must be configured"));
+        }
+        // Reject the whole file rather than silently skipping devices or profiles, so mistakes are noticed
+        if let Some(devices) = &config.devices && let Some(error) = websocket::parse_devices(devices).1.first() {
+            return Err(SimpleError::new(format!("Invalid device {}: {}", error.index, error.message)));
+        }
+        if let Some(profiles) = &config.profiles && let Some(error) = websocket::parse_profiles(profiles).1.first() {
+            return Err(SimpleError::new(format!("Invalid profile {}: {}", error.index, error.message)));
+        }
+        config.ignored_windows()?;
+        config.schedule_settings()?;
+        Ok(config)
+    }
+
+    /// Parses [Config::ignored_windows].
+    pub fn ignored_windows(&self) -> SimpleResult<Vec<profiles::WindowCondition>> {
+        self.ignored_windows.iter()
+            .map(|condition| websocket::parse_condition(condition).map_err(|e| SimpleError::new(format!("Invalid ignored window: {}", e))))
+            .collect()
+    }
+
+    pub fn profile_grace_periods(&self) -> (Duration, Duration) {
+        (Duration::from_millis(self.profile_activation_delay_ms), Duration::from_millis(self.profile_deactivation_delay_ms))
+    }
+
+    pub fn transition_duration(&self) -> Duration {
+        Duration::from_millis(self.transition_duration_ms)
+    }
+
+    /// The source for the colors sampled from the desktop, with [Config::capture_priority].
+    pub fn capture_source(&self) -> render_service::Source {
+        render_service::Source {
+            name: Arc::from(render_service::CAPTURE_SOURCE),
+            priority: self.capture_priority,
+            until: None,
+            content: render_service::SourceContent::Capture,
+            opacity: 1.0,
+        }
+    }
+
+    /// Parses the settings for the [scheduler::Scheduler].
+    pub fn schedule_settings(&self) -> SimpleResult<scheduler::ScheduleSettings> {
+        let points = self.brightness_schedule.iter()
+            .map(|point| Ok((scheduler::parse_time_of_day(&point.time)?, point.brightness)))
+            .collect::<SimpleResult<Vec<_>>>()?;
+        let idle_windows = self.idle_windows.iter()
+            .map(|condition| websocket::parse_condition(condition).map_err(|e| SimpleError::new(format!("Invalid idle window: {}", e))))
+            .collect::<SimpleResult<Vec<_>>>()?;
+        Ok(scheduler::ScheduleSettings {
+            brightness_curve: try_with!(scheduler::BrightnessCurve::new(points), "Invalid brightness schedule"),
+            idle_timeout: self.idle_timeout_secs.map(Duration::from_secs),
+            black_screen_timeout: self.black_screen_timeout_secs.map(Duration::from_secs),
+            idle_windows,
+        })
+    }
+
+    /// Whether the settings used by [Config::schedule_settings] differ.
+    pub fn schedule_changed(&self, other: &Config) -> bool {
+        self.brightness_schedule != other.brightness_schedule || self.idle_timeout_secs != other.idle_timeout_secs
+            || self.black_screen_timeout_secs != other.black_screen_timeout_secs || self.idle_windows != other.idle_windows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(include_str!("../../lumos.example.toml")).unwrap();
+        assert_eq!(config.monitors.len(), 1);
+        assert_eq!(config.devices.unwrap().len(), 1);
+        assert_eq!(config.profiles.unwrap()[0].id, 1);
+
+        // Missing settings keep their defaults
+        let config = Config::parse("websocket_port = 1234").unwrap();
+        assert_eq!(config.websocket_port, 1234);
+        assert_eq!(config.metrics_port, Config::default().metrics_port);
+        assert!(config.devices.is_none());
+
+        assert!(Config::parse("websocket_prot = 1234").is_err());
+        assert!(Config::parse("monitors = []").is_err());
+        assert!(Config::parse(r#"ignored_windows = [{ processName = "(" }]"#).is_err());
+        let config = Config::parse(r#"ignored_windows = [{ processName = "^Discord" }]"#).unwrap();
+        assert_eq!(config.ignored_windows().unwrap().len(), 1);
+        assert!(Config::parse(r#"brightness_schedule = [{ time = "25:00", brightness = 1.0 }]"#).is_err());
+        assert!(Config::parse(r#"brightness_schedule = [{ time = "07:00", brightness = 2.0 }]"#).is_err());
+        let config = Config::parse("idle_timeout_secs = 600").unwrap();
+        assert_eq!(config.schedule_settings().unwrap().idle_timeout, Some(Duration::from_secs(600)));
+        assert!(Config::parse(r#"
+            [[profiles]]
+            id = 1
+            regex = "("
+            priority = 0
+            areas = []
+        "#).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::debug;
+use notify::Watcher;
+use simple_error::{SimpleResult, try_with};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How long to wait for further changes after a file changed, since editors often write files in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Notifies about changes to the configuration file, or requests to reload it (SIGHUP on unix systems).
+pub struct ConfigWatcher {
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+    changes: mpsc::UnboundedReceiver<()>,
+    /// When the last change was received plus [DEBOUNCE], if it hasn't been reported yet.
+    pending: Option<Instant>,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> SimpleResult<Self> {
+        let path: PathBuf = try_with!(path.canonicalize(), "Could not find {}", path.display());
+        let (tx, changes) = mpsc::unbounded_channel();
+        let watched_path = path.clone();
+        let mut watcher = try_with!(notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event
+                && !event.kind.is_access()
+                && event.paths.iter().any(|changed| changed == &watched_path) {
+                let _ = tx.send(());
+            }
+        }), "Could not create file watcher");
+        // Editors commonly replace the file rather than changing it, so watch the directory it is in
+        let dir = path.parent().unwrap_or(&path);
+        try_with!(watcher.watch(dir, notify::RecursiveMode::NonRecursive), "Could not watch {}", dir.display());
+        Ok(ConfigWatcher {
+            _watcher: watcher,
+            changes,
+            pending: None,
+            #[cfg(unix)]
+            hangup: try_with!(tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()), "Could not listen for SIGHUP"),
+        })
+    }
+
+    /// Waits until the configuration should be reloaded.
+    ///
+    /// This method is cancel safe: changes received by a call that was dropped are reported by the next call.
+    pub async fn changed(&mut self) {
+        loop {
+            #[cfg(unix)]
+            let hangup = self.hangup.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+            let pending = self.pending;
+            let debounced = async move {
+                match pending {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                Some(_) = self.changes.recv() => self.pending = Some(Instant::now() + DEBOUNCE),
+                _ = debounced => {
+                    self.pending = None;
+                    debug!("Configuration file changed");
+                    return;
+                },
+                Some(_) = hangup => {
+                    debug!("Received SIGHUP");
+                    return;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_changed() {
+        let dir = std::env::temp_dir().join(format!("lumos-watcher-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lumos.toml");
+        std::fs::write(&path, "").unwrap();
+        let mut watcher = ConfigWatcher::new(&path).unwrap();
+
+        std::fs::write(dir.join("other.toml"), "").unwrap();
+        std::fs::write(&path, "websocket_port = 1234").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changed_cancelled() {
+        let dir = std::env::temp_dir().join(format!("lumos-watcher-cancel-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lumos.toml");
+        std::fs::write(&path, "").unwrap();
+        let mut watcher = ConfigWatcher::new(&path).unwrap();
+
+        std::fs::write(&path, "websocket_port = 1234").unwrap();
+        // Drop calls while they wait for further changes, as other branches of a select! would
+        for _ in 0..100 {
+            assert!(tokio::time::timeout(DEBOUNCE / 4, watcher.changed()).await.is_err());
+            if watcher.pending.is_some() {
+                break;
+            }
+        }
+        assert!(watcher.pending.is_some());
+        tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+//! Finds the hardware that can be configured: outputs, monitors and audio devices.
+use log::warn;
+use serde::Serialize;
+
+use crate::outputs::{self, AvailableQmkDevice, AvailableSerialPort};
+
+/// Everything found by [discover].
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discovery {
+    pub serial_ports: Vec<AvailableSerialPort>,
+    pub qmk_devices: Vec<AvailableQmkDevice>,
+    pub monitors: Vec<Monitor>,
+    /// The names of audio playback devices, as accepted by [audio_capture::AudioCaptureController::set_audio_devices].
+    pub audio_devices: Vec<String>,
+    /// Why some kinds of hardware couldn't be listed. The other kinds are still listed.
+    pub errors: Vec<String>,
+}
+
+/// See [desktop_capture::MonitorInfo].
+#[derive(Debug, Clone, Serialize)]
+pub struct Monitor {
+    pub index: u32,
+    pub name: String,
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Lists the available hardware. Enumerating devices blocks, so it's done on a blocking thread.
+pub async fn discover() -> Discovery {
+    match tokio::task::spawn_blocking(discover_blocking).await {
+        Ok(discovery) => discovery,
+        Err(e) => Discovery {
+            errors: vec![format!("Discovery failed: {}", e)],
+            ..Default::default()
+        },
+    }
+}
+
+fn discover_blocking() -> Discovery {
+    let mut errors = Vec::new();
+    let serial_ports = collect("serial ports", outputs::available_serial_ports(), &mut errors);
+    let qmk_devices = collect("QMK devices", outputs::available_qmk_devices(), &mut errors);
+    let monitors = collect("monitors", desktop_capture::available_monitors(), &mut errors).into_iter()
+        .map(|monitor| Monitor {
+            index: monitor.index,
+            name: monitor.name,
+            left: monitor.left,
+            top: monitor.top,
+            width: monitor.width,
+            height: monitor.height,
+        })
+        .collect();
+    let audio_devices = collect("audio devices", audio_capture::available_audio_devices(), &mut errors);
+    Discovery { serial_ports, qmk_devices, monitors, audio_devices, errors }
+}
+
+/// Returns the listed items, or records the error and returns nothing.
+fn collect<T>(kind: &str, result: simple_error::SimpleResult<Vec<T>>, errors: &mut Vec<String>) -> Vec<T> {
+    result.unwrap_or_else(|e| {
+        let message = format!("Could not list {}: {}", kind, e);
+        warn!("{}", message);
+        errors.push(message);
+        Vec::new()
+    })
+}
@@ -0,0 +1,377 @@
+//! A server for the Hyperion JSON API, so the remotes, home automation integrations and apps made for Hyperion can
+//! control lumos.
+//!
+//! Colors and effects are shown as sources that contend with the desktop capture (see [SourceMux]), one for each
+//! Hyperion priority. Adjusting the brightness and turning the LED device component on or off changes the output of
+//! all devices, like the corresponding websocket requests. See [protocol] for the supported commands.
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color::RgbF32;
+use log::{debug, info, warn};
+use simple_error::{SimpleError, SimpleResult};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::common::RgbVec;
+use crate::render_service::{OutputControl, Source, SourceContent, SourceMux};
+use crate::render_service::specification::{DeviceEffect, DeviceSpecification, SamplingType};
+use crate::websocket::Frame;
+
+pub mod protocol;
+use protocol::{Command, Request, RequestHeader, Response, ServerInfo};
+
+/// Messages larger than this are rejected. Requests are small, except for long color patterns.
+const MAX_MESSAGE_SIZE: usize = 65536;
+/// The Hyperion priority of the desktop capture. Hyperion priorities are mapped around it, so lower ones cover the
+/// desktop capture and higher ones are covered by it, like they would cover the grabber of Hyperion.
+const GRABBER_PRIORITY: i32 = 240;
+/// Prefixes the names of the sources set through this server.
+const SOURCE_PREFIX: &str = "hyperion:";
+/// The effects that can be shown with [Command::Effect].
+const EFFECTS: [&str; 2] = ["Solid color", "Breathing"];
+const DEFAULT_EFFECT_COLOR: [u8; 3] = [255, 255, 255];
+const DEFAULT_BREATHING_PERIOD_MS: u64 = 4000;
+
+/// The state that is reported to clients.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub sources: watch::Receiver<SourceMux>,
+    pub control: watch::Receiver<OutputControl>,
+    pub devices: watch::Receiver<Vec<DeviceSpecification>>,
+}
+
+/// Starts a server for the Hyperion JSON API on the given address. Each connection sends requests as JSON objects, one
+/// per line.
+///
+/// Returns `(task, frames)`, where `task` runs the server and `frames` are the changes requested by clients.
+pub async fn run_hyperion_server(address: IpAddr, port: u32, context: ServerContext, cancel_token: CancellationToken)
+        -> SimpleResult<(impl Future<Output=()>, impl Stream<Item=Frame>)> {
+    let port = u16::try_from(port).map_err(|_| SimpleError::new(format!("Invalid port {}", port)))?;
+    let addr = SocketAddr::new(address, port);
+    let listener = TcpListener::bind(addr).await.map_err(SimpleError::from)?;
+    info!("Hyperion JSON API listening on: {}", addr);
+    let (frame_tx, frame_rx) = mpsc::channel(16);
+    let task = async move {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => {
+                    match conn {
+                        Ok((stream, client_addr)) => {
+                            tokio::spawn(handle_connection(stream, frame_tx.clone(), context.clone(), client_addr));
+                        },
+                        Err(e) => warn!("Failed to accept Hyperion connection: {}", e),
+                    }
+                },
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+        debug!("Shutting down Hyperion server");
+    };
+    Ok((task, ReceiverStream::new(frame_rx)))
+}
+
+async fn handle_connection(stream: TcpStream, frame_tx: mpsc::Sender<Frame>, context: ServerContext, client_addr: SocketAddr) {
+    info!("Accepted Hyperion connection from: {}", client_addr);
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_MESSAGE_SIZE as u64).read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) if !line.ends_with(b"\n") && line.len() >= MAX_MESSAGE_SIZE => {
+                warn!("Message from {} is larger than {} bytes", client_addr, MAX_MESSAGE_SIZE);
+                break;
+            },
+            Ok(_) => {},
+            Err(e) => {
+                warn!("Connection to {} failed: {}", client_addr, e);
+                break;
+            },
+        }
+        let text = String::from_utf8_lossy(&line);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let (response, frames) = handle_message(&text, &context, Instant::now());
+        if let Some(error) = &response.error {
+            warn!("Rejected Hyperion message from {}: {}", client_addr, error);
+        }
+        for frame in frames {
+            if frame_tx.send(frame).await.is_err() {
+                return;
+            }
+        }
+        let mut response = serde_json::to_string(&response).unwrap();
+        response.push('\n');
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+    info!("Disconnected from {}", client_addr);
+}
+
+/// Handles a single request, returning the response and the frames to hand to the application.
+fn handle_message(text: &str, context: &ServerContext, now: Instant) -> (Response, Vec<Frame>) {
+    let request = match serde_json::from_str::<Request>(text) {
+        Ok(request) => request,
+        Err(e) => {
+            // Answer using whatever can still be parsed from the message
+            let header = serde_json::from_str::<RequestHeader>(text).ok();
+            let tan = header.as_ref().and_then(|header| header.tan);
+            let command = header.and_then(|header| header.command).unwrap_or_default();
+            return (Response::error(&command, tan, format!("Invalid message: {}", e)), Vec::new());
+        },
+    };
+    let name = request.command.name();
+    match handle_command(request.command, context, now) {
+        Ok((frames, info)) => (Response { info, ..Response::ok(name, request.tan) }, frames),
+        Err(e) => (Response::error(name, request.tan, e.to_string()), Vec::new()),
+    }
+}
+
+fn handle_command(command: Command, context: &ServerContext, now: Instant) -> SimpleResult<(Vec<Frame>, Option<ServerInfo>)> {
+    let frames = match command {
+        Command::ServerInfo => return Ok((Vec::new(), Some(server_info(context, now)))),
+        Command::Color { color, priority, duration } => {
+            let chunks = color.chunks_exact(3);
+            if color.is_empty() || !chunks.remainder().is_empty() {
+                return Err(SimpleError::new("Colors must consist of three components each"));
+            }
+            let colors: RgbVec = chunks.map(|rgb| to_rgb([rgb[0], rgb[1], rgb[2]])).collect();
+            let content = match colors[..] {
+                [color] => SourceContent::Color(color),
+                // Patterns are fitted to the devices that are running now
+                _ => SourceContent::Colors(context.devices.borrow().iter()
+                    .map(|device| (device.name.clone(), Arc::new(colors.iter().cycle().take(device.size).copied().collect())))
+                    .collect()),
+            };
+            vec![Frame::Source(source(priority, content, duration, now)?)]
+        },
+        Command::Effect { effect, priority, duration } => {
+            let color = to_rgb(effect.args.color.unwrap_or(DEFAULT_EFFECT_COLOR));
+            let device_effect = match effect.name.as_str() {
+                "Solid color" => DeviceEffect::SolidColor(color),
+                "Breathing" => {
+                    let period_ms = effect.args.period_ms.unwrap_or(DEFAULT_BREATHING_PERIOD_MS);
+                    if period_ms == 0 {
+                        return Err(SimpleError::new("Effect period must be positive"));
+                    }
+                    DeviceEffect::Breathing { color, period: Duration::from_millis(period_ms) }
+                },
+                name => return Err(SimpleError::new(format!("Unknown effect '{}'", name))),
+            };
+            vec![Frame::Source(source(priority, SourceContent::Effect(device_effect), duration, now)?)]
+        },
+        Command::Clear { priority: -1 } => {
+            context.sources.borrow().live_sources(now)
+                .filter(|source| source.name.starts_with(SOURCE_PREFIX))
+                .map(|source| Frame::ClearSource(source.name.to_string()))
+                .collect()
+        },
+        Command::Clear { priority } => {
+            check_priority(priority)?;
+            vec![Frame::ClearSource(source_name(priority))]
+        },
+        Command::Adjustment { adjustment } => match adjustment.brightness {
+            Some(brightness) if !(0.0..=100.0).contains(&brightness) => {
+                return Err(SimpleError::new(format!("Brightness must be between 0 and 100, was {}", brightness)));
+            },
+            Some(brightness) => vec![Frame::Brightness(brightness / 100.0)],
+            None => Vec::new(),
+        },
+        Command::ComponentState { component_state } => match component_state.component.as_str() {
+            "ALL" | "LEDDEVICE" => vec![Frame::Enabled(component_state.state)],
+            component => return Err(SimpleError::new(format!("Component '{}' can't be changed", component))),
+        },
+    };
+    Ok((frames, None))
+}
+
+fn check_priority(priority: i32) -> SimpleResult<()> {
+    if !(1..=253).contains(&priority) {
+        return Err(SimpleError::new(format!("Priority must be between 1 and 253, was {}", priority)));
+    }
+    Ok(())
+}
+
+fn source_name(priority: i32) -> String {
+    format!("{}{}", SOURCE_PREFIX, priority)
+}
+
+/// Creates the source for a Hyperion priority, which is live from `now` on.
+fn source(priority: i32, content: SourceContent, duration_ms: Option<i64>, now: Instant) -> SimpleResult<Source> {
+    check_priority(priority)?;
+    Ok(Source {
+        name: Arc::from(source_name(priority)),
+        priority: GRABBER_PRIORITY - priority,
+        until: duration_ms.filter(|duration_ms| *duration_ms > 0).map(|duration_ms| now + Duration::from_millis(duration_ms as u64)),
+        content,
+        opacity: 1.0,
+    })
+}
+
+fn server_info(context: &ServerContext, now: Instant) -> ServerInfo {
+    let control = *context.control.borrow();
+    let sources = context.sources.borrow();
+    let priorities: Vec<_> = sources.live_sources(now).enumerate()
+        .map(|(index, source)| protocol::PriorityInfo {
+            priority: (GRABBER_PRIORITY - source.priority).clamp(0, 255),
+            active: true,
+            visible: index == 0,
+            component_id: match source.content {
+                SourceContent::Capture => "GRABBER",
+                SourceContent::Color(_) => "COLOR",
+                SourceContent::Effect(_) => "EFFECT",
+                SourceContent::Colors(_) | SourceContent::Image(_) => "IMAGE",
+            },
+            origin: source.name.to_string(),
+            owner: String::new(),
+            duration_ms: source.until.map(|until| (until - now).as_millis() as u64),
+            value: match source.content {
+                SourceContent::Color(color) => Some(protocol::PriorityValue { rgb: to_u8(color) }),
+                _ => None,
+            },
+        })
+        .collect();
+    let capturing = priorities.iter().any(|priority| priority.component_id == "GRABBER");
+    ServerInfo {
+        priorities,
+        priorities_autoselect: true,
+        adjustment: vec![protocol::AdjustmentInfo { id: "default", brightness: (control.brightness * 100.0).round() as u32 }],
+        effects: EFFECTS.iter()
+            .map(|&name| protocol::EffectInfo {
+                name,
+                args: protocol::EffectArgs {
+                    color: Some(DEFAULT_EFFECT_COLOR),
+                    period_ms: (name == "Breathing").then_some(DEFAULT_BREATHING_PERIOD_MS),
+                },
+            })
+            .collect(),
+        components: vec![
+            protocol::ComponentInfo { name: "ALL", enabled: control.enabled },
+            protocol::ComponentInfo { name: "LEDDEVICE", enabled: control.enabled },
+            protocol::ComponentInfo { name: "GRABBER", enabled: capturing },
+        ],
+        leds: context.devices.borrow().iter().flat_map(leds).collect(),
+        hostname: std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| "lumos".to_string()),
+        instance: vec![protocol::InstanceInfo { instance: 0, running: true, friendly_name: "lumos".to_string() }],
+    }
+}
+
+/// The areas of the capture region the LEDs of a device sample.
+fn leds(device: &DeviceSpecification) -> Vec<protocol::LedInfo> {
+    let section = |index: usize| (index as f32 / device.size as f32, (index + 1) as f32 / device.size as f32);
+    (0..device.size)
+        .map(|index| match device.sampling_type {
+            SamplingType::Horizontal => {
+                let (hmin, hmax) = section(index);
+                protocol::LedInfo { hmin, hmax, vmin: 0.0, vmax: 1.0 }
+            },
+            SamplingType::Vertical => {
+                let (vmin, vmax) = section(index);
+                protocol::LedInfo { hmin: 0.0, hmax: 1.0, vmin, vmax }
+            },
+            SamplingType::Ambilight(_) => protocol::LedInfo { hmin: 0.0, hmax: 1.0, vmin: 0.0, vmax: 1.0 },
+        })
+        .collect()
+}
+
+fn to_rgb([red, green, blue]: [u8; 3]) -> RgbF32 {
+    RgbF32 { red: red as f32 / 255.0, green: green as f32 / 255.0, blue: blue as f32 / 255.0 }
+}
+
+fn to_u8(color: RgbF32) -> [u8; 3] {
+    [(color.red * 255.0).round() as u8, (color.green * 255.0).round() as u8, (color.blue * 255.0).round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(sources: SourceMux) -> ServerContext {
+        ServerContext {
+            sources: watch::channel(sources).1,
+            control: watch::channel(OutputControl::default()).1,
+            devices: watch::channel(Vec::new()).1,
+        }
+    }
+
+    fn handle(text: &str, context: &ServerContext) -> (Response, Vec<Frame>) {
+        handle_message(text, context, Instant::now())
+    }
+
+    #[test]
+    fn test_color() {
+        let (response, frames) = handle(r#"{"command": "color", "tan": 3, "color": [255, 0, 0], "priority": 50, "duration": 1000}"#, &context(SourceMux::default()));
+        assert!(response.success);
+        assert_eq!(serde_json::to_string(&response).unwrap(), r#"{"command":"color","tan":3,"success":true}"#);
+        let [Frame::Source(source)] = &frames[..] else { panic!("Expected a source") };
+        assert_eq!((&*source.name, source.priority), ("hyperion:50", GRABBER_PRIORITY - 50));
+        assert!(source.until.is_some());
+        assert!(matches!(source.content, SourceContent::Color(color) if color == RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }));
+
+        let (response, frames) = handle(r#"{"command": "color", "color": [255, 0], "priority": 50}"#, &context(SourceMux::default()));
+        assert!(!response.success);
+        assert!(frames.is_empty());
+        assert!(!handle(r#"{"command": "color", "color": [255, 0, 0], "priority": 254}"#, &context(SourceMux::default())).0.success);
+
+        let (response, _) = handle(r#"{"command": "color", "tan": 7}"#, &context(SourceMux::default()));
+        assert_eq!((response.command.as_str(), response.tan, response.success), ("color", Some(7), false));
+    }
+
+    #[test]
+    fn test_clear() {
+        let context = context(SourceMux::default());
+        let (_, frames) = handle(r#"{"command": "color", "color": [255, 0, 0], "priority": 50}"#, &context);
+        let [Frame::Source(source)] = &frames[..] else { panic!("Expected a source") };
+        let mut sources = SourceMux::default();
+        sources.set(source.clone());
+        sources.set(Source { name: Arc::from("alert"), ..source.clone() });
+        let context = ServerContext { sources: watch::channel(sources).1, ..context };
+
+        let (_, frames) = handle(r#"{"command": "clear", "priority": 50}"#, &context);
+        assert!(matches!(&frames[..], [Frame::ClearSource(name)] if name == "hyperion:50"));
+        // Only the sources set through the Hyperion API are cleared
+        let (_, frames) = handle(r#"{"command": "clear", "priority": -1}"#, &context);
+        assert!(matches!(&frames[..], [Frame::ClearSource(name)] if name == "hyperion:50"));
+    }
+
+    #[test]
+    fn test_controls() {
+        let context = context(SourceMux::default());
+        let (_, frames) = handle(r#"{"command": "adjustment", "adjustment": {"brightness": 50, "gammaRed": 1.5}}"#, &context);
+        assert!(matches!(frames[..], [Frame::Brightness(brightness)] if brightness == 0.5));
+        assert!(!handle(r#"{"command": "adjustment", "adjustment": {"brightness": 150}}"#, &context).0.success);
+
+        let (_, frames) = handle(r#"{"command": "componentstate", "componentstate": {"component": "LEDDEVICE", "state": false}}"#, &context);
+        assert!(matches!(frames[..], [Frame::Enabled(false)]));
+        assert!(!handle(r#"{"command": "componentstate", "componentstate": {"component": "V4L", "state": true}}"#, &context).0.success);
+
+        let (_, frames) = handle(r#"{"command": "effect", "effect": {"name": "Breathing", "args": {"color": [0, 0, 255]}}, "priority": 10}"#, &context);
+        let [Frame::Source(source)] = &frames[..] else { panic!("Expected a source") };
+        assert!(matches!(source.content, SourceContent::Effect(DeviceEffect::Breathing { .. })));
+        assert!(!handle(r#"{"command": "effect", "effect": {"name": "Rainbow swirl"}, "priority": 10}"#, &context).0.success);
+    }
+
+    #[test]
+    fn test_server_info() {
+        let mut sources = SourceMux::default();
+        sources.set(source(50, SourceContent::Color(to_rgb([0, 255, 0])), None, Instant::now()).unwrap());
+        let (response, frames) = handle(r#"{"command": "serverinfo", "tan": 1}"#, &context(sources));
+        assert!(frames.is_empty());
+        let info = response.info.unwrap();
+        assert_eq!(info.priorities.iter().map(|priority| priority.priority).collect::<Vec<_>>(), vec![50, GRABBER_PRIORITY]);
+        assert!(info.priorities[0].visible && !info.priorities[1].visible);
+        assert_eq!(info.priorities[0].value.as_ref().unwrap().rgb, [0, 255, 0]);
+        assert_eq!(info.adjustment[0].brightness, 100);
+        assert!(info.components.iter().all(|component| component.enabled));
+    }
+}
@@ -0,0 +1,202 @@
+//! The subset of the Hyperion JSON API that is supported.
+//!
+//! Each message is a JSON object on a single line. Clients send [Request]s, each of which is answered with a
+//! [Response] that echoes the `command` and `tan` of the request. For example, the request
+//! ```json
+//! { "command": "color", "tan": 4, "color": [255, 0, 0], "priority": 50, "duration": 5000 }
+//! ```
+//! is answered with
+//! ```json
+//! { "command": "color", "tan": 4, "success": true }
+//! ```
+use serde::{Deserialize, Serialize};
+
+/// The fields shared by all requests. Used to answer requests that failed to parse.
+#[derive(Deserialize)]
+pub struct RequestHeader {
+    pub command: Option<String>,
+    pub tan: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct Request {
+    /// An identifier chosen by the client, which is included in the [Response] to this request.
+    pub tan: Option<i64>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+    /// Requests the state of the server. Answered with [ServerInfo].
+    ServerInfo,
+    /// Shows a color, or a pattern of colors that is repeated along each device.
+    Color {
+        /// The red, green and blue components of each color, between 0 and 255.
+        color: Vec<u8>,
+        /// Lower priorities cover higher ones. Between 1 and 253.
+        priority: i32,
+        /// How long to show the color for in milliseconds. Not given or not positive shows it until it is cleared.
+        duration: Option<i64>,
+    },
+    /// Shows one of the effects listed in [ServerInfo::effects].
+    Effect {
+        effect: EffectRequest,
+        priority: i32,
+        duration: Option<i64>,
+    },
+    /// Removes the color or effect with the given priority, or all of them for -1.
+    Clear {
+        priority: i32,
+    },
+    Adjustment {
+        adjustment: Adjustment,
+    },
+    ComponentState {
+        #[serde(rename = "componentstate")]
+        component_state: ComponentState,
+    },
+}
+
+impl Command {
+    /// The name of the command, as given in requests.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::ServerInfo => "serverinfo",
+            Command::Color { .. } => "color",
+            Command::Effect { .. } => "effect",
+            Command::Clear { .. } => "clear",
+            Command::Adjustment { .. } => "adjustment",
+            Command::ComponentState { .. } => "componentstate",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EffectRequest {
+    pub name: String,
+    #[serde(default)]
+    pub args: EffectArgs,
+}
+
+/// The arguments of all supported effects. Missing arguments have the defaults listed in [ServerInfo::effects].
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectArgs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_ms: Option<u64>,
+}
+
+/// Changes to the color adjustment. Hyperion has many more settings, which are ignored.
+#[derive(Deserialize)]
+pub struct Adjustment {
+    /// Between 0 and 100.
+    pub brightness: Option<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct ComponentState {
+    /// One of the components listed in [ServerInfo::components].
+    pub component: String,
+    pub state: bool,
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tan: Option<i64>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<ServerInfo>,
+}
+
+impl Response {
+    pub fn ok(command: &str, tan: Option<i64>) -> Self {
+        Response { command: command.to_string(), tan, success: true, error: None, info: None }
+    }
+
+    pub fn error(command: &str, tan: Option<i64>, error: impl Into<String>) -> Self {
+        Response { command: command.to_string(), tan, success: false, error: Some(error.into()), info: None }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ServerInfo {
+    /// The sources that are currently shown, by ascending priority.
+    pub priorities: Vec<PriorityInfo>,
+    /// Sources can't be selected manually, the one with the lowest priority is always shown.
+    pub priorities_autoselect: bool,
+    pub adjustment: Vec<AdjustmentInfo>,
+    pub effects: Vec<EffectInfo>,
+    pub components: Vec<ComponentInfo>,
+    /// The area of the screen each LED samples, in the order of the devices.
+    pub leds: Vec<LedInfo>,
+    pub hostname: String,
+    /// Hyperion can drive several LED setups, which are all covered by a single instance here.
+    pub instance: Vec<InstanceInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriorityInfo {
+    pub priority: i32,
+    pub active: bool,
+    /// Whether the source is the one being shown.
+    pub visible: bool,
+    /// One of `GRABBER`, `COLOR`, `EFFECT` or `IMAGE`.
+    pub component_id: &'static str,
+    pub origin: String,
+    pub owner: String,
+    /// How long until the source expires, if ever.
+    #[serde(rename = "duration_ms", skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<PriorityValue>,
+}
+
+#[derive(Serialize)]
+pub struct PriorityValue {
+    #[serde(rename = "RGB")]
+    pub rgb: [u8; 3],
+}
+
+#[derive(Serialize)]
+pub struct AdjustmentInfo {
+    pub id: &'static str,
+    /// Between 0 and 100.
+    pub brightness: u32,
+}
+
+#[derive(Serialize)]
+pub struct EffectInfo {
+    pub name: &'static str,
+    pub args: EffectArgs,
+}
+
+#[derive(Serialize)]
+pub struct ComponentInfo {
+    pub name: &'static str,
+    pub enabled: bool,
+}
+
+/// The bounds of the area an LED samples, each between 0 and 1.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LedInfo {
+    pub hmin: f32,
+    pub hmax: f32,
+    pub vmin: f32,
+    pub vmax: f32,
+}
+
+#[derive(Serialize)]
+pub struct InstanceInfo {
+    pub instance: u32,
+    pub running: bool,
+    pub friendly_name: String,
+}
@@ -0,0 +1,276 @@
+//! Colors pushed by other programs, e.g. game mods, stream overlays or scripts.
+//!
+//! Programs send [InputPacket]s over UDP (see [run_udp_server]) or as binary websocket messages. Each packet sets the
+//! colors of a named source, which contends with the desktop capture and other sources by priority (see
+//! [crate::render_service::SourceMux]). A packet either contains the colors of one device, or an image that every
+//! device samples like a captured frame.
+//!
+//! All packets start with a header:
+//!
+//! | Bytes | Content                                                                                  |
+//! |-------|------------------------------------------------------------------------------------------|
+//! | 1     | The kind of packet: 0 clears the source, 1 sets the colors of a device, 2 sets an image   |
+//! | 1     | The length of the source name                                                            |
+//! | n     | The name of the source, in UTF-8                                                         |
+//!
+//! Packets of kind 1 and 2 continue with:
+//!
+//! | Bytes | Content                                                                                  |
+//! |-------|------------------------------------------------------------------------------------------|
+//! | 4     | The priority of the source, as a signed little-endian integer (the capture has 0)        |
+//! | 4     | How long the source is shown in milliseconds, little-endian. 0 shows it until cleared     |
+//! | 1     | The opacity of the source, 255 being opaque                                              |
+//!
+//! Followed by, for kind 1, the length of the device name (2 bytes, little-endian), the device name in UTF-8 and the
+//! red, green and blue components of each LED (1 byte each). For kind 2, the width and height of the image (2 bytes
+//! each, little-endian) and the red, green and blue components of each pixel, row by row.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color::{RgbF32, RgbU8};
+use simple_error::{SimpleError, SimpleResult};
+
+use crate::common::RgbVec;
+use crate::render_service::{self, Source, SourceContent};
+
+mod udp;
+pub use udp::run_udp_server;
+
+/// Packets larger than this are rejected. Fits a 4K image.
+pub const MAX_PACKET_SIZE: usize = 3840 * 2160 * 3 + 1024;
+
+#[derive(Debug, Clone)]
+pub enum InputPacket {
+    /// Removes the source with the given name.
+    Clear {
+        source: String,
+    },
+    Set {
+        source: String,
+        priority: i32,
+        /// How long the source is shown, or [None] to show it until it is cleared.
+        timeout: Option<Duration>,
+        opacity: f32,
+        content: InputContent,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum InputContent {
+    DeviceColors {
+        device: String,
+        colors: RgbVec,
+    },
+    Image(desktop_capture::Frame),
+}
+
+/// Reads the fields of a packet in order.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> SimpleResult<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(SimpleError::new("Packet is too short"));
+        }
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> SimpleResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> SimpleResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> SimpleResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self, length: usize) -> SimpleResult<String> {
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| SimpleError::new("Names must be UTF-8"))
+    }
+
+    /// Reads the rest of the packet as RGB pixels.
+    fn pixels(&mut self) -> SimpleResult<Vec<RgbU8>> {
+        let chunks = self.data.chunks_exact(3);
+        if !chunks.remainder().is_empty() {
+            return Err(SimpleError::new("Colors must consist of three bytes each"));
+        }
+        let pixels = chunks.map(|rgb| RgbU8 { red: rgb[0], green: rgb[1], blue: rgb[2] }).collect();
+        self.data = &[];
+        Ok(pixels)
+    }
+}
+
+/// Decodes a packet in the format described in the [module documentation](self).
+pub fn decode_packet(data: &[u8]) -> SimpleResult<InputPacket> {
+    if data.len() > MAX_PACKET_SIZE {
+        return Err(SimpleError::new(format!("Packet is larger than {} bytes", MAX_PACKET_SIZE)));
+    }
+    let mut reader = Reader { data };
+    let kind = reader.u8()?;
+    let name_length = reader.u8()? as usize;
+    let source = reader.string(name_length)?;
+    if source.is_empty() || source == render_service::CAPTURE_SOURCE {
+        return Err(SimpleError::new(format!("Invalid source name '{}'", source)));
+    }
+    if kind == 0 {
+        return Ok(InputPacket::Clear { source });
+    }
+    let priority = reader.u32()? as i32;
+    let timeout_ms = reader.u32()?;
+    let opacity = reader.u8()? as f32 / 255.0;
+    let content = match kind {
+        1 => {
+            let device_length = reader.u16()? as usize;
+            let device = reader.string(device_length)?;
+            let colors = reader.pixels()?.iter().map(|rgb| RgbF32 {
+                red: rgb.red as f32 / 255.0,
+                green: rgb.green as f32 / 255.0,
+                blue: rgb.blue as f32 / 255.0,
+            }).collect();
+            InputContent::DeviceColors { device, colors }
+        },
+        2 => {
+            let width = reader.u16()? as usize;
+            let height = reader.u16()? as usize;
+            let buffer = reader.pixels()?;
+            if width == 0 || height == 0 || buffer.len() != width * height {
+                return Err(SimpleError::new(format!("Image has {} pixels, expected {}x{}", buffer.len(), width, height)));
+            }
+            InputContent::Image(desktop_capture::Frame { buffer, width, height, downscaling: 1 })
+        },
+        _ => return Err(SimpleError::new(format!("Unknown packet kind {}", kind))),
+    };
+    Ok(InputPacket::Set {
+        source,
+        priority,
+        timeout: (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64)),
+        opacity,
+        content,
+    })
+}
+
+/// What to do with the sources after a packet was received.
+pub enum SourceChange {
+    Set(Source),
+    Clear(String),
+}
+
+/// Combines the device colors of consecutive packets for the same source, so a source can set the colors of several
+/// devices.
+#[derive(Default)]
+pub struct InputSources {
+    device_colors: HashMap<String, DeviceColors>,
+}
+
+/// The colors a source set for each device.
+#[derive(Default)]
+struct DeviceColors {
+    colors: HashMap<String, Arc<RgbVec>>,
+    until: Option<Instant>,
+}
+
+impl InputSources {
+    /// Turns a packet into a change to the sources, at `now`.
+    pub fn apply(&mut self, packet: InputPacket, now: Instant) -> SourceChange {
+        let (source, priority, timeout, opacity, content) = match packet {
+            InputPacket::Clear { source } => {
+                self.device_colors.remove(&source);
+                return SourceChange::Clear(source);
+            },
+            InputPacket::Set { source, priority, timeout, opacity, content } => (source, priority, timeout, opacity, content),
+        };
+        let until = timeout.map(|timeout| now + timeout);
+        let content = match content {
+            InputContent::DeviceColors { device, colors } => {
+                let entry = self.device_colors.entry(source.clone()).or_default();
+                // Colors of devices that expired aren't shown again
+                if entry.until.is_some_and(|until| until <= now) {
+                    entry.colors.clear();
+                }
+                entry.colors.insert(device, Arc::new(colors));
+                entry.until = until;
+                SourceContent::Colors(entry.colors.clone())
+            },
+            InputContent::Image(image) => {
+                self.device_colors.remove(&source);
+                SourceContent::Image(Arc::new(image))
+            },
+        };
+        SourceChange::Set(Source { name: Arc::from(source), priority, until, content, opacity })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(kind: u8, source: &str) -> Vec<u8> {
+        let mut packet = vec![kind, source.len() as u8];
+        packet.extend_from_slice(source.as_bytes());
+        if kind != 0 {
+            packet.extend_from_slice(&10i32.to_le_bytes());
+            packet.extend_from_slice(&500u32.to_le_bytes());
+            packet.push(255);
+        }
+        packet
+    }
+
+    fn device_packet(source: &str, device: &str, colors: &[u8]) -> Vec<u8> {
+        let mut packet = header(1, source);
+        packet.extend_from_slice(&(device.len() as u16).to_le_bytes());
+        packet.extend_from_slice(device.as_bytes());
+        packet.extend_from_slice(colors);
+        packet
+    }
+
+    #[test]
+    fn test_decode_packet() {
+        let packet = decode_packet(&device_packet("mod", "Desk", &[255, 0, 0, 0, 0, 255])).unwrap();
+        let InputPacket::Set { source, priority, timeout, opacity, content: InputContent::DeviceColors { device, colors } } = packet else {
+            panic!("Expected device colors, got {:?}", packet);
+        };
+        assert_eq!((source.as_str(), priority, timeout, opacity), ("mod", 10, Some(Duration::from_millis(500)), 1.0));
+        assert_eq!(device, "Desk");
+        assert_eq!(colors, vec![RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }, RgbF32 { red: 0.0, green: 0.0, blue: 1.0 }]);
+
+        let mut image = header(2, "overlay");
+        image.extend_from_slice(&[2, 0, 1, 0]);
+        image.extend_from_slice(&[0; 6]);
+        assert!(matches!(decode_packet(&image), Ok(InputPacket::Set { content: InputContent::Image(frame), .. }) if frame.width == 2));
+        image.push(0);
+        assert!(decode_packet(&image).is_err());
+
+        assert!(matches!(decode_packet(&header(0, "mod")), Ok(InputPacket::Clear { source }) if source == "mod"));
+        assert!(decode_packet(&header(0, "capture")).is_err());
+        assert!(decode_packet(&header(3, "mod")).is_err());
+        assert!(decode_packet(&device_packet("mod", "Desk", &[255, 0])).is_err());
+        assert!(decode_packet(&[1, 10, b'm']).is_err());
+    }
+
+    #[test]
+    fn test_input_sources() {
+        let now = Instant::now();
+        let mut sources = InputSources::default();
+        let packet = |device: &str| decode_packet(&device_packet("mod", device, &[255, 255, 255])).unwrap();
+        sources.apply(packet("Desk"), now);
+        let SourceChange::Set(source) = sources.apply(packet("Shelf"), now) else { panic!("Expected a source") };
+        let SourceContent::Colors(colors) = source.content else { panic!("Expected colors") };
+        assert_eq!(colors.len(), 2);
+
+        // The colors of the other device expired in the meantime
+        let SourceChange::Set(source) = sources.apply(packet("Desk"), now + Duration::from_secs(1)) else { panic!("Expected a source") };
+        let SourceContent::Colors(colors) = source.content else { panic!("Expected colors") };
+        assert_eq!(colors.len(), 1);
+        assert_eq!(source.until, Some(now + Duration::from_millis(1500)));
+
+        assert!(matches!(sources.apply(InputPacket::Clear { source: "mod".to_string() }, now), SourceChange::Clear(name) if name == "mod"));
+    }
+}
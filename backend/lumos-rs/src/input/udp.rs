@@ -0,0 +1,51 @@
+use std::future::Future;
+
+use tokio_stream::Stream;
+use log::{debug, info, warn};
+use simple_error::{SimpleError, SimpleResult};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use super::{decode_packet, InputPacket};
+
+/// The largest payload of a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// Starts receiving [InputPacket]s over UDP on localhost. Each datagram contains one packet.
+///
+/// Returns `(task, packets)`, where `task` runs the server and `packets` are the packets that could be decoded.
+pub async fn run_udp_server(port: u32, cancel_token: CancellationToken)
+        -> SimpleResult<(impl Future<Output=()>, impl Stream<Item=InputPacket>)> {
+    let (packet_tx, packet_rx) = mpsc::channel(64);
+    let addr = format!("127.0.0.1:{}", port);
+    let socket = UdpSocket::bind(&addr).await.map_err(SimpleError::from)?;
+    info!("Receiving colors on: udp://{}", addr);
+    let task = async move {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buffer) => {
+                    match received {
+                        Ok((size, sender)) => match decode_packet(&buffer[..size]) {
+                            // A lost clear would leave the source shown forever, so only colors are dropped
+                            Ok(packet @ InputPacket::Clear { .. }) => if packet_tx.send(packet).await.is_err() {
+                                break;
+                            },
+                            // Senders usually stream colors, so drop them rather than falling behind
+                            Ok(packet) => if let Err(mpsc::error::TrySendError::Full(_)) = packet_tx.try_send(packet) {
+                                debug!("Dropping input packet from {}", sender);
+                            },
+                            Err(e) => warn!("Invalid input packet from {}: {}", sender, e),
+                        },
+                        Err(e) => warn!("Failed to receive input packet: {}", e),
+                    }
+                },
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+        debug!("Shutting down input server");
+    };
+    Ok((task, ReceiverStream::new(packet_rx)))
+}
@@ -1,7 +1,9 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![feature(let_chains, test, trait_alias)]
 #![allow(clippy::needless_return)]
-use log::{info, warn};
+use std::collections::HashMap;
+
+use log::{error, info, warn};
 use futures::StreamExt;
 use tokio_util::sync::CancellationToken;
 
@@ -12,78 +14,274 @@ mod render_service;
 mod outputs;
 mod websocket;
 mod profiles;
-
-mod config {
-    pub const DESKTOP_CAPTURE_FPS: f32 = 15.0;
-    /// How much to reduce the resolution of captured frames, to improve performance (the resolution is halved this number of times)
-    pub const DESKTOP_CAPTURE_DECIMATION: u32 = 2;
-    pub const WEBSOCKET_PORT: u32 = 9901;
-    use crate::common::Rect;
-    pub const MONITORS: [Rect; 2] = [
-        Rect{ left: 0, top: -8, width: 2560, height: 1440 },
-        Rect{ left: -1920, top: 0, width: 1920, height: 1080 },
-    ];
-    /// The region of monitor 0 to capture for horizontal samplers when no profile is active.
-	pub const DEFAULT_CAPTURE_REGION_HOR: Rect = Rect{ left: 0, top: 840, width: 2560, height: 600 };
-    /// The region of monitor 0 to capture for vertical samplers when no profile is active.
-	pub const DEFAULT_CAPTURE_REGION_VER: Rect = Rect{ left: 0, top: 0, width: 400, height: 1440 };
-}
+mod telemetry;
+mod discovery;
+mod store;
+mod config;
+mod scheduler;
+/// Colors pushed by other programs
+mod input;
+mod hyperion;
 
 #[tokio::main]
 async fn main() {
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Debug,
-        simplelog::Config::default(),
-        simplelog::TerminalMode::Stdout,
-        simplelog::ColorChoice::Auto
-    ).unwrap();
+    let args = <config::Args as clap::Parser>::parse();
+    init_logging(&args);
+    let mut config = match config::Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        },
+    };
     info!("Starting application");
 
     // Used to tell all long-running tasks to exit when main exits
     let shutdown = CancellationToken::new();
     let _shutdown_guard = shutdown.clone().drop_guard();
 
-    let (ws_task, mut ws_messages) = websocket::run_websocket_server(
-        config::WEBSOCKET_PORT,
+    let mut render_service = render_service::RenderService::new(config.desktop_capture_fps,
+        config.desktop_capture_decimation,
+        config.default_capture_region_hor,
+        config.default_capture_region_ver);
+
+    let config_store = match store::ConfigStore::default_path() {
+        Ok(path) => Some(store::ConfigStore::load(path)),
+        Err(e) => {
+            warn!("Configuration will not be stored: {}", e);
+            None
+        },
+    };
+    let stored_config = config_store.as_ref().map(store::ConfigStore::config).unwrap_or_default();
+
+    let mut telemetry = telemetry::RollingTelemetry::new(telemetry::TELEMETRY_WINDOW);
+    let (telemetry_tx, telemetry_rx) = tokio::sync::watch::channel(telemetry::TelemetryReport::default());
+    let mut telemetry_interval = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    let (ws_task, ws_messages, ws_broadcaster) = websocket::run_websocket_server(
+        config.websocket_port,
+        websocket::ServerContext {
+            previews: render_service.previews(),
+            telemetry: telemetry_rx.clone(),
+            config: match &config_store {
+                Some(store) => store.subscribe(),
+                None => tokio::sync::watch::channel(stored_config.clone()).1,
+            },
+            sources: render_service.sources(),
+        },
         shutdown.clone(),
     ).await.expect("Could not open websocket");
     tokio::spawn(ws_task);
 
-    let mut profile_listener = profiles::ProfileListener::new(config::MONITORS.to_vec()).await;
+    // Metrics are optional, so failing to serve them isn't fatal
+    match telemetry::prometheus::run_metrics_server(config.metrics_port, telemetry_rx, shutdown.clone()).await {
+        Ok(metrics_task) => { tokio::spawn(metrics_task); },
+        Err(e) => warn!("Could not serve metrics: {}", e),
+    }
+
+    // Clients of the Hyperion JSON API make the same changes as websocket clients
+    let hyperion_frames = match config.hyperion_port {
+        Some(port) => {
+            let context = hyperion::ServerContext {
+                sources: render_service.sources(),
+                control: render_service.control(),
+                devices: render_service.devices(),
+            };
+            match hyperion::run_hyperion_server(config.hyperion_address, port, context, shutdown.clone()).await {
+                Ok((hyperion_task, frames)) => {
+                    tokio::spawn(hyperion_task);
+                    frames.boxed()
+                },
+                Err(e) => {
+                    warn!("Could not serve the Hyperion JSON API: {}", e);
+                    futures::stream::pending().boxed()
+                },
+            }
+        },
+        None => futures::stream::pending().boxed(),
+    };
+    let mut ws_messages = futures::stream::select(ws_messages, hyperion_frames);
+
+    let (input_task, mut input_packets) = input::run_udp_server(config.input_port, shutdown.clone()).await
+        .expect("Could not open input socket");
+    tokio::spawn(input_task);
+    let mut input_sources = input::InputSources::default();
+
+    let window_source = profiles::window_source::platform_source().await.unwrap_or_else(|e| {
+        warn!("Profiles will not be activated for focused windows: {}", e);
+        Box::new(profiles::window_source::NoWindowSource)
+    });
+    let mut profile_listener = profiles::ProfileListener::new(window_source, config.monitors.clone());
+    // The configuration was validated when it was loaded
+    profile_listener.set_ignored_windows(config.ignored_windows().unwrap_or_default());
+    let (activation_delay, deactivation_delay) = config.profile_grace_periods();
+    profile_listener.set_grace_periods(activation_delay, deactivation_delay);
+
+    let mut scheduler = scheduler::Scheduler::new(config.schedule_settings().unwrap_or_default(), render_service.frames(),
+        profile_listener.focused_windows());
 
-    let mut render_service = render_service::RenderService::new(config::DESKTOP_CAPTURE_FPS,
-        config::DEFAULT_CAPTURE_REGION_HOR,
-        config::DEFAULT_CAPTURE_REGION_VER);
-    // The main loop handles messages from the websocket server, the profile listener and the ctrl-c signal
+    // Restore the stored configuration, so the application is useful without a client
+    render_service.set_audio_devices(stored_config.audio_devices);
+    render_service.update_control(|control| {
+        control.brightness = stored_config.settings.brightness;
+        control.transition = config.transition_duration();
+    });
+    render_service.set_source(config.capture_source());
+    profile_listener.set_profiles(websocket::parse_profiles(config.profiles.as_ref().unwrap_or(&stored_config.profiles)).0);
+    let devices = websocket::parse_devices(config.devices.as_ref().unwrap_or(&stored_config.devices)).0;
+    if !devices.is_empty() {
+        info!("Starting {} stored device(s)", devices.len());
+        render_service.set_devices(devices).await;
+    }
+
+    let mut config_watcher = config::Config::file_path(&args).and_then(|path| {
+        config::ConfigWatcher::new(&path)
+            .map_err(|e| warn!("Configuration changes will not be applied until restarting: {}", e))
+            .ok()
+    });
+
+    let persist = |update: &dyn Fn(&mut store::StoredConfig)| {
+        if let Some(store) = &config_store && let Err(e) = store.update(update) {
+            warn!("Could not store configuration: {}", e);
+        }
+    };
+    // The last state each device reported, to repeat it for devices that keep running when the devices are replaced
+    let mut device_states: HashMap<String, render_service::OutputStateEvent> = HashMap::new();
+    // The main loop handles messages from the websocket server, the profile listener, the scheduler, telemetry updates and
+    // the ctrl-c signal
     loop {
         tokio::select! {
             ws_msg = ws_messages.next() => {
                 if let Some(msg) = ws_msg {
                     match msg {
-                        websocket::Frame::Devices(devs) => {
-                            info!("Starting {} device(s)", devs.len());
-                            render_service.set_devices(devs);
+                        websocket::Frame::Devices { devices, entries } => {
+                            info!("Starting {} device(s)", devices.len());
+                            persist(&|config| config.devices = entries.clone());
+                            let names: Vec<String> = devices.iter().map(|device| device.name.clone()).collect();
+                            let started = render_service.set_devices(devices).await;
+                            // Clients wait for the state of every device they sent, which started devices report themselves
+                            device_states.retain(|name, _| names.contains(name) && !started.contains(name));
+                            for state in device_states.values() {
+                                ws_broadcaster.send(device_state_event(state.clone()));
+                            }
                         },
-                        websocket::Frame::Profiles(profs) => {
-                            info!("Received {} profile(s)", profs.len());
-                            profile_listener.set_profiles(profs);
+                        websocket::Frame::Profiles { profiles, entries } => {
+                            info!("Received {} profile(s)", profiles.len());
+                            persist(&|config| config.profiles = entries.clone());
+                            profile_listener.set_profiles(profiles);
                         },
                         websocket::Frame::AudioDevices(audio_devs) => {
                             info!("Received {} audio device(s)", audio_devs.len());
+                            persist(&|config| config.audio_devices = audio_devs.clone());
                             render_service.set_audio_devices(audio_devs);
                         },
                         websocket::Frame::Shutdown => {
                             shutdown.cancel();
                         },
+                        websocket::Frame::Paused(paused) => {
+                            info!("{} output", if paused { "Pausing" } else { "Resuming" });
+                            render_service.update_control(|control| control.paused = paused);
+                        },
+                        websocket::Frame::Brightness(brightness) => {
+                            persist(&|config| config.settings.brightness = brightness);
+                            render_service.update_control(|control| control.brightness = brightness);
+                        },
+                        websocket::Frame::Enabled(enabled) => {
+                            info!("Turning output {}", if enabled { "on" } else { "off" });
+                            render_service.update_control(|control| control.enabled = enabled);
+                        },
+                        websocket::Frame::ForceProfile { profile_id, monitor } => {
+                            match profile_listener.activate(profile_id, monitor) {
+                                Ok(profile_info) => {
+                                    ws_broadcaster.send(websocket::Event::ActiveProfile { monitor, profile: Some(profile_id) });
+                                    render_service.set_forced_profile(profile_info.profile.map(|profile| (monitor, profile))).await;
+                                },
+                                Err(e) => warn!("Could not force profile: {}", e),
+                            }
+                        },
+                        websocket::Frame::ClearForcedProfile => {
+                            // Tell clients about the profiles that apply again
+                            for monitor in 0..config.monitors.len() as u32 {
+                                ws_broadcaster.send(websocket::Event::ActiveProfile {
+                                    monitor,
+                                    profile: render_service.active_profile(monitor).map(|active| active.profile.id),
+                                });
+                            }
+                            render_service.set_forced_profile(None).await;
+                        },
+                        websocket::Frame::SolidColor { color, duration } => {
+                            let until = duration.map(|duration| std::time::Instant::now() + duration);
+                            render_service.update_control(|control| control.solid_color = Some(render_service::SolidColor { color, until }));
+                        },
+                        websocket::Frame::ClearSolidColor => {
+                            render_service.update_control(|control| control.solid_color = None);
+                        },
+                        websocket::Frame::Source(source) => {
+                            render_service.set_source(source);
+                        },
+                        websocket::Frame::ClearSource(name) => {
+                            render_service.clear_source(&name);
+                        },
+                        websocket::Frame::Input(packet) => {
+                            apply_input(input_sources.apply(packet, std::time::Instant::now()), &render_service);
+                        },
                     };
                 }
             },
+            Some(packet) = input_packets.next() => {
+                apply_input(input_sources.apply(packet, std::time::Instant::now()), &render_service);
+            },
+            output_state = render_service.next_output_state() => {
+                match output_state.state {
+                    render_service::OutputState::Connected => info!("Device '{}' is connected", output_state.device_name),
+                    render_service::OutputState::Disconnected => warn!("Device '{}' is disconnected: {}",
+                        output_state.device_name, output_state.error.as_deref().unwrap_or("unknown error")),
+                }
+                device_states.insert(output_state.device_name.clone(), output_state.clone());
+                ws_broadcaster.send(device_state_event(output_state));
+            },
             profile_info = profile_listener.next() => {
                 match profile_info {
-                    Ok(profile_info) => render_service.set_active_profile(profile_info.monitor_index, profile_info.profile).await,
+                    Ok(profile_info) => {
+                        ws_broadcaster.send(websocket::Event::ActiveProfile {
+                            monitor: profile_info.monitor_index,
+                            profile: profile_info.profile.as_ref().map(|active| active.profile.id),
+                        });
+                        render_service.set_active_profile(profile_info.monitor_index, profile_info.profile).await;
+                    },
                     Err(e) => warn!("Profile listener got error: {}", e),
                 }
             },
+            scheduled = scheduler.next() => {
+                render_service.update_control(|control| {
+                    control.scheduled_brightness = scheduled.brightness;
+                    control.idle = scheduled.idle;
+                });
+            },
+            // select! creates the futures of disabled branches too, so the watcher can't be unwrapped there
+            _ = async {
+                match config_watcher.as_mut() {
+                    Some(watcher) => watcher.changed().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match config::Config::load(&args) {
+                    Ok(new_config) => {
+                        info!("Applying changed configuration");
+                        let stored_config = config_store.as_ref().map(store::ConfigStore::config).unwrap_or_default();
+                        apply_config_changes(&config, &new_config, &stored_config, &mut render_service, &mut profile_listener, &mut scheduler).await;
+                        config = new_config;
+                        ws_broadcaster.send(websocket::Event::ConfigReloaded { error: None });
+                    },
+                    Err(e) => {
+                        error!("Keeping the previous configuration: {}", e);
+                        ws_broadcaster.send(websocket::Event::ConfigReloaded { error: Some(e.to_string()) });
+                    },
+                }
+            },
+            _ = telemetry_interval.tick() => {
+                telemetry_tx.send_replace(telemetry.record(render_service.counters()));
+            },
             _ = tokio::signal::ctrl_c() => {
                 shutdown.cancel();
                 break;
@@ -94,4 +292,85 @@ async fn main() {
         }
     }
     info!("Shutting down...");
+    // Turn off the LEDs before exiting, rather than leaving them at their last color
+    render_service.shutdown_devices().await;
+}
+
+fn device_state_event(state: render_service::OutputStateEvent) -> websocket::Event {
+    websocket::Event::DeviceState {
+        name: state.device_name,
+        connected: state.state == render_service::OutputState::Connected,
+        error: state.error,
+    }
+}
+
+/// Applies the differences between the `old` and `new` configuration, leaving everything that didn't change running.
+///
+/// Devices and profiles that are no longer given in the configuration file are replaced by the stored ones.
+async fn apply_config_changes(old: &config::Config, new: &config::Config, stored: &store::StoredConfig,
+                              render_service: &mut render_service::RenderService, profile_listener: &mut profiles::ProfileListener,
+                              scheduler: &mut scheduler::Scheduler) {
+    if new.desktop_capture_fps != old.desktop_capture_fps || new.desktop_capture_decimation != old.desktop_capture_decimation {
+        render_service.set_capture_settings(new.desktop_capture_fps, new.desktop_capture_decimation).await;
+    }
+    if new.default_capture_region_hor != old.default_capture_region_hor || new.default_capture_region_ver != old.default_capture_region_ver {
+        render_service.set_default_capture_regions(new.default_capture_region_hor, new.default_capture_region_ver).await;
+    }
+    if new.capture_priority != old.capture_priority {
+        render_service.set_source(new.capture_source());
+    }
+    if new.transition_duration_ms != old.transition_duration_ms {
+        render_service.update_control(|control| control.transition = new.transition_duration());
+    }
+    if new.monitors != old.monitors {
+        profile_listener.set_monitors(new.monitors.clone());
+    }
+    if new.ignored_windows != old.ignored_windows {
+        profile_listener.set_ignored_windows(new.ignored_windows().unwrap_or_default());
+    }
+    if new.profile_grace_periods() != old.profile_grace_periods() {
+        let (activation_delay, deactivation_delay) = new.profile_grace_periods();
+        profile_listener.set_grace_periods(activation_delay, deactivation_delay);
+    }
+    if new.schedule_changed(old) {
+        scheduler.set_settings(new.schedule_settings().unwrap_or_default());
+    }
+    if new.profiles != old.profiles {
+        info!("Applying changed profiles");
+        profile_listener.set_profiles(websocket::parse_profiles(new.profiles.as_ref().unwrap_or(&stored.profiles)).0);
+    }
+    // Without devices in the file, the stored ones are used, so adding or removing identical devices changes nothing
+    let new_devices = new.devices.as_ref().unwrap_or(&stored.devices);
+    if new_devices != old.devices.as_ref().unwrap_or(&stored.devices) {
+        info!("Applying changed devices");
+        render_service.set_devices(websocket::parse_devices(new_devices).0).await;
+    }
+    if new.websocket_port != old.websocket_port || new.metrics_port != old.metrics_port || new.input_port != old.input_port
+        || new.hyperion_port != old.hyperion_port || new.hyperion_address != old.hyperion_address {
+        warn!("Changed ports are only used after restarting");
+    }
+}
+
+fn apply_input(change: input::SourceChange, render_service: &render_service::RenderService) {
+    match change {
+        input::SourceChange::Set(source) => render_service.set_source(source),
+        input::SourceChange::Clear(name) => { render_service.clear_source(&name); },
+    }
+}
+
+/// Logs to stdout, and to the log file if one was given.
+fn init_logging(args: &config::Args) {
+    let mut loggers: Vec<Box<dyn simplelog::SharedLogger>> = vec![simplelog::TermLogger::new(
+        args.log_level,
+        simplelog::Config::default(),
+        simplelog::TerminalMode::Stdout,
+        simplelog::ColorChoice::Auto
+    )];
+    if let Some(path) = &args.log_file {
+        match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => loggers.push(simplelog::WriteLogger::new(args.log_level, simplelog::Config::default(), file)),
+            Err(e) => eprintln!("Could not open log file {}: {}", path.display(), e),
+        }
+    }
+    simplelog::CombinedLogger::init(loggers).unwrap();
 }
@@ -1,13 +1,24 @@
 use simple_error::{ SimpleError, try_with };
-use log::info;
+use log::{info, debug};
 use std::net;
+use std::time::{Duration, Instant};
 
 use crate::common::RgbVec;
 use crate::render_service::RenderOutput;
+use crate::render_service::specification::OutputSpecification;
 
 const QMK_HID_USAGE_PAGE: u16 = 0xFF60;
 const QMK_HID_USAGE: u16      = 0x61;
 
+/// Opens the output described by `spec`, with `size` LEDs.
+pub fn open_output(spec: &OutputSpecification, size: usize) -> Result<Box<dyn RenderOutput + Send>, SimpleError> {
+    Ok(match spec {
+        OutputSpecification::Wled { address, port } => Box::new(WledRenderOutput::new(size, address.clone(), *port)?),
+        OutputSpecification::Qmk { vendor_id, product_id } => Box::new(QmkRenderOutput::new(size, *vendor_id, *product_id)?),
+        OutputSpecification::Serial { port_name, baud_rate, protocol } => Box::new(SerialRenderOutput::new(size, port_name, *baud_rate, *protocol)?),
+    })
+}
+
 /// A network device running WLED (<https://kno.wled.ge/>).
 pub struct WledRenderOutput {
     size: usize,
@@ -52,13 +63,54 @@ impl RenderOutput for WledRenderOutput {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn shutdown(&mut self) -> Result<(), SimpleError> {
+        // A timeout of 0 makes WLED leave realtime mode immediately, returning to its normal effects
+        let packet = [self.output_buffer[0], 0];
+        try_with!(self.socket.send_to(&packet, format!("{}:{}", self.address, self.port)), format!("{}:{}", self.address, self.port));
+        Ok(())
+    }
 }
 
 /// A keyboard running the QMK firmware
-pub struct QmkRenderOutput {
+///
+/// Colors are sent using QMK's raw HID feature. Raw HID reports are fixed at [QMK_REPORT_SIZE] bytes, so a frame is
+/// split up into several reports:
+///
+/// * `[QMK_CMD_SET_LEDS, offset_lo, offset_hi, count, r0, g0, b0, r1, g1, b1, ...]` - Sets the colors of `count` LEDs
+///   (at most [QMK_LEDS_PER_REPORT]), starting at the 16-bit little-endian LED index `offset`.
+/// * `[QMK_CMD_COMMIT]` - Displays all colors received since the last commit. The keyboard should not show any colors
+///   before receiving this, so that frames are always updated atomically.
+/// * `[QMK_CMD_RELEASE]` - Sent when the output is shut down. The keyboard should go back to its own lighting effects.
+///
+/// Unused bytes at the end of each report are zero.
+pub struct QmkRenderOutput<D: HidWrite = hidapi::HidDevice> {
     size: usize,
+    /// All reports for a frame (including the commit report), each prefixed with the report ID.
     output_buffer: Vec<u8>,
-    hid_device: hidapi::HidDevice,
+    hid_device: D,
+}
+
+/// The size of a QMK raw HID report, excluding the report ID.
+const QMK_REPORT_SIZE: usize = 32;
+const QMK_CMD_SET_LEDS: u8 = 0xEE;
+const QMK_CMD_COMMIT: u8   = 0xEF;
+const QMK_CMD_RELEASE: u8  = 0xF0;
+/// The size of the header of a [QMK_CMD_SET_LEDS] report: the command, the offset and the count.
+const QMK_SET_LEDS_HEADER_SIZE: usize = 4;
+const QMK_LEDS_PER_REPORT: usize = (QMK_REPORT_SIZE - QMK_SET_LEDS_HEADER_SIZE) / 3;
+
+/// A device that raw HID reports can be written to. Implemented by [hidapi::HidDevice], and allows mocking the device
+/// in tests.
+pub trait HidWrite {
+    /// Writes a report to the device, returning the number of bytes written. The first byte of `data` is the report ID.
+    fn write(&mut self, data: &[u8]) -> Result<usize, SimpleError>;
+}
+
+impl HidWrite for hidapi::HidDevice {
+    fn write(&mut self, data: &[u8]) -> Result<usize, SimpleError> {
+        hidapi::HidDevice::write(self, data).map_err(SimpleError::from)
+    }
 }
 
 use std::sync::Mutex;
@@ -72,43 +124,97 @@ lazy_static::lazy_static! {
 impl QmkRenderOutput {
     pub fn new(size: usize, vendor_id: u16, product_id: u16) -> Result<Self, SimpleError> {
         info!("Creating QMK output of size {} for VID({:#x}) PID({:#x})", size, vendor_id, product_id);
-        let guard = API.lock().unwrap();
-        let api = guard.as_ref().map_err(SimpleError::from)?;
+        let mut guard = API.lock().unwrap();
+        let api = guard.as_mut().map_err(|e| SimpleError::new(e.to_string()))?;
+        // The device may have been plugged in (or reconnected) since we last looked
+        api.refresh_devices().map_err(SimpleError::from)?;
         let device_info = api.device_list()
-            .find(|dev| dev.vendor_id() == vendor_id &&
-                dev.product_id() == product_id &&
-                dev.usage_page() == QMK_HID_USAGE_PAGE &&
-                dev.usage() == QMK_HID_USAGE)
+            .find(|dev| dev.vendor_id() == vendor_id && dev.product_id() == product_id && is_qmk_raw_hid(dev))
             .ok_or(SimpleError::new("No such device"))?;
         let device = device_info.open_device(api).map_err(SimpleError::from)?;
+        QmkRenderOutput::with_device(size, device)
+    }
+}
+
+/// Whether `device` is the raw HID interface of a QMK keyboard.
+fn is_qmk_raw_hid(device: &hidapi::DeviceInfo) -> bool {
+    device.usage_page() == QMK_HID_USAGE_PAGE && device.usage() == QMK_HID_USAGE
+}
+
+/// A connected device that [QmkRenderOutput::new] can open.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableQmkDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Lists the connected devices with a QMK raw HID interface.
+pub fn available_qmk_devices() -> Result<Vec<AvailableQmkDevice>, SimpleError> {
+    let mut guard = API.lock().unwrap();
+    let api = guard.as_mut().map_err(|e| SimpleError::new(e.to_string()))?;
+    api.refresh_devices().map_err(SimpleError::from)?;
+    let mut devices: Vec<AvailableQmkDevice> = Vec::new();
+    for info in api.device_list().filter(|dev| is_qmk_raw_hid(dev)) {
+        // Some platforms list an interface once per top-level collection
+        if devices.iter().any(|dev| dev.vendor_id == info.vendor_id() && dev.product_id == info.product_id()) {
+            continue;
+        }
+        devices.push(AvailableQmkDevice {
+            vendor_id: info.vendor_id(),
+            product_id: info.product_id(),
+            manufacturer: info.manufacturer_string().map(str::to_string),
+            product: info.product_string().map(str::to_string),
+        });
+    }
+    Ok(devices)
+}
+
+impl<D: HidWrite> QmkRenderOutput<D> {
+    /// Creates an output that writes to an already opened device.
+    pub fn with_device(size: usize, hid_device: D) -> Result<Self, SimpleError> {
+        if size > u16::MAX as usize + 1 {
+            return Err(SimpleError::new(format!("The QMK output supports a maximum size of {}", u16::MAX as usize + 1)));
+        }
 
-        let mut output_buffer = vec![0u8; 3 + 3*size];
-        output_buffer[0] = 0;
-        output_buffer[1] = 0xED;
-        output_buffer[2] = size as u8;
+        let n_led_reports = size.div_ceil(QMK_LEDS_PER_REPORT);
+        let mut output_buffer = vec![0u8; (n_led_reports + 1) * (1 + QMK_REPORT_SIZE)];
+        for (i, report) in output_buffer.chunks_exact_mut(1 + QMK_REPORT_SIZE).enumerate() {
+            // report[0] is the report ID, which is always 0
+            if i == n_led_reports {
+                report[1] = QMK_CMD_COMMIT;
+            } else {
+                let offset = i * QMK_LEDS_PER_REPORT;
+                let count = QMK_LEDS_PER_REPORT.min(size - offset);
+                report[1] = QMK_CMD_SET_LEDS;
+                report[2..4].copy_from_slice(&(offset as u16).to_le_bytes());
+                report[4] = count as u8;
+            }
+        }
         Ok(QmkRenderOutput {
             size,
             output_buffer,
-            hid_device: device,
+            hid_device,
         })
     }
 }
 
-impl RenderOutput for QmkRenderOutput {
+impl<D: HidWrite> RenderOutput for QmkRenderOutput<D> {
     fn draw(&mut self, buffer: &RgbVec) -> Result<(), SimpleError> {
-        assert_eq!(3*buffer.len() + 3, self.output_buffer.len());
+        assert_eq!(buffer.len(), self.size);
 
         for (i, &color) in buffer.iter().enumerate() {
-            self.output_buffer[3 + 3*i] = (color.red * 255f32) as u8;
-            self.output_buffer[3 + 3*i + 1] = (color.green * 255f32) as u8;
-            self.output_buffer[3 + 3*i + 2] = (color.blue * 255f32) as u8;
+            let report_start = (i / QMK_LEDS_PER_REPORT) * (1 + QMK_REPORT_SIZE);
+            let color_start = report_start + 1 + QMK_SET_LEDS_HEADER_SIZE + 3*(i % QMK_LEDS_PER_REPORT);
+            self.output_buffer[color_start] = (color.red * 255f32) as u8;
+            self.output_buffer[color_start + 1] = (color.green * 255f32) as u8;
+            self.output_buffer[color_start + 2] = (color.blue * 255f32) as u8;
         }
 
-        let bytes_written = self.hid_device.write(&self.output_buffer).map_err(SimpleError::from)?;
-        if bytes_written < self.output_buffer.len() {
-            return Err(SimpleError::new(
-                format!("Expected to write {} bytes, but actual value was {}.", self.output_buffer.len(), bytes_written)
-            ));
+        for report in self.output_buffer.chunks_exact(1 + QMK_REPORT_SIZE) {
+            write_report(&mut self.hid_device, report)?;
         }
         Ok(())
     }
@@ -116,49 +222,177 @@ impl RenderOutput for QmkRenderOutput {
     fn size(&self) -> usize {
         self.size
     }
+
+    fn shutdown(&mut self) -> Result<(), SimpleError> {
+        let mut report = [0u8; 1 + QMK_REPORT_SIZE];
+        report[1] = QMK_CMD_RELEASE;
+        write_report(&mut self.hid_device, &report)
+    }
+}
+
+fn write_report<D: HidWrite>(hid_device: &mut D, report: &[u8]) -> Result<(), SimpleError> {
+    let bytes_written = hid_device.write(report)?;
+    if bytes_written < report.len() {
+        return Err(SimpleError::new(
+            format!("Expected to write {} bytes, but actual value was {}.", report.len(), bytes_written)
+        ));
+    }
+    Ok(())
+}
+
+/// The protocol spoken by a [SerialRenderOutput].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialProtocol {
+    /// The Adalight protocol (<https://github.com/adafruit/Adalight>).
+    Adalight,
+    /// The "Awa" variant of Adalight used by HyperSerial (<https://github.com/awawa-dev/HyperSerialESP32>), which adds
+    /// Fletcher checksums to the end of each frame.
+    Awa,
+}
+
+impl SerialProtocol {
+    /// The magic bytes that start each frame. Devices also send these in their hello message when they are ready.
+    fn magic(&self) -> &'static [u8; 3] {
+        match self {
+            SerialProtocol::Adalight => b"Ada",
+            SerialProtocol::Awa => b"Awa",
+        }
+    }
+}
+
+pub const SERIAL_DEFAULT_BAUD_RATE: u32 = 115_200;
+/// How long to wait for the device's hello message after opening the port. Many Arduinos reset when the port is
+/// opened, and drop anything they receive before they've finished booting.
+const SERIAL_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The size of the frame header: the magic bytes, the LED count and the header checksum.
+const SERIAL_HEADER_SIZE: usize = 6;
+
+/// A serial port that a [SerialRenderOutput] could be opened for.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableSerialPort {
+    pub port_name: String,
+    /// The IDs and descriptions of USB devices. Most LED controllers are USB serial adapters.
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Lists the serial ports on this system.
+pub fn available_serial_ports() -> Result<Vec<AvailableSerialPort>, SimpleError> {
+    let ports = serialport::available_ports().map_err(SimpleError::from)?;
+    Ok(ports.into_iter().map(|port| match port.port_type {
+        serialport::SerialPortType::UsbPort(usb) => AvailableSerialPort {
+            port_name: port.port_name,
+            vendor_id: Some(usb.vid),
+            product_id: Some(usb.pid),
+            manufacturer: usb.manufacturer,
+            product: usb.product,
+        },
+        _ => AvailableSerialPort {
+            port_name: port.port_name,
+            vendor_id: None,
+            product_id: None,
+            manufacturer: None,
+            product: None,
+        },
+    }).collect())
 }
 
-/// A serial port speaking the Adalight protocol.
+/// A serial port speaking the Adalight protocol, or one of its variants (see [SerialProtocol]).
 pub struct SerialRenderOutput {
     port: Box<dyn serialport::SerialPort>,
+    protocol: SerialProtocol,
     output_buffer: Vec<u8>,
     size: usize,
+    /// When the port was opened, if we're still waiting for the device's hello message.
+    awaiting_handshake_since: Option<Instant>,
+    /// Data received while waiting for the hello message.
+    handshake_buffer: Vec<u8>,
 }
 
 impl SerialRenderOutput {
-    pub fn new(size: usize, port_name: &str) -> Result<Self, SimpleError> {
-        if size > 256 {
-            return Err(SimpleError::new("The serial output supports a maximum size of 256"));
-        }
-        let port = serialport::new(port_name, 115_200)
-            .timeout(std::time::Duration::from_millis(10))
+    pub fn new(size: usize, port_name: &str, baud_rate: u32, protocol: SerialProtocol) -> Result<Self, SimpleError> {
+        info!("Creating serial output of size {} for port '{}' ({} baud, {:?})", size, port_name, baud_rate, protocol);
+        let port = serialport::new(port_name, baud_rate)
+            .timeout(Duration::from_millis(10))
             .open().map_err(SimpleError::from)?;
-        let mut output_buffer = vec![0u8; 6 + 3*size];
-        output_buffer[0] = 'A' as u8;
-        output_buffer[1] = 'd' as u8;
-        output_buffer[2] = 'a' as u8;
-        output_buffer[3] = 0;
-        output_buffer[4] = (size - 1) as u8;
-        output_buffer[5] = (size - 1) as u8 ^ 0x55;
+        SerialRenderOutput::with_port(size, port, protocol)
+    }
+
+    /// Creates an output that writes to an already opened port.
+    ///
+    /// No frames are written until the device has sent its hello message, or [SERIAL_HANDSHAKE_TIMEOUT] has passed.
+    pub fn with_port(size: usize, port: Box<dyn serialport::SerialPort>, protocol: SerialProtocol) -> Result<Self, SimpleError> {
+        if size == 0 || size > u16::MAX as usize + 1 {
+            return Err(SimpleError::new(format!("The serial output supports sizes between 1 and {}", u16::MAX as usize + 1)));
+        }
+        let checksum_size = if protocol == SerialProtocol::Awa { 3 } else { 0 };
+        let mut output_buffer = vec![0u8; SERIAL_HEADER_SIZE + 3*size + checksum_size];
+        let [count_hi, count_lo] = ((size - 1) as u16).to_be_bytes();
+        output_buffer[0..3].copy_from_slice(protocol.magic());
+        output_buffer[3] = count_hi;
+        output_buffer[4] = count_lo;
+        output_buffer[5] = count_hi ^ count_lo ^ 0x55;
         Ok(SerialRenderOutput {
             port,
+            protocol,
             output_buffer,
             size,
+            awaiting_handshake_since: Some(Instant::now()),
+            handshake_buffer: Vec::new(),
         })
     }
+
+    /// Reads any pending data from the device, returning whether its hello message has been received.
+    fn poll_handshake(&mut self) -> Result<bool, SimpleError> {
+        let available = self.port.bytes_to_read().map_err(SimpleError::from)? as usize;
+        if available == 0 {
+            return Ok(false);
+        }
+        let start = self.handshake_buffer.len();
+        self.handshake_buffer.resize(start + available, 0);
+        let read = self.port.read(&mut self.handshake_buffer[start..]).map_err(SimpleError::from)?;
+        self.handshake_buffer.truncate(start + read);
+
+        let magic = self.protocol.magic();
+        let found = self.handshake_buffer.windows(magic.len()).any(|window| window == magic);
+        // Keep the tail of the buffer, in case the magic is split between reads
+        let keep_from = self.handshake_buffer.len().saturating_sub(magic.len() - 1);
+        self.handshake_buffer.drain(..keep_from);
+        Ok(found)
+    }
 }
 
 impl RenderOutput for SerialRenderOutput {
     fn draw(&mut self, buffer: &RgbVec) -> Result<(), SimpleError> {
-        assert_eq!(3*buffer.len() + 6, self.output_buffer.len());
+        assert_eq!(buffer.len(), self.size);
+
+        if let Some(since) = self.awaiting_handshake_since {
+            if self.poll_handshake()? {
+                debug!("Received hello message from serial device");
+            } else if since.elapsed() >= SERIAL_HANDSHAKE_TIMEOUT {
+                debug!("No hello message received from serial device, sending frames anyway");
+            } else {
+                return Ok(());
+            }
+            self.awaiting_handshake_since = None;
+            self.handshake_buffer = Vec::new();
+        }
 
         for (i, &color) in buffer.iter().enumerate() {
-            self.output_buffer[6 + 3*i] = (color.red * 255f32) as u8;
-            self.output_buffer[6 + 3*i + 1] = (color.green * 255f32) as u8;
-            self.output_buffer[6 + 3*i + 2] = (color.blue * 255f32) as u8;
+            self.output_buffer[SERIAL_HEADER_SIZE + 3*i] = (color.red * 255f32) as u8;
+            self.output_buffer[SERIAL_HEADER_SIZE + 3*i + 1] = (color.green * 255f32) as u8;
+            self.output_buffer[SERIAL_HEADER_SIZE + 3*i + 2] = (color.blue * 255f32) as u8;
+        }
+        if self.protocol == SerialProtocol::Awa {
+            let data_end = SERIAL_HEADER_SIZE + 3*self.size;
+            let checksums = awa_checksums(&self.output_buffer[SERIAL_HEADER_SIZE..data_end]);
+            self.output_buffer[data_end..].copy_from_slice(&checksums);
         }
 
-        let written = self.port.write(&self.output_buffer).unwrap();
+        let written = self.port.write(&self.output_buffer).map_err(SimpleError::from)?;
         if written != self.output_buffer.len() {
             return Err(SimpleError::new(format!("Expected to write {} bytes, actual value was {}", self.output_buffer.len(), written)));
         }
@@ -170,3 +404,173 @@ impl RenderOutput for SerialRenderOutput {
         self.size
     }
 }
+
+/// Calculates the checksums that end each frame in the [SerialProtocol::Awa] protocol: two Fletcher-16 sums, plus an
+/// extended sum which also depends on the position of each byte.
+fn awa_checksums(data: &[u8]) -> [u8; 3] {
+    let mut fletcher1: u16 = 0;
+    let mut fletcher2: u16 = 0;
+    let mut fletcher_ext: u32 = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        // The position is a 16-bit counter on the device, so it isn't truncated to a byte
+        fletcher_ext = (fletcher_ext + (byte as u16 ^ i as u16) as u32) % 255;
+        fletcher1 = (fletcher1 + byte as u16) % 255;
+        fletcher2 = (fletcher2 + fletcher1) % 255;
+    }
+    // 0x41 ('A') is avoided, since the device would take it as the start of a new frame
+    if fletcher_ext == 0x41 {
+        fletcher_ext = 0xaa;
+    }
+    [fletcher1 as u8, fletcher2 as u8, fletcher_ext as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color::RgbF32;
+
+    /// A [HidWrite] that records all reports written to it.
+    struct MockHidDevice {
+        reports: Vec<Vec<u8>>,
+        /// If set, each write reports this many bytes as written instead of the full report.
+        short_write: Option<usize>,
+    }
+
+    impl MockHidDevice {
+        fn new() -> Self {
+            MockHidDevice { reports: Vec::new(), short_write: None }
+        }
+    }
+
+    impl HidWrite for MockHidDevice {
+        fn write(&mut self, data: &[u8]) -> Result<usize, SimpleError> {
+            self.reports.push(data.to_vec());
+            Ok(self.short_write.unwrap_or(data.len()))
+        }
+    }
+
+    fn test_colors(size: usize) -> RgbVec {
+        (0..size).map(|i| RgbF32 { red: i as f32 / 255.0, green: 1.0, blue: 0.0 }).collect()
+    }
+
+    #[test]
+    fn test_qmk_single_report() {
+        let mut output = QmkRenderOutput::with_device(3, MockHidDevice::new()).unwrap();
+        output.draw(&test_colors(3)).unwrap();
+
+        let reports = &output.hid_device.reports;
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|report| report.len() == 1 + QMK_REPORT_SIZE));
+        assert_eq!(reports[0][..14], [0, QMK_CMD_SET_LEDS, 0, 0, 3, 0, 255, 0, 1, 255, 0, 2, 255, 0]);
+        assert!(reports[0][14..].iter().all(|&b| b == 0));
+        assert_eq!(reports[1][..2], [0, QMK_CMD_COMMIT]);
+        assert!(reports[1][2..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_qmk_multiple_reports() {
+        let size = 2 * QMK_LEDS_PER_REPORT + 2;
+        let mut output = QmkRenderOutput::with_device(size, MockHidDevice::new()).unwrap();
+        output.draw(&test_colors(size)).unwrap();
+        output.draw(&test_colors(size)).unwrap();
+
+        let reports = &output.hid_device.reports;
+        assert_eq!(reports.len(), 8);
+        for frame in reports.chunks(4) {
+            let expected_headers = [
+                (0u16, QMK_LEDS_PER_REPORT),
+                (QMK_LEDS_PER_REPORT as u16, QMK_LEDS_PER_REPORT),
+                (2 * QMK_LEDS_PER_REPORT as u16, 2),
+            ];
+            for (report, (offset, count)) in frame.iter().zip(expected_headers) {
+                assert_eq!(report[1], QMK_CMD_SET_LEDS);
+                assert_eq!(u16::from_le_bytes([report[2], report[3]]), offset);
+                assert_eq!(report[4] as usize, count);
+                // The red channel of each color is its index
+                for i in 0..count {
+                    assert_eq!(report[5 + 3*i] as u16, offset + i as u16);
+                }
+            }
+            assert_eq!(frame[3][1], QMK_CMD_COMMIT);
+        }
+    }
+
+    #[test]
+    fn test_qmk_short_write() {
+        let mut device = MockHidDevice::new();
+        device.short_write = Some(8);
+        let mut output = QmkRenderOutput::with_device(20, device).unwrap();
+        assert!(output.draw(&test_colors(20)).is_err());
+        // Nothing should be written after the failed report, especially not the commit
+        assert_eq!(output.hid_device.reports.len(), 1);
+    }
+
+    #[test]
+    fn test_qmk_shutdown() {
+        let mut output = QmkRenderOutput::with_device(20, MockHidDevice::new()).unwrap();
+        output.shutdown().unwrap();
+        let reports = &output.hid_device.reports;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0][..2], [0, QMK_CMD_RELEASE]);
+    }
+
+    #[test]
+    fn test_awa_checksums() {
+        assert_eq!(awa_checksums(&[]), [0, 0, 0]);
+        assert_eq!(awa_checksums(&[1, 2, 3]), [6, 10, 5]);
+        assert_eq!(awa_checksums(&[255; 4]), [0, 0, 249]);
+        // Positions past 255 are part of the extended sum
+        assert_eq!(awa_checksums(&[7; 300]), [60, 105, 241]);
+    }
+
+    /// Opens a pty pair, returning (output_port, device_port).
+    #[cfg(unix)]
+    fn open_pty_pair() -> (Box<dyn serialport::SerialPort>, serialport::TTYPort) {
+        use serialport::SerialPort;
+        let (mut device, output) = serialport::TTYPort::pair().unwrap();
+        device.set_timeout(Duration::from_millis(500)).unwrap();
+        (Box::new(output), device)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serial_adalight() {
+        use std::io::{Read, Write};
+        let (port, mut device) = open_pty_pair();
+        let mut output = SerialRenderOutput::with_port(300, port, SerialProtocol::Adalight).unwrap();
+
+        // Nothing is written before the device is ready
+        output.draw(&test_colors(300)).unwrap();
+        device.write_all(b"Ada\n").unwrap();
+        device.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        output.draw(&test_colors(300)).unwrap();
+
+        let mut frame = vec![0u8; SERIAL_HEADER_SIZE + 3*300];
+        device.read_exact(&mut frame).unwrap();
+        assert_eq!(frame[..6], [b'A', b'd', b'a', 0x01, 0x2B, 0x01 ^ 0x2B ^ 0x55]);
+        assert_eq!(frame[6..9], [0, 255, 0]);
+        assert_eq!(frame[frame.len() - 3..], [255, 255, 0]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serial_awa() {
+        use std::io::{Read, Write};
+        let (port, mut device) = open_pty_pair();
+        let mut output = SerialRenderOutput::with_port(1, port, SerialProtocol::Awa).unwrap();
+        // The hello message may be split up and surrounded by other text
+        device.write_all(b"Welcome!\r\nA").unwrap();
+        device.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        output.draw(&vec![RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }]).unwrap();
+        device.write_all(b"wa driver\r\n").unwrap();
+        device.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        output.draw(&vec![RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }]).unwrap();
+
+        let mut frame = [0u8; SERIAL_HEADER_SIZE + 3 + 3];
+        device.read_exact(&mut frame).unwrap();
+        assert_eq!(frame, [b'A', b'w', b'a', 0, 0, 0x55, 255, 0, 0, 0, 0, 3]);
+    }
+}
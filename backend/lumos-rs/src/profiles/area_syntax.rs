@@ -0,0 +1,396 @@
+//! The text syntax for [MonitorAreaSpecification]s, which is also used by the frontend. For example:
+//!
+//! ```text
+//! 2560x1440 - {
+//!    x: 0px;
+//!    y: 840px;
+//!    width: 100%;
+//!    height: 600px;
+//! }
+//! ```
+//!
+//! Each area starts with the monitor resolution it is valid for (or `*` for any resolution), followed by the sampling
+//! directions it can be used for: `-` for horizontal, `|` for vertical, or `*` for both. The block sets the position
+//! and size of the area in pixels (`px`, which may be negative) or as a percentage of the monitor's width or height
+//! (`%`).
+use std::fmt;
+
+use super::{MonitorAreaSpecification, MonitorDistance};
+
+/// An error in the text of an area specification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    /// The line the error is on, starting at 1.
+    pub line: usize,
+    /// The column the error is at, starting at 1.
+    pub col: usize,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {}, col {})", self.message, self.line, self.col)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Parses a list of areas.
+pub fn parse(text: &str) -> Result<Vec<MonitorAreaSpecification>, SyntaxError> {
+    let mut parser = Parser { tokens: tokenize(text), pos: 0 };
+    let mut areas = Vec::new();
+    while parser.peek().kind != TokenKind::Eof {
+        areas.push(parser.area()?);
+    }
+    Ok(areas)
+}
+
+/// Formats `areas` in the text syntax, so that [parse] returns them again.
+pub fn format(areas: &[MonitorAreaSpecification]) -> String {
+    let areas: Vec<String> = areas.iter()
+        .map(|area| {
+            let resolution = match area.resolution {
+                Some((width, height)) => format!("{}x{}", width, height),
+                None => "*".to_string(),
+            };
+            let direction = match (area.is_horizontal, area.is_vertical) {
+                (true, false) => "-",
+                (false, true) => "|",
+                _ => "*",
+            };
+            format!("{} {} {{\n   x: {};\n   y: {};\n   width: {};\n   height: {};\n}}", resolution, direction,
+                format_distance(area.left), format_distance(area.top), format_distance(area.width), format_distance(area.height))
+        })
+        .collect();
+    areas.join("\n\n")
+}
+
+fn format_distance(distance: MonitorDistance) -> String {
+    match distance {
+        MonitorDistance::Pixels(px) => format!("{}px", px),
+        MonitorDistance::Proportion(proportion) => format!("{}%", to_percentage(proportion)),
+    }
+}
+
+/// Converts a [MonitorDistance::Proportion] to the percentage it was written as, without the rounding errors of
+/// converting it to a proportion.
+pub fn to_percentage(proportion: f32) -> f64 {
+    (proportion as f64 * 100.0 * 1e4).round() / 1e4
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    StatementEnd,
+    BlockOpen,
+    BlockClose,
+    FieldEnd,
+    DecimalPoint,
+    PercentageSign,
+    Wildcard,
+    Pipe,
+    Hyphen,
+    Identifier(String),
+    Integer(String),
+    Unknown(char),
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::StatementEnd => write!(f, "';'"),
+            TokenKind::BlockOpen => write!(f, "'{{'"),
+            TokenKind::BlockClose => write!(f, "'}}'"),
+            TokenKind::FieldEnd => write!(f, "':'"),
+            TokenKind::DecimalPoint => write!(f, "'.'"),
+            TokenKind::PercentageSign => write!(f, "'%'"),
+            TokenKind::Wildcard => write!(f, "'*'"),
+            TokenKind::Pipe => write!(f, "'|'"),
+            TokenKind::Hyphen => write!(f, "'-'"),
+            TokenKind::Identifier(value) => write!(f, "'{}'", value),
+            TokenKind::Integer(value) => write!(f, "number {}", value),
+            TokenKind::Unknown(char) => write!(f, "'{}'", char),
+            TokenKind::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    col: usize,
+}
+
+/// Splits `text` into tokens, ending with a [TokenKind::Eof] token.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let (mut line, mut col) = (1, 1);
+    while let Some(&char) = chars.peek() {
+        let (start_line, start_col) = (line, col);
+        let mut take = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let char = chars.next().unwrap();
+            if char == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+            char
+        };
+        let kind = match char {
+            _ if char.is_whitespace() => {
+                take(&mut chars);
+                continue;
+            },
+            ';' => TokenKind::StatementEnd,
+            '{' => TokenKind::BlockOpen,
+            '}' => TokenKind::BlockClose,
+            ':' => TokenKind::FieldEnd,
+            '.' => TokenKind::DecimalPoint,
+            '%' => TokenKind::PercentageSign,
+            '*' => TokenKind::Wildcard,
+            '|' => TokenKind::Pipe,
+            '-' => TokenKind::Hyphen,
+            _ if char.is_ascii_digit() || char.is_ascii_alphabetic() => {
+                let is_digit = char.is_ascii_digit();
+                let mut value = String::new();
+                while let Some(&next) = chars.peek() && (if is_digit { next.is_ascii_digit() } else { next.is_ascii_alphabetic() }) {
+                    value.push(take(&mut chars));
+                }
+                tokens.push(Token {
+                    kind: if is_digit { TokenKind::Integer(value) } else { TokenKind::Identifier(value) },
+                    line: start_line,
+                    col: start_col,
+                });
+                continue;
+            },
+            _ => TokenKind::Unknown(char),
+        };
+        take(&mut chars);
+        tokens.push(Token { kind, line: start_line, col: start_col });
+    }
+    tokens.push(Token { kind: TokenKind::Eof, line, col });
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        // The last token is EOF, which is returned forever
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(token: &Token, message: impl Into<String>) -> SyntaxError {
+        SyntaxError { message: message.into(), line: token.line, col: token.col }
+    }
+
+    fn expected(token: &Token, expected: &str) -> SyntaxError {
+        Self::error(token, format!("Expected {}, got {}", expected, token.kind))
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<(), SyntaxError> {
+        let token = self.next();
+        if token.kind != kind {
+            return Err(Self::expected(&token, expected));
+        }
+        Ok(())
+    }
+
+    fn area(&mut self) -> Result<MonitorAreaSpecification, SyntaxError> {
+        let resolution = self.selector()?;
+        let (is_horizontal, is_vertical) = self.direction()?;
+        self.expect(TokenKind::BlockOpen, "'{'")?;
+        let (mut left, mut top, mut width, mut height) = (None, None, None, None);
+        while self.peek().kind != TokenKind::BlockClose {
+            let name_token = self.next();
+            let field = match &name_token.kind {
+                TokenKind::Identifier(name) if name == "x" => &mut left,
+                TokenKind::Identifier(name) if name == "y" => &mut top,
+                TokenKind::Identifier(name) if name == "width" => &mut width,
+                TokenKind::Identifier(name) if name == "height" => &mut height,
+                TokenKind::Identifier(_) => return Err(Self::error(&name_token, "Field name must be one of x, y, width or height")),
+                _ => return Err(Self::expected(&name_token, "a field name or '}'")),
+            };
+            if field.is_some() {
+                return Err(Self::error(&name_token, format!("{} was defined twice in this block", name_token.kind)));
+            }
+            self.expect(TokenKind::FieldEnd, "':'")?;
+            *field = Some(self.distance()?);
+            self.expect(TokenKind::StatementEnd, "';'")?;
+        }
+        let block_close = self.next();
+        let require = |field: Option<MonitorDistance>, name: &str| {
+            field.ok_or_else(|| Self::error(&block_close, format!("Area is missing '{}' field", name)))
+        };
+        Ok(MonitorAreaSpecification {
+            resolution,
+            is_horizontal,
+            is_vertical,
+            left: require(left, "x")?,
+            top: require(top, "y")?,
+            width: require(width, "width")?,
+            height: require(height, "height")?,
+        })
+    }
+
+    fn selector(&mut self) -> Result<Option<(usize, usize)>, SyntaxError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Wildcard => Ok(None),
+            TokenKind::Integer(width) => {
+                let x = self.next();
+                if x.kind != TokenKind::Identifier("x".to_string()) {
+                    return Err(Self::expected(&x, "'x'"));
+                }
+                let height_token = self.next();
+                let TokenKind::Integer(height) = &height_token.kind else {
+                    return Err(Self::expected(&height_token, "a number"));
+                };
+                let parse = |value: &str, token: &Token| value.parse().map_err(|_| Self::error(token, "Resolution is too large"));
+                Ok(Some((parse(width, &token)?, parse(height, &height_token)?)))
+            },
+            _ => Err(Self::expected(&token, "'*' or monitor dimensions")),
+        }
+    }
+
+    /// Returns whether the area is horizontal and whether it is vertical.
+    fn direction(&mut self) -> Result<(bool, bool), SyntaxError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Wildcard => Ok((true, true)),
+            TokenKind::Hyphen => Ok((true, false)),
+            TokenKind::Pipe => Ok((false, true)),
+            _ => Err(Self::expected(&token, "'*', '-' or '|'")),
+        }
+    }
+
+    fn distance(&mut self) -> Result<MonitorDistance, SyntaxError> {
+        let number_token = self.peek().clone();
+        // Pixels can be negative, e.g. for monitors left of the primary one
+        let negative = number_token.kind == TokenKind::Hyphen;
+        if negative {
+            self.next();
+        }
+        let (value, is_integer) = self.number()?;
+        let value = if negative { format!("-{}", value) } else { value };
+        let unit = self.next();
+        match &unit.kind {
+            TokenKind::PercentageSign => {
+                let value: f64 = value.parse().map_err(|_| Self::error(&number_token, "Invalid number"))?;
+                if !(0.0..=100.0).contains(&value) {
+                    return Err(Self::error(&number_token, "Value out of bounds, must be 0-100"));
+                }
+                Ok(MonitorDistance::Proportion((value / 100.0) as f32))
+            },
+            TokenKind::Identifier(unit_name) if unit_name == "px" => {
+                if !is_integer {
+                    return Err(Self::error(&number_token, "Pixel values must be whole numbers"));
+                }
+                // Pixels are exchanged with the frontend as 32-bit integers
+                let value: i32 = value.parse().map_err(|_| Self::error(&number_token, "Pixel value is too large"))?;
+                Ok(MonitorDistance::Pixels(value as isize))
+            },
+            TokenKind::Identifier(_) => Err(Self::error(&unit, format!("Unexpected {}, only 'px' and '%' units are supported", unit.kind))),
+            _ => Err(Self::expected(&unit, "'px' or '%'")),
+        }
+    }
+
+    /// Returns the text of the number, and whether it is an integer.
+    fn number(&mut self) -> Result<(String, bool), SyntaxError> {
+        let token = self.next();
+        let TokenKind::Integer(mut value) = token.kind.clone() else {
+            return Err(Self::expected(&token, "a number"));
+        };
+        let mut is_integer = true;
+        if self.peek().kind == TokenKind::DecimalPoint {
+            self.next();
+            let fraction = self.next();
+            let TokenKind::Integer(fraction_value) = &fraction.kind else {
+                return Err(Self::expected(&fraction, "a number"));
+            };
+            value = format!("{}.{}", value, fraction_value);
+            is_integer = false;
+        }
+        Ok((value, is_integer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "2560x1440 - {\n   x: 0px;\n   y: 840px;\n   width: 100%;\n   height: 600px;\n}\n\n* * {\n   x: 12.5%;\n   y: 0px;\n   width: 75%;\n   height: 50%;\n}";
+
+    #[test]
+    fn test_parse() {
+        let areas = parse(TEXT).unwrap();
+        assert_eq!(areas, vec![
+            MonitorAreaSpecification {
+                resolution: Some((2560, 1440)),
+                is_horizontal: true,
+                is_vertical: false,
+                left: MonitorDistance::Pixels(0),
+                top: MonitorDistance::Pixels(840),
+                width: MonitorDistance::Proportion(1.0),
+                height: MonitorDistance::Pixels(600),
+            },
+            MonitorAreaSpecification {
+                resolution: None,
+                is_horizontal: true,
+                is_vertical: true,
+                left: MonitorDistance::Proportion(0.125),
+                top: MonitorDistance::Pixels(0),
+                width: MonitorDistance::Proportion(0.75),
+                height: MonitorDistance::Proportion(0.5),
+            },
+        ]);
+        // The fields can be in any order, and whitespace doesn't matter
+        assert!(parse("*|{height:1px;width:2px;y:3px;x:4px;}").is_ok());
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(&parse(TEXT).unwrap()), TEXT);
+        let areas = vec![MonitorAreaSpecification {
+            resolution: None,
+            is_horizontal: false,
+            is_vertical: true,
+            left: MonitorDistance::Proportion(0.8),
+            top: MonitorDistance::Proportion(0.333),
+            width: MonitorDistance::Pixels(-10),
+            height: MonitorDistance::Proportion(0.0),
+        }];
+        assert!(format(&areas).contains("x: 80%;\n   y: 33.3%;\n   width: -10px;"));
+        assert_eq!(parse(&format(&areas)).unwrap(), areas);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| parse(text).unwrap_err().to_string();
+        assert_eq!(error("* * {\n   x: 10em;\n}"), "Unexpected 'em', only 'px' and '%' units are supported (line 2, col 9)");
+        assert_eq!(error("* * {\n   x: 1px;\n   x: 2px;\n}"), "'x' was defined twice in this block (line 3, col 4)");
+        assert_eq!(error("* * {\n   x: 1px;\n}"), "Area is missing 'y' field (line 3, col 1)");
+        assert_eq!(error("* * {\n   x: 101%;"), "Value out of bounds, must be 0-100 (line 2, col 7)");
+        assert_eq!(error("* * {\n   x: 1.5px;"), "Pixel values must be whole numbers (line 2, col 7)");
+        assert_eq!(error("* * {\n   x: -1%;"), "Value out of bounds, must be 0-100 (line 2, col 7)");
+        assert_eq!(error("* * {\n   x: 99999999999px;"), "Pixel value is too large (line 2, col 7)");
+        assert_eq!(error("1920x - {"), "Expected a number, got '-' (line 1, col 7)");
+        assert_eq!(error("* * {\n   x: 1px"), "Expected ';', got end of input (line 2, col 10)");
+        assert_eq!(error("* * { depth: 1px; }"), "Field name must be one of x, y, width or height (line 1, col 7)");
+    }
+}
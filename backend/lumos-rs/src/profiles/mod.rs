@@ -1,11 +1,15 @@
 
-use simple_error::{SimpleResult, try_with};
+use std::collections::HashMap;
+
+use simple_error::{SimpleError, SimpleResult, try_with};
 use crate::common::Rect;
+use crate::render_service::specification::DeviceOverride;
 
-mod window_listener;
+pub mod area_syntax;
+pub mod window_source;
 
 /// Describes a length on some monitor
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MonitorDistance {
     /// An number of pixels
     Pixels(isize),
@@ -17,7 +21,7 @@ pub enum MonitorDistance {
 ///
 /// If `resolution` is not None, it specifies the resolution this area is valid for.
 /// If `resolution` is None, this area is valid for all resolutions.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct MonitorAreaSpecification {
     pub resolution: Option<(usize, usize)>,
     /// Whether this area can be used for [crate::render_service::specification::SamplingType::Horizontal]
@@ -41,10 +45,44 @@ pub struct ApplicationProfile {
     /// The priority of this profile. When multiple profiles are active at the same time (i.e. on different monitors),
     /// the profile with the highest priority (and its monitor) should be used.
     pub priority: i32,
-    /// A regular expressions describing the titles of windows this profile should be active for.
-    pub title_regex: regex::Regex,
+    /// Describes the windows this profile should be active for.
+    pub condition: WindowCondition,
     /// Specifies the monitor region that should be captured when this profile is active.
     pub areas: Vec<MonitorAreaSpecification>,
+    /// Changes to the settings of devices while this profile is active, by device name.
+    pub device_overrides: HashMap<String, DeviceOverride>,
+}
+
+/// A condition on the focused window, which decides whether an [ApplicationProfile] is active.
+#[derive(Debug, Clone)]
+pub enum WindowCondition {
+    /// The window title matches the regular expression.
+    Title(regex::Regex),
+    /// The name of the executable the window belongs to (e.g. `game.exe`) matches the regular expression.
+    ProcessName(regex::Regex),
+    /// The window class matches the regular expression.
+    Class(regex::Regex),
+    /// The window is (or isn't) full-screen.
+    Fullscreen(bool),
+    /// All of the conditions hold.
+    All(Vec<WindowCondition>),
+    /// Any of the conditions holds.
+    Any(Vec<WindowCondition>),
+}
+
+impl WindowCondition {
+    /// Returns whether `window` satisfies this condition. Conditions on metadata the window source doesn't know are
+    /// never satisfied.
+    pub fn matches(&self, window: &window_source::FocusedWindow) -> bool {
+        match self {
+            WindowCondition::Title(regex) => regex.is_match(&window.title),
+            WindowCondition::ProcessName(regex) => window.process_name.as_ref().is_some_and(|name| regex.is_match(name)),
+            WindowCondition::Class(regex) => window.class.as_ref().is_some_and(|class| regex.is_match(class)),
+            WindowCondition::Fullscreen(fullscreen) => window.fullscreen == *fullscreen,
+            WindowCondition::All(conditions) => conditions.iter().all(|condition| condition.matches(window)),
+            WindowCondition::Any(conditions) => conditions.iter().any(|condition| condition.matches(window)),
+        }
+    }
 }
 
 /// Info about the active profile (or lack thereof) for some monitor.
@@ -72,59 +110,201 @@ pub struct ActiveProfile {
 
 pub use listener::*;
 mod listener {
+    use std::time::Duration;
+
+    use log::trace;
+    use tokio::sync::watch;
+    use tokio::time::Instant;
+
     use super::*;
 
     /// Service for listening to changes to the active profiles (i.e. profile activations & deactivations).
     ///
-    /// Profiles are activated when the user focuses an OS window whose title matches the profile's
-    /// [ApplicationProfile::title_regex], and deactivated when a user focuses another window on the same monitor that
-    /// does not match the [ApplicationProfile::title_regex]. Only one profile per monitor can be active at a time.
+    /// Profiles are activated when the user focuses an OS window that satisfies the profile's
+    /// [ApplicationProfile::condition], and deactivated when a user focuses another window on the same monitor that
+    /// does not satisfy the [ApplicationProfile::condition]. Only one profile per monitor can be active at a time.
+    ///
+    /// To avoid flickering when the focus changes only briefly (e.g. when alt-tabbing or when an overlay pops up),
+    /// changes are only reported once the focus has stayed for a grace period, and windows matching the ignore list
+    /// don't change anything.
     pub struct ProfileListener {
-        window_listener: window_listener::FocusedWindowListener,
+        window_source: Box<dyn window_source::FocusedWindowSource>,
         profiles: Vec<ApplicationProfile>,
         monitors: Vec<Rect>,
+        ignored_windows: Vec<WindowCondition>,
+        activation_delay: Duration,
+        deactivation_delay: Duration,
+        /// The id of the profile last reported for each monitor (if any).
+        reported: HashMap<u32, Option<u32>>,
+        /// A change that will be reported once its grace period is over.
+        pending: Option<PendingChange>,
+        /// The last window that was focused, including ignored windows.
+        focus: watch::Sender<Option<window_source::FocusedWindow>>,
+    }
+
+    struct PendingChange {
+        info: ActiveProfileInfo,
+        at: Instant,
+    }
+
+    fn profile_id(info: &ActiveProfileInfo) -> Option<u32> {
+        info.profile.as_ref().map(|active| active.profile.id)
     }
 
     impl ProfileListener {
-        pub async fn new(monitors: Vec<Rect>) -> Self {
+        /// Creates a listener for the windows reported by `window_source` (see [window_source::platform_source]).
+        pub fn new(window_source: Box<dyn window_source::FocusedWindowSource>, monitors: Vec<Rect>) -> Self {
             ProfileListener {
-                window_listener: window_listener::FocusedWindowListener::new(monitors.clone()).await,
+                window_source,
                 profiles: Vec::new(),
                 monitors,
+                ignored_windows: Vec::new(),
+                activation_delay: Duration::ZERO,
+                deactivation_delay: Duration::ZERO,
+                reported: HashMap::new(),
+                pending: None,
+                focus: watch::Sender::new(None),
             }
         }
 
+        /// Returns a receiver for every window that is focused, including ignored ones. Windows are only received while
+        /// [ProfileListener::next] is being polled.
+        pub fn focused_windows(&self) -> watch::Receiver<Option<window_source::FocusedWindow>> {
+            self.focus.subscribe()
+        }
+
         /// Sets the profiles to listen for.
         pub fn set_profiles(&mut self, profiles: Vec<ApplicationProfile>) {
             self.profiles = profiles;
+            // The profiles might have changed, so report them again when their windows are focused
+            self.reported.clear();
+            self.pending = None;
+        }
+
+        /// Sets the windows to ignore, e.g. overlays or the task switcher. Focusing them changes nothing.
+        pub fn set_ignored_windows(&mut self, ignored_windows: Vec<WindowCondition>) {
+            self.ignored_windows = ignored_windows;
+        }
+
+        /// Sets how long a window has to stay focused before the profile it matches is activated, or before the active
+        /// profile is deactivated because the window doesn't match any profile.
+        pub fn set_grace_periods(&mut self, activation_delay: Duration, deactivation_delay: Duration) {
+            self.activation_delay = activation_delay;
+            self.deactivation_delay = deactivation_delay;
+        }
+
+        /// Changes the positions and resolutions of the monitors. Applies to profiles activated from now on.
+        pub fn set_monitors(&mut self, monitors: Vec<Rect>) {
+            self.monitors = monitors;
         }
 
         /// Waits for and returns the next profile activation or deactivation.
+        ///
+        /// This is cancel safe: changes that are waiting for their grace period are kept when the future is dropped.
         pub async fn next(&mut self) -> SimpleResult<ActiveProfileInfo> {
-            let (monitor_index, title) = try_with!(self.window_listener.next().await, "Window listener failed");
-            let monitor_dimensions = {
-                let monitor = self.monitors[monitor_index as usize];
-                (monitor.width, monitor.height)
-            };
+            loop {
+                let pending_at = self.pending.as_ref().map(|pending| pending.at);
+                tokio::select! {
+                    window = self.window_source.next() => {
+                        let window = try_with!(window, "Window source failed");
+                        self.focus.send_replace(Some(window.clone()));
+                        if self.ignored_windows.iter().any(|condition| condition.matches(&window)) {
+                            trace!("Ignoring window '{}'", window.title);
+                            continue;
+                        }
+                        let info = self.match_window(&window)?;
+                        if let Some(info) = self.debounce(info) {
+                            return Ok(info);
+                        }
+                    },
+                    _ = tokio::time::sleep_until(pending_at.unwrap_or_else(Instant::now)), if pending_at.is_some() => {
+                        let info = self.pending.take().unwrap().info;
+                        return Ok(self.report(info));
+                    },
+                }
+            }
+        }
+
+        fn match_window(&self, window: &window_source::FocusedWindow) -> SimpleResult<ActiveProfileInfo> {
+            let monitor_index = self.monitor_of(window)?;
             let matched_profile = self.profiles
                 .iter()
-                .find(|prof| prof.title_regex.is_match(&title))
+                .find(|prof| prof.condition.matches(window))
                 .map(ApplicationProfile::clone);
-
-            let profile_with_region = matched_profile.map(|profile| {
-                ActiveProfile {
-                    // Give the real regions in pixels to capture from.
-                    actual_horizontal_region: profile.match_area_horizontal(monitor_dimensions).map(|area| area.to_pixels(monitor_dimensions)),
-                    actual_vertical_region: profile.match_area_vertical(monitor_dimensions).map(|area| area.to_pixels(monitor_dimensions)),
-                    profile,
-                }
-            });
+            let profile_with_region = matched_profile.map(|profile| self.activate_on(profile, monitor_index));
 
             Ok(ActiveProfileInfo{
                 monitor_index,
                 profile: profile_with_region,
             })
         }
+
+        /// Returns `info` if it should be reported right away, or schedules it to be reported after its grace period.
+        fn debounce(&mut self, info: ActiveProfileInfo) -> Option<ActiveProfileInfo> {
+            let id = profile_id(&info);
+            if self.reported.get(&info.monitor_index).copied().flatten() == id {
+                // The focus went back to where it was before, so nothing changes
+                self.pending = None;
+                return None;
+            }
+            let delay = if id.is_some() { self.activation_delay } else { self.deactivation_delay };
+            if delay.is_zero() {
+                self.pending = None;
+                return Some(self.report(info));
+            }
+            let at = match &self.pending {
+                // Focusing another window with the same outcome (or a title change) doesn't restart the grace period
+                Some(pending) if pending.info.monitor_index == info.monitor_index && profile_id(&pending.info) == id => pending.at,
+                _ => Instant::now() + delay,
+            };
+            self.pending = Some(PendingChange { info, at });
+            None
+        }
+
+        fn report(&mut self, info: ActiveProfileInfo) -> ActiveProfileInfo {
+            self.reported.insert(info.monitor_index, profile_id(&info));
+            info
+        }
+
+        /// Activates the profile with the given id on a monitor, regardless of which window is focused. Fails if there
+        /// is no such profile or monitor.
+        pub fn activate(&self, profile_id: u32, monitor_index: u32) -> SimpleResult<ActiveProfileInfo> {
+            if monitor_index as usize >= self.monitors.len() {
+                return Err(SimpleError::new(format!("There is no monitor {}", monitor_index)));
+            }
+            let profile = self.profiles
+                .iter()
+                .find(|prof| prof.id == profile_id)
+                .ok_or_else(|| SimpleError::new(format!("There is no profile with id {}", profile_id)))?;
+            Ok(ActiveProfileInfo {
+                monitor_index,
+                profile: Some(self.activate_on(profile.clone(), monitor_index)),
+            })
+        }
+
+        /// Finds the monitor that `window` is on, or the first monitor if its position is unknown.
+        fn monitor_of(&self, window: &window_source::FocusedWindow) -> SimpleResult<u32> {
+            let Some((left, top)) = window.position else {
+                return Ok(0);
+            };
+            self.monitors.iter()
+                .position(|mon| left >= mon.left && left < mon.right() && top >= mon.top && top < mon.bottom())
+                .map(|index| index as u32)
+                .ok_or_else(|| SimpleError::new(format!("Couldn't calculate monitor for window '{}' at ({}, {})", window.title, left, top)))
+        }
+
+        fn activate_on(&self, profile: ApplicationProfile, monitor_index: u32) -> ActiveProfile {
+            let monitor_dimensions = {
+                let monitor = self.monitors[monitor_index as usize];
+                (monitor.width, monitor.height)
+            };
+            ActiveProfile {
+                // Give the real regions in pixels to capture from.
+                actual_horizontal_region: profile.match_area_horizontal(monitor_dimensions).map(|area| area.to_pixels(monitor_dimensions)),
+                actual_vertical_region: profile.match_area_vertical(monitor_dimensions).map(|area| area.to_pixels(monitor_dimensions)),
+                profile,
+            }
+        }
     }
 }
 
@@ -170,4 +350,123 @@ impl ApplicationProfile {
         }
         universal_area
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use super::window_source::{FocusedWindow, ScriptedWindowSource};
+
+    fn profile(id: u32, title_regex: &str) -> ApplicationProfile {
+        let full = MonitorDistance::Proportion(1.0);
+        ApplicationProfile {
+            id,
+            priority: 0,
+            condition: WindowCondition::Title(regex::Regex::new(title_regex).unwrap()),
+            areas: vec![MonitorAreaSpecification {
+                resolution: None,
+                is_horizontal: true,
+                is_vertical: false,
+                left: MonitorDistance::Pixels(0),
+                top: MonitorDistance::Proportion(0.1),
+                width: full,
+                height: MonitorDistance::Proportion(0.8),
+            }],
+            device_overrides: HashMap::new(),
+        }
+    }
+
+    fn window(title: &str, position: Option<(isize, isize)>) -> FocusedWindow {
+        FocusedWindow { title: title.to_string(), process_name: None, class: None, fullscreen: false, position }
+    }
+
+    #[test]
+    fn test_condition() {
+        let regex = |regex| regex::Regex::new(regex).unwrap();
+        let game = FocusedWindow {
+            title: "Game - Level 3".to_string(),
+            process_name: Some("game.exe".to_string()),
+            class: Some("UnityWndClass".to_string()),
+            fullscreen: true,
+            position: None,
+        };
+        let launcher = FocusedWindow { fullscreen: false, title: "Game Launcher".to_string(), ..game.clone() };
+        let condition = WindowCondition::All(vec![
+            WindowCondition::Any(vec![
+                WindowCondition::ProcessName(regex("^game\\.exe$")),
+                WindowCondition::Class(regex("^GameClass$")),
+            ]),
+            WindowCondition::Fullscreen(true),
+        ]);
+        assert!(condition.matches(&game));
+        assert!(!condition.matches(&launcher));
+        assert!(!condition.matches(&window("Game - Level 3", None)));
+        assert!(WindowCondition::Title(regex("^Game")).matches(&launcher));
+    }
+
+    #[tokio::test]
+    async fn test_next() {
+        let source = ScriptedWindowSource::new([
+            window("Game", Some((1920, 0))),
+            window("Editor", Some((1920, 100))),
+            window("Game", None),
+            window("Game", Some((-100, 0))),
+        ].map(|window| (Duration::ZERO, window)));
+        let monitors = vec![
+            Rect { left: 0, top: 0, width: 1920, height: 1080 },
+            Rect { left: 1920, top: 0, width: 2560, height: 1440 },
+        ];
+        let mut listener = ProfileListener::new(Box::new(source), monitors);
+        listener.set_profiles(vec![profile(1, "^Game$")]);
+        let focus = listener.focused_windows();
+
+        let info = listener.next().await.unwrap();
+        assert_eq!(focus.borrow().as_ref().map(|window| window.title.as_str()), Some("Game"));
+        assert_eq!(info.monitor_index, 1);
+        let active = info.profile.unwrap();
+        assert_eq!(active.profile.id, 1);
+        assert_eq!(active.actual_horizontal_region, Some(Rect { left: 0, top: 144, width: 2560, height: 1152 }));
+        assert_eq!(active.actual_vertical_region, None);
+
+        let info = listener.next().await.unwrap();
+        assert_eq!(info.monitor_index, 1);
+        assert!(info.profile.is_none());
+
+        // Windows with an unknown position are assumed to be on the first monitor
+        let info = listener.next().await.unwrap();
+        assert_eq!(info.monitor_index, 0);
+        assert_eq!(info.profile.unwrap().actual_horizontal_region, Some(Rect { left: 0, top: 108, width: 1920, height: 864 }));
+
+        assert!(listener.next().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grace_periods() {
+        let ms = Duration::from_millis;
+        let source = ScriptedWindowSource::new([
+            (ms(0), window("Game", None)),
+            // Alt-tabbing away and back quickly changes nothing
+            (ms(1000), window("Browser", None)),
+            (ms(200), window("Game", None)),
+            // Overlays are ignored, even when they stay
+            (ms(100), window("Overlay", None)),
+            (ms(5000), window("Browser", None)),
+            (ms(100), window("Editor", None)),
+        ]);
+        let mut listener = ProfileListener::new(Box::new(source), vec![Rect { left: 0, top: 0, width: 1920, height: 1080 }]);
+        listener.set_profiles(vec![profile(1, "^Game$")]);
+        listener.set_ignored_windows(vec![WindowCondition::Title(regex::Regex::new("^Overlay$").unwrap())]);
+        listener.set_grace_periods(ms(300), ms(1000));
+
+        let start = tokio::time::Instant::now();
+        let info = listener.next().await.unwrap();
+        assert_eq!(info.profile.unwrap().profile.id, 1);
+        assert_eq!(start.elapsed(), ms(300));
+
+        // Focusing another window without a profile doesn't restart the deactivation grace period
+        let info = listener.next().await.unwrap();
+        assert!(info.profile.is_none());
+        assert_eq!(start.elapsed(), ms(1000 + 200 + 100 + 5000 + 1000));
+    }
+}
@@ -0,0 +1,85 @@
+//! Sources of information about the window the user has focused, which profiles are activated for.
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use simple_error::SimpleResult;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::WindowsWindowSource;
+#[cfg(all(unix, not(target_os = "macos")))]
+mod x11;
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use self::x11::X11WindowSource;
+
+/// A window that was focused by the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusedWindow {
+    pub title: String,
+    /// The file name of the executable that owns the window (e.g. `game.exe`), if it could be determined.
+    pub process_name: Option<String>,
+    /// The window class (`WM_CLASS` on X11), if it could be determined.
+    pub class: Option<String>,
+    /// Whether the window covers its whole monitor.
+    pub fullscreen: bool,
+    /// The position of the top left corner of the window's contents on the desktop, or [None] if it isn't known (in
+    /// which case the window is assumed to be on the first monitor).
+    pub position: Option<(isize, isize)>,
+}
+
+/// Reports the windows the user focuses.
+pub trait FocusedWindowSource: Send {
+    /// Waits for and returns the next focused window. Windows may be reported again when they change, e.g. when
+    /// their title changes.
+    fn next(&mut self) -> BoxFuture<'_, SimpleResult<FocusedWindow>>;
+}
+
+/// Creates the source for the windowing system of the current platform.
+pub async fn platform_source() -> SimpleResult<Box<dyn FocusedWindowSource>> {
+    #[cfg(windows)]
+    return Ok(Box::new(WindowsWindowSource::new().await?));
+    #[cfg(all(unix, not(target_os = "macos")))]
+    return Ok(Box::new(X11WindowSource::new()?));
+    #[allow(unreachable_code)]
+    Err(simple_error::SimpleError::new("Focused windows can't be detected on this platform"))
+}
+
+/// A source that never reports any windows, for when focused windows can't be detected.
+pub struct NoWindowSource;
+
+impl FocusedWindowSource for NoWindowSource {
+    fn next(&mut self) -> BoxFuture<'_, SimpleResult<FocusedWindow>> {
+        std::future::pending().boxed()
+    }
+}
+
+/// A source that reports a fixed sequence of windows, each after a delay, and then nothing.
+#[cfg(test)]
+pub struct ScriptedWindowSource {
+    windows: std::collections::VecDeque<(std::time::Duration, FocusedWindow)>,
+    /// When the next window is reported, once it's being waited for
+    next_at: Option<tokio::time::Instant>,
+}
+
+#[cfg(test)]
+impl ScriptedWindowSource {
+    pub fn new(windows: impl IntoIterator<Item = (std::time::Duration, FocusedWindow)>) -> Self {
+        ScriptedWindowSource { windows: windows.into_iter().collect(), next_at: None }
+    }
+}
+
+#[cfg(test)]
+impl FocusedWindowSource for ScriptedWindowSource {
+    fn next(&mut self) -> BoxFuture<'_, SimpleResult<FocusedWindow>> {
+        async move {
+            let Some((delay, _)) = self.windows.front() else {
+                return std::future::pending().await;
+            };
+            // Keep the time when the future is dropped, so the delays add up like they would for a real source
+            let at = *self.next_at.get_or_insert_with(|| tokio::time::Instant::now() + *delay);
+            tokio::time::sleep_until(at).await;
+            self.next_at = None;
+            Ok(self.windows.pop_front().unwrap().1)
+        }.boxed()
+    }
+}
@@ -0,0 +1,125 @@
+use std::mem::MaybeUninit;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::trace;
+use simple_error::{SimpleResult, SimpleError, try_with};
+use tokio::sync::mpsc;
+use windows::core::PWSTR;
+use windows::Win32::UI::WindowsAndMessaging::{GetClassNameA, GetWindowInfo, GetWindowTextA, GetWindowThreadProcessId, WINDOWINFO};
+use windows::Win32::Foundation::{CloseHandle, HWND, RECT};
+use windows::Win32::Graphics::Gdi::{GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONEAREST};
+use windows::Win32::System::Threading::{OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION};
+
+use super::{FocusedWindow, FocusedWindowSource};
+
+
+/// Service for listening to Windows window focus events on the current system.
+pub struct WindowsWindowSource {
+    hook: Option<wineventhook::WindowEventHook>,
+    event_rx: mpsc::UnboundedReceiver<wineventhook::WindowEvent>,
+}
+
+impl WindowsWindowSource {
+    pub async fn new() -> SimpleResult<Self> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let hook = try_with!(wineventhook::WindowEventHook::hook(
+            wineventhook::EventFilter::default().all_processes().all_threads().event(wineventhook::raw_event::SYSTEM_FOREGROUND),
+            event_tx
+        ).await, "Could not hook window events");
+        Ok(WindowsWindowSource {
+            hook: Some(hook),
+            event_rx,
+        })
+    }
+
+    async fn next_window(&mut self) -> SimpleResult<FocusedWindow> {
+        let Some(event) = self.event_rx.recv().await else {
+            return Err(SimpleError::new("Windows event hook was closed prematurely"));
+        };
+        let hwnd = HWND(event.raw.window_handle as isize);
+        let title_str = {
+            let mut title = vec![0u8; 256];
+            unsafe { GetWindowTextA(hwnd, title.as_mut_slice()); }
+            let str = String::from_utf8_lossy(&title).to_string();
+            let nul_index = str.find('\0').unwrap();
+            str[0..nul_index].to_string()
+        };
+        trace!("Window: {}", &title_str);
+
+        let win_info = unsafe {
+            let mut info: MaybeUninit<WINDOWINFO> = MaybeUninit::zeroed();
+            (*info.as_mut_ptr()).cbSize = std::mem::size_of::<WINDOWINFO>() as u32;
+            GetWindowInfo(hwnd, info.as_mut_ptr());
+            info.assume_init()
+        };
+
+        // Minimized windows are moved to this position, they have neither a meaningful position nor cover the monitor
+        let (position, fullscreen) = if win_info.rcClient.left == -32000 && win_info.rcClient.top == -32000 {
+            (None, false)
+        } else {
+            // The client area excludes the title bar and borders, so maximized windows don't count as fullscreen
+            (Some((win_info.rcClient.left as isize, win_info.rcClient.top as isize)), covers_monitor(hwnd, &win_info.rcClient))
+        };
+        Ok(FocusedWindow {
+            title: title_str,
+            process_name: process_name(hwnd),
+            class: class_name(hwnd),
+            fullscreen,
+            position,
+        })
+    }
+}
+
+/// Returns the file name of the executable of the process that owns `hwnd`.
+fn process_name(hwnd: HWND) -> Option<String> {
+    unsafe {
+        let mut process_id = 0u32;
+        GetWindowThreadProcessId(hwnd, &mut process_id);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id).ok()?;
+        let mut path = vec![0u16; 1024];
+        let mut len = path.len() as u32;
+        let success = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(path.as_mut_ptr()), &mut len).as_bool();
+        CloseHandle(process);
+        if !success {
+            return None;
+        }
+        let path = String::from_utf16_lossy(&path[..len as usize]);
+        path.rsplit('\\').next().map(str::to_string)
+    }
+}
+
+fn class_name(hwnd: HWND) -> Option<String> {
+    let mut class = vec![0u8; 256];
+    let len = unsafe { GetClassNameA(hwnd, class.as_mut_slice()) };
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&class[..len as usize]).to_string())
+}
+
+/// Returns whether `client_rect` (the client area of `hwnd`) covers the whole monitor that `hwnd` is on.
+fn covers_monitor(hwnd: HWND, client_rect: &RECT) -> bool {
+    let monitor_rect = unsafe {
+        let mut info = MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+        if !GetMonitorInfoW(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST), &mut info).as_bool() {
+            return false;
+        }
+        info.rcMonitor
+    };
+    client_rect.left <= monitor_rect.left && client_rect.top <= monitor_rect.top &&
+        client_rect.right >= monitor_rect.right && client_rect.bottom >= monitor_rect.bottom
+}
+
+impl FocusedWindowSource for WindowsWindowSource {
+    fn next(&mut self) -> BoxFuture<'_, SimpleResult<FocusedWindow>> {
+        self.next_window().boxed()
+    }
+}
+
+impl Drop for WindowsWindowSource {
+    fn drop(&mut self) {
+        if let Some(hook) = self.hook.take() {
+            tokio::spawn(hook.unhook());
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, trace};
+use simple_error::{SimpleError, SimpleResult, try_with};
+use tokio::sync::mpsc;
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::{FocusedWindow, FocusedWindowSource};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_STATE,
+        _NET_WM_STATE_FULLSCREEN,
+        UTF8_STRING,
+    }
+}
+
+/// Service for listening to window focus events from an X11 server (see `_NET_ACTIVE_WINDOW` in the EWMH spec).
+///
+/// Besides focus changes, changes to the title and position of the focused window are reported as well.
+pub struct X11WindowSource {
+    windows: mpsc::UnboundedReceiver<SimpleResult<FocusedWindow>>,
+}
+
+impl X11WindowSource {
+    /// Connects to the X11 server given by the `DISPLAY` environment variable.
+    pub fn new() -> SimpleResult<Self> {
+        let (conn, screen_num) = try_with!(x11rb::connect(None), "Could not connect to X11 server");
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = try_with!(Atoms::new(&conn).map_err(x11rb::errors::ReplyError::from).and_then(|cookie| cookie.reply()), "Could not get X11 atoms");
+        let attributes = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+        try_with!(conn.change_window_attributes(root, &attributes)
+            .map_err(x11rb::errors::ReplyError::from)
+            .and_then(|cookie| cookie.check()), "Could not listen for X11 events");
+
+        let (windows_tx, windows) = mpsc::unbounded_channel();
+        // x11rb is blocking, so wait for events on a separate thread which exits when the source is dropped
+        std::thread::spawn(move || {
+            let mut watcher = ActiveWindowWatcher { conn, root, atoms, active: None, last: None };
+            if let Err(e) = watcher.run(&windows_tx) {
+                let _ = windows_tx.send(Err(e));
+            }
+        });
+        Ok(X11WindowSource { windows })
+    }
+}
+
+impl FocusedWindowSource for X11WindowSource {
+    fn next(&mut self) -> BoxFuture<'_, SimpleResult<FocusedWindow>> {
+        async move {
+            self.windows.recv().await.unwrap_or_else(|| Err(SimpleError::new("X11 event thread stopped")))
+        }.boxed()
+    }
+}
+
+/// Follows the active window and the properties of that window.
+struct ActiveWindowWatcher {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+    active: Option<Window>,
+    /// The window that was last reported, to skip reporting unchanged windows.
+    last: Option<FocusedWindow>,
+}
+
+impl ActiveWindowWatcher {
+    /// Reports the focused window to `windows` whenever it changes, until `windows` is closed or the connection fails.
+    fn run(&mut self, windows: &mpsc::UnboundedSender<SimpleResult<FocusedWindow>>) -> SimpleResult<()> {
+        self.update_active()?;
+        loop {
+            if let Some(window) = self.focused_window() && self.last.as_ref() != Some(&window) {
+                trace!("Window: {}", window.title);
+                if windows.send(Ok(window.clone())).is_err() {
+                    return Ok(());
+                }
+                self.last = Some(window);
+            }
+
+            let event = try_with!(self.conn.wait_for_event(), "X11 connection failed");
+            match event {
+                Event::PropertyNotify(event) if event.window == self.root && event.atom == self.atoms._NET_ACTIVE_WINDOW => {
+                    self.update_active()?;
+                },
+                // Title and geometry changes of the active window are picked up when it is reported
+                Event::PropertyNotify(_) | Event::ConfigureNotify(_) => {},
+                Event::DestroyNotify(event) if Some(event.window) == self.active => self.active = None,
+                _ => {},
+            }
+        }
+    }
+
+    /// Reads the active window from the root window, and starts listening for changes to it.
+    fn update_active(&mut self) -> SimpleResult<()> {
+        let reply = try_with!(
+            self.conn.get_property(false, self.root, self.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, 0, 1)
+                .map_err(x11rb::errors::ReplyError::from)
+                .and_then(|cookie| cookie.reply()),
+            "Could not get active X11 window"
+        );
+        let active = reply.value32().and_then(|mut values| values.next()).filter(|window| *window != x11rb::NONE);
+        if active == self.active {
+            return Ok(());
+        }
+        debug!("Active X11 window changed to {:?}", active);
+        if let Some(previous) = self.active {
+            // The previous window might not exist anymore, so errors are ignored
+            let _ = self.conn.change_window_attributes(previous, &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT));
+        }
+        if let Some(window) = active {
+            let attributes = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY);
+            let _ = self.conn.change_window_attributes(window, &attributes);
+        }
+        try_with!(self.conn.flush(), "X11 connection failed");
+        self.active = active;
+        Ok(())
+    }
+
+    /// Returns the active window and its metadata, or [None] if there is no active window (anymore).
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        let window = self.active?;
+        let title = self.property_string(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)
+            .filter(|title| !title.is_empty())
+            .or_else(|| self.property_string(window, AtomEnum::WM_NAME.into(), AtomEnum::ANY.into()))?;
+        let position = self.conn.translate_coordinates(window, self.root, 0, 0).ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| (reply.dst_x as isize, reply.dst_y as isize));
+        // WM_CLASS consists of the instance name and the class name, separated by NUL characters
+        let class = self.property_string(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())
+            .and_then(|class| class.split('\0').nth(1).map(str::to_string))
+            .filter(|class| !class.is_empty());
+        let fullscreen = self.property_u32s(window, self.atoms._NET_WM_STATE, AtomEnum::ATOM.into())
+            .is_some_and(|states| states.contains(&self.atoms._NET_WM_STATE_FULLSCREEN));
+        let process_name = self.property_u32s(window, self.atoms._NET_WM_PID, AtomEnum::CARDINAL.into())
+            .and_then(|pid| pid.first().copied())
+            .and_then(process_name);
+        Some(FocusedWindow { title, process_name, class, fullscreen, position })
+    }
+
+    fn property_u32s(&self, window: Window, property: u32, type_: u32) -> Option<Vec<u32>> {
+        let reply = self.conn.get_property(false, window, property, type_, 0, 1024).ok()?.reply().ok()?;
+        let values: Vec<u32> = reply.value32()?.collect();
+        Some(values)
+    }
+
+    fn property_string(&self, window: Window, property: u32, type_: u32) -> Option<String> {
+        let reply = self.conn.get_property(false, window, property, type_, 0, 1024).ok()?.reply().ok()?;
+        Some(String::from_utf8_lossy(&reply.value).into_owned())
+    }
+}
+
+/// Returns the name of the process with the given id. Only works for local processes, and only on Linux.
+fn process_name(pid: u32) -> Option<String> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+    use super::*;
+
+    async fn next(source: &mut X11WindowSource) -> FocusedWindow {
+        tokio::time::timeout(Duration::from_secs(5), source.next()).await.unwrap().unwrap()
+    }
+
+    /// Requires an X11 server, e.g. `xvfb-run cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "requires an X11 server"]
+    async fn test_active_window() {
+        let mut source = X11WindowSource::new().unwrap();
+
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn).unwrap().reply().unwrap();
+        let window = conn.generate_id().unwrap();
+        conn.create_window(0, window, root, 10, 20, 100, 100, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new()).unwrap();
+        conn.change_property8(PropMode::REPLACE, window, atoms._NET_WM_NAME, atoms.UTF8_STRING, "Game ✓".as_bytes()).unwrap();
+        conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"game\0Game\0").unwrap();
+        conn.change_property32(PropMode::REPLACE, window, atoms._NET_WM_STATE, AtomEnum::ATOM, &[atoms._NET_WM_STATE_FULLSCREEN]).unwrap();
+        conn.change_property32(PropMode::REPLACE, window, atoms._NET_WM_PID, AtomEnum::CARDINAL, &[std::process::id()]).unwrap();
+        conn.change_property32(PropMode::REPLACE, root, atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, &[window]).unwrap();
+        conn.flush().unwrap();
+
+        let focused = loop {
+            // Another window might have been active before
+            let focused = next(&mut source).await;
+            if focused.title == "Game ✓" {
+                break focused;
+            }
+        };
+        assert_eq!(focused.position, Some((10, 20)));
+        assert_eq!(focused.class.as_deref(), Some("Game"));
+        assert!(focused.fullscreen);
+        assert!(focused.process_name.is_some());
+
+        conn.change_property8(PropMode::REPLACE, window, atoms._NET_WM_NAME, atoms.UTF8_STRING, b"Game - Paused").unwrap();
+        conn.flush().unwrap();
+        assert_eq!(next(&mut source).await.title, "Game - Paused");
+        conn.destroy_window(window).unwrap();
+        conn.flush().unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use color::RgbF32;
+
+use crate::common::RgbVec;
+
+/// Global adjustments to the output of all devices, which can be changed without restarting the devices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputControl {
+    /// When false, all devices draw black.
+    pub enabled: bool,
+    /// While paused, devices keep showing the last frame they drew.
+    pub paused: bool,
+    /// Multiplies all drawn colors. In [0.0, 1.0].
+    pub brightness: f32,
+    /// Multiplies all drawn colors along with `brightness`, following the time of day (see [crate::scheduler]).
+    pub scheduled_brightness: f32,
+    /// Set while the user is away. All devices draw black, like when not `enabled`.
+    pub idle: bool,
+    /// Drawn instead of the sampled colors while set, even when paused.
+    pub solid_color: Option<SolidColor>,
+    /// How long devices take to crossfade to new colors when their source changes, e.g. when capturing stops or a
+    /// profile is activated.
+    pub transition: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolidColor {
+    pub color: RgbF32,
+    /// When to go back to drawing the sampled colors, or [None] to show the color until it's cleared.
+    pub until: Option<Instant>,
+}
+
+impl Default for OutputControl {
+    fn default() -> Self {
+        OutputControl {
+            enabled: true,
+            paused: false,
+            brightness: 1.0,
+            scheduled_brightness: 1.0,
+            idle: false,
+            solid_color: None,
+            transition: Duration::ZERO,
+        }
+    }
+}
+
+impl OutputControl {
+    /// The solid color to show at `now`, if any.
+    pub fn active_solid_color(&self, now: Instant) -> Option<RgbF32> {
+        self.solid_color
+            .filter(|solid| solid.until.is_none_or(|until| now < until))
+            .map(|solid| solid.color)
+    }
+
+    /// Decides what a device of the given `size` should draw at `now`, given the latest sampled and transformed
+    /// `frame` (if any). Returns [None] if nothing should be drawn.
+    pub fn apply(&self, frame: Option<&RgbVec>, size: usize, now: Instant) -> Option<RgbVec> {
+        if !self.enabled || self.idle {
+            return Some(vec![RgbF32::default(); size]);
+        }
+        let mut colors = match self.active_solid_color(now) {
+            Some(color) => vec![color; size],
+            None if self.paused => return None,
+            None => frame?.clone(),
+        };
+        scale(&mut colors, self.brightness * self.scheduled_brightness);
+        Some(colors)
+    }
+}
+
+/// Multiplies all `colors` by `factor`.
+pub fn scale(colors: &mut RgbVec, factor: f32) {
+    if factor < 1.0 {
+        for color in colors.iter_mut() {
+            color.red *= factor;
+            color.green *= factor;
+            color.blue *= factor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> RgbF32 {
+        RgbF32 { red: value, green: value, blue: value }
+    }
+
+    #[test]
+    fn test_apply() {
+        let now = Instant::now();
+        let frame = vec![gray(0.8)];
+        let mut control = OutputControl::default();
+        assert_eq!(control.apply(Some(&frame), 1, now), Some(frame.clone()));
+        assert_eq!(control.apply(None, 1, now), None);
+
+        control.brightness = 0.5;
+        assert_eq!(control.apply(Some(&frame), 1, now), Some(vec![gray(0.4)]));
+        control.scheduled_brightness = 0.5;
+        assert_eq!(control.apply(Some(&frame), 1, now), Some(vec![gray(0.2)]));
+        control.scheduled_brightness = 1.0;
+
+        // Solid colors are shown even when paused, until they expire
+        control.paused = true;
+        assert_eq!(control.apply(Some(&frame), 1, now), None);
+        control.solid_color = Some(SolidColor { color: gray(1.0), until: Some(now + Duration::from_secs(1)) });
+        assert_eq!(control.apply(Some(&frame), 2, now), Some(vec![gray(0.5); 2]));
+        assert_eq!(control.apply(Some(&frame), 2, now + Duration::from_secs(2)), None);
+
+        control.idle = true;
+        assert_eq!(control.apply(Some(&frame), 1, now), Some(vec![gray(0.0)]));
+        control.idle = false;
+        control.enabled = false;
+        assert_eq!(control.apply(Some(&frame), 1, now), Some(vec![gray(0.0)]));
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use crate::common::RgbVec;
+
+/// Blends from the colors that were shown to new ones when the source of the colors changes, e.g. when capturing stops
+/// or another effect is shown.
+#[derive(Default)]
+pub struct Crossfade {
+    /// The colors that were shown when the transition started, while it is in progress
+    from: Option<RgbVec>,
+    started_at: Option<Instant>,
+    /// The colors last returned by [Crossfade::apply]
+    last: Option<RgbVec>,
+}
+
+impl Crossfade {
+    /// Starts a transition from the colors that were shown last. Does nothing if nothing was shown yet.
+    pub fn start(&mut self, now: Instant) {
+        self.from = self.last.clone();
+        self.started_at = Some(now);
+    }
+
+    /// Whether a transition of the given `duration` is in progress at `now`, so the colors should be drawn even if they
+    /// don't change.
+    pub fn is_active(&self, duration: Duration, now: Instant) -> bool {
+        self.from.is_some() && self.started_at.is_some_and(|started_at| now < started_at + duration)
+    }
+
+    /// Returns `colors`, blended with the colors that were shown when the transition started.
+    pub fn apply(&mut self, colors: Option<&RgbVec>, duration: Duration, now: Instant) -> Option<RgbVec> {
+        let colors = colors?;
+        let progress = match (&self.from, self.started_at) {
+            (Some(from), Some(started_at)) if from.len() == colors.len() && !duration.is_zero() => {
+                (now.saturating_duration_since(started_at).as_secs_f32() / duration.as_secs_f32()).min(1.0)
+            },
+            _ => 1.0,
+        };
+        let blended = match &self.from {
+            Some(from) if progress < 1.0 => from.iter().zip(colors).map(|(from, to)| color::blend(from, to, progress)).collect(),
+            _ => {
+                self.from = None;
+                colors.clone()
+            },
+        };
+        self.last = Some(blended.clone());
+        Some(blended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color::RgbF32;
+
+    fn gray(value: f32) -> RgbF32 {
+        RgbF32 { red: value, green: value, blue: value }
+    }
+
+    #[test]
+    fn test_apply() {
+        let duration = Duration::from_secs(1);
+        let now = Instant::now();
+        let mut crossfade = Crossfade::default();
+        // Nothing was shown yet, so there is nothing to fade from
+        crossfade.start(now);
+        assert_eq!(crossfade.apply(Some(&vec![gray(1.0)]), duration, now), Some(vec![gray(1.0)]));
+        assert!(!crossfade.is_active(duration, now));
+
+        crossfade.start(now);
+        assert!(crossfade.is_active(duration, now));
+        assert_eq!(crossfade.apply(Some(&vec![gray(0.0)]), duration, now + duration / 4), Some(vec![gray(0.75)]));
+        assert_eq!(crossfade.apply(Some(&vec![gray(0.0)]), duration, now + duration / 2), Some(vec![gray(0.5)]));
+        assert_eq!(crossfade.apply(Some(&vec![gray(0.0)]), duration, now + duration), Some(vec![gray(0.0)]));
+        assert!(!crossfade.is_active(duration, now + duration));
+        assert_eq!(crossfade.apply(None, duration, now + duration), None);
+
+        // Without a duration, the colors change right away
+        crossfade.start(now);
+        assert_eq!(crossfade.apply(Some(&vec![gray(1.0)]), Duration::ZERO, now), Some(vec![gray(1.0)]));
+    }
+}
@@ -2,6 +2,7 @@ use color::RgbF32;
 use color::RgbU8;
 use crate::common::Rect;
 use crate::common::RgbVec;
+use super::specification::SamplingType;
 use rayon::prelude::*;
 
 /// A [FrameSampler] is responsible for sampling a captured [desktop_capture::Frame] and reducing it
@@ -11,6 +12,16 @@ pub trait FrameSampler<Params> : Send {
     fn set_params(&mut self, params: Params);
 }
 
+/// Creates a sampler for devices with the given sampling type that samples whole frames, e.g. images from other
+/// programs. Returns [None] for sampling types that need more information.
+pub fn whole_frame_sampler(sampling_type: &SamplingType, size: usize) -> Option<Box<dyn FrameSampler<Rect> + Sync>> {
+    let region = Rect { left: 0, top: 0, width: usize::MAX, height: usize::MAX };
+    match sampling_type {
+        SamplingType::Horizontal => Some(Box::new(HorizontalFrameSampler::new(size, region))),
+        SamplingType::Vertical => Some(Box::new(VerticalFrameSampler::new(size, region))),
+        SamplingType::Ambilight(_) => None,
+    }
+}
 
 /// For an N-sized output buffer, divides each frame into N equally sized regions horizontally. Each region
 /// takes up the entire frame vertically. The output values are equal to the mean RGB values of each region.
@@ -46,12 +57,10 @@ impl FrameSampler<Rect> for HorizontalFrameSampler {
         region.width = region.width.min(frame.width - region.left as usize);
         region.top = region.top.max(0);
         region.height = region.height.min(frame.height - region.top as usize);
-        let section_width = region.width as f64 / self.size as f64;
 
         (0..self.size).into_par_iter().map(|i| {
             let mut sum = color::Rgb{ red: 0u64, green: 0u64, blue: 0u64 };
-            let section_start = region.left + (i as f64 * section_width).ceil() as isize;
-            let section_end = region.left + ((i+1) as f64 * section_width).ceil() as isize;
+            let (section_start, section_end) = section_bounds(i, self.size, region.left, region.width);
             for y in region.top..region.bottom() {
                 for x in section_start..section_end {
                     let val: RgbU8 = frame.buffer[(y * frame.width as isize + x) as usize];
@@ -106,12 +115,10 @@ impl FrameSampler<Rect> for VerticalFrameSampler {
         region.width = region.width.min(frame.width - region.left as usize);
         region.top = region.top.max(0);
         region.height = region.height.min(frame.height - region.top as usize);
-        let section_height = region.height as f64 / self.size as f64;
 
         (0..self.size).into_par_iter().map(|i| {
             let mut section_sum = color::Rgb{ red: 0u64, green: 0u64, blue: 0u64 };
-            let section_start = region.top + (i as f64 * section_height).ceil() as isize;
-            let section_end = region.top + ((i+1) as f64 * section_height).ceil() as isize;
+            let (section_start, section_end) = section_bounds(i, self.size, region.top, region.height);
             for y in section_start..section_end {
 
                 for x in region.left..region.right() {
@@ -132,6 +139,21 @@ impl FrameSampler<Rect> for VerticalFrameSampler {
     }
 }
 
+/// Returns the bounds of section `index`, when dividing the `length` pixels after `start` into `count` sections.
+///
+/// When there are fewer pixels than sections, sections that would be empty get the pixel nearest to their center, so
+/// there is always something to average.
+fn section_bounds(index: usize, count: usize, start: isize, length: usize) -> (isize, isize) {
+    let section_length = length as f64 / count as f64;
+    let section_start = (index as f64 * section_length).ceil() as isize;
+    let section_end = ((index + 1) as f64 * section_length).ceil() as isize;
+    if section_end > section_start || length == 0 {
+        return (start + section_start, start + section_end);
+    }
+    let nearest = (((index as f64 + 0.5) * section_length) as isize).min(length as isize - 1);
+    (start + nearest, start + nearest + 1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -211,6 +233,20 @@ mod tests {
         assert_eq!(result[2], RgbF32{red: 0.0, green: 0.0, blue: 0.0});
     }
 
+    #[test]
+    fn test_small_image() {
+        // Images from other programs can have fewer pixels than the device has LEDs
+        let color1 = RgbU8{red: 255, green: 0, blue: 0};
+        let color2 = RgbU8{red: 0, green: 0, blue: 255};
+        let frame = Frame { width: 2, height: 1, buffer: vec![color1, color2], downscaling: 1 };
+        let red = RgbF32{red: 1.0, green: 0.0, blue: 0.0};
+        let blue = RgbF32{red: 0.0, green: 0.0, blue: 1.0};
+        let sampler = whole_frame_sampler(&SamplingType::Horizontal, 4).unwrap();
+        assert_eq!(sampler.sample(&frame), vec![red, red, blue, blue]);
+        let sampler = whole_frame_sampler(&SamplingType::Vertical, 3).unwrap();
+        assert_eq!(sampler.sample(&frame), vec![RgbF32{red: 0.5, green: 0.0, blue: 0.5}; 3]);
+    }
+
     extern crate test;
 
     #[bench]
@@ -2,27 +2,75 @@
 pub mod specification;
 mod transformations;
 pub mod frame_sampler;
+mod output_thread;
+mod reconnecting_output;
+mod stats;
+mod control;
+mod crossfade;
+pub use reconnecting_output::{OutputState, OutputStateEvent};
+pub use control::{OutputControl, SolidColor};
+pub use stats::DeviceStats;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use desktop_capture::FrameCaptureEvent;
 use log::debug;
 
-use tokio::sync::watch;
+use tokio::sync::{broadcast, mpsc, watch};
 use transformations::BufferStreamTransformation;
 use futures::stream::{ Stream, BoxStream, StreamExt };
+use tokio_stream::wrappers::WatchStream;
 use simple_error::SimpleError;
 
 use crate::common::RgbVec;
 use transformations::color::{to_hsv, to_rgb};
 
+use self::crossfade::Crossfade;
 use self::frame_sampler::FrameSampler;
+use self::output_thread::OutputThread;
+use super::mux::{DeviceInput, SourceMux};
+use self::reconnecting_output::{OutputConnector, ReconnectingOutput};
+
+/// How often animated [specification::DeviceEffect]s are drawn.
+const EFFECT_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+/// How often the last frame is drawn again while paused. Some devices go back to their own effects when they receive no
+/// frames for a while, e.g. WLED after 2 seconds.
+const PAUSED_REDRAW_INTERVAL: Duration = Duration::from_secs(1);
 
 /// A device for which to sample [desktop_capture::Frame]s and render color values.
 /// This struct can be used to drive the entire process of sampling, transforming and drawing to a device.
 ///
-/// `T` is the physical device to draw to, see [RenderOutput].
+/// The output is run on its own thread (see [OutputThread]), so that drawing never blocks the async runtime, and is
+/// reopened whenever it fails (see [ReconnectingOutput]).
 pub struct RenderDevice<'a> {
-    output: Box<dyn RenderOutput + Send>,
+    name: Arc<str>,
+    size: usize,
+    output: OutputThread,
     stream: BoxStream<'a, RgbVec>,
+    previews: broadcast::Sender<DevicePreview>,
+    stats: Arc<DeviceStats>,
+    /// When the frame currently being transformed was sampled
+    sampled_at: Arc<Mutex<Option<Instant>>>,
+    /// Set when the frame currently being transformed comes from another source than the previous one (capturing
+    /// started or stopped, or the capture region changed)
+    source_changed: Arc<AtomicBool>,
+    control: watch::Receiver<OutputControl>,
+    sources: watch::Receiver<SourceMux>,
+    /// Samples images from other sources than the desktop capture
+    image_sampler: Option<Box<dyn FrameSampler<crate::common::Rect> + Sync>>,
+    parameters: watch::Receiver<specification::DeviceParameters>,
+    /// The time base for animated [specification::DeviceEffect]s
+    started_at: Instant,
+}
+
+/// The colors drawn to a device, after all transformations. Sent for every frame while anyone is subscribed.
+#[derive(Debug, Clone)]
+pub struct DevicePreview {
+    /// The [specification::DeviceSpecification::name] of the device.
+    pub device_name: Arc<str>,
+    pub colors: Arc<RgbVec>,
 }
 
 /// An output sink for color values (i.e. [RgbVec]s).
@@ -31,73 +79,233 @@ pub struct RenderDevice<'a> {
 pub trait RenderOutput {
     fn draw(&mut self, buffer: &RgbVec) -> Result<(), SimpleError>;
     fn size(&self) -> usize;
+
+    /// Called when the output will no longer be drawn to, e.g. because the device was removed or the application is
+    /// exiting. Should leave the device in a sensible state, by turning its LEDs off or handing control back to the
+    /// device itself.
+    ///
+    /// By default, draws black.
+    fn shutdown(&mut self) -> Result<(), SimpleError> {
+        self.draw(&vec![color::RgbF32::default(); self.size()])
+    }
 }
 
 
 impl<'a> RenderDevice<'a> {
     /// Creates a new device from the given [specification::DeviceSpecification].
     ///
-    /// When the device is run, it will process frames from the provided stream.
-    pub fn new<Fr, Au, Sa, P>(spec: specification::DeviceSpecification, frame_events: Fr, audio: Au, mut sampler: Sa, mut params: watch::Receiver<P>) -> Self where
+    /// When the device is run, it will process frames from the provided stream. Changes to the connection state of the
+    /// output are sent to `output_states`, and the drawn colors to `previews`. The sampled colors contend with the other
+    /// `sources`, and the colors are adjusted according to `control` before they are drawn.
+    ///
+    /// The [specification::DeviceParameters] of `spec` are ignored; they are taken from `parameters` instead, so they
+    /// can be changed while the device is running.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<Fr, Au, Sa, P>(spec: specification::DeviceSpecification, frame_events: Fr, audio: Au, mut sampler: Sa, mut params: watch::Receiver<P>,
+                              parameters: watch::Receiver<specification::DeviceParameters>, output_states: mpsc::UnboundedSender<OutputStateEvent>,
+                              previews: broadcast::Sender<DevicePreview>, control: watch::Receiver<OutputControl>,
+                              sources: watch::Receiver<SourceMux>) -> Self where
         Fr: Stream<Item = desktop_capture::FrameCaptureEvent> + std::marker::Send + 'a,
         Au: Stream<Item = f32> + std::marker::Send + 'a,
         Sa: FrameSampler<P> + std::marker::Sync + 'a,
         P: Clone + Sync + Send + 'a,
     {
+        let stats = Arc::new(DeviceStats::default());
+        let sampled_at = Arc::new(Mutex::new(None));
+        let source_changed = Arc::new(AtomicBool::new(false));
+
+        // Process the last frame again whenever the parameters change, so the changes show up even when no new frames
+        // arrive (e.g. for the fallback color while capturing is stopped)
+        let parameter_changes = WatchStream::new(parameters.clone()).skip(1).map(|_| None);
+        let frame_events = futures::stream::select(frame_events.map(|event| Some(Arc::new(event))), parameter_changes)
+            .scan(None, |last_event, event| {
+                if event.is_some() {
+                    *last_event = event;
+                }
+                futures::future::ready(Some(last_event.clone()))
+            })
+            .filter_map(futures::future::ready);
+
         // Create a stream of sampled colors
-        let output_size = spec.output.size();
+        let output_size = spec.size;
+        let sampling_stats = stats.clone();
+        let sampling_done = sampled_at.clone();
+        let sampling_parameters = parameters.clone();
+        let sampling_source_changed = source_changed.clone();
+        let mut was_captured = None;
         let mut stream = frame_events.boxed().map(move |event| {
             if let Ok(changed) = params.has_changed() && changed {
                 sampler.set_params(params.borrow_and_update().clone());
+                sampling_source_changed.store(true, Ordering::Relaxed);
+            }
+            let is_captured = matches!(*event, FrameCaptureEvent::Captured(_));
+            if was_captured.is_some_and(|was_captured| was_captured != is_captured) {
+                sampling_source_changed.store(true, Ordering::Relaxed);
             }
-            match event {
+            was_captured = Some(is_captured);
+            let sampling_start = Instant::now();
+            let colors = match &*event {
                 FrameCaptureEvent::Stopped => {
-                    vec![spec.fallback_color; output_size]
+                    vec![sampling_parameters.borrow().fallback_color; output_size]
                 },
                 FrameCaptureEvent::Captured(frame) => {
-                    sampler.sample(&frame)
+                    sampler.sample(frame)
                 }
-            }
+            };
+            let now = Instant::now();
+            DeviceStats::increment(&sampling_stats.samples);
+            DeviceStats::add_duration(&sampling_stats.sampling_nanos, now - sampling_start);
+            *sampling_done.lock().unwrap() = Some(now);
+            colors
         }).boxed();
 
-        // Transform the stream according to the specification
+        // Transform the stream according to the parameters
         {
             // Transformations in HSV color space
             let mut hsv_stream = to_hsv(stream);
-            if let Some(params) = spec.hsv_adjustments {
-                hsv_stream = transformations::color::apply_adjustment(hsv_stream, params.hue, params.value, params.saturation);
-            }
-            if let Some(audio_params) = spec.audio_sampling {
-                let transformation = transformations::audio::AudioIntensityTransformation{
-                    audio: audio.boxed(),
-                    amount: audio_params.amount,
-                };
-                hsv_stream = transformation.transform(hsv_stream);
-            }
+            hsv_stream = transformations::color::apply_adjustment(hsv_stream, parameters.clone());
+            let transformation = transformations::audio::AudioIntensityTransformation{
+                audio: audio.boxed(),
+                params: parameters.clone(),
+            };
+            hsv_stream = transformation.transform(hsv_stream);
             stream = to_rgb(hsv_stream);
         }
 
         if spec.smoothing.is_some() {
             panic!("Not implemented");
         }
-        stream = transformations::color::apply_gamma(stream, spec.gamma);
+        stream = transformations::color::apply_gamma(stream, parameters.clone());
+
+        let image_sampler = frame_sampler::whole_frame_sampler(&spec.sampling_type, output_size);
+        let name: Arc<str> = Arc::from(spec.name.as_str());
+        let output_spec = spec.output;
+        let connector: OutputConnector = Box::new(move || crate::outputs::open_output(&output_spec, output_size));
+        let output = ReconnectingOutput::new(spec.name, output_size, connector, output_states, stats.clone());
 
         RenderDevice{
-            output: spec.output,
+            name,
+            size: output_size,
+            output: OutputThread::spawn(Box::new(output), stats.clone()),
             stream,
+            previews,
+            stats,
+            sampled_at,
+            source_changed,
+            control,
+            sources,
+            image_sampler,
+            parameters,
+            started_at: Instant::now(),
         }
     }
 
-    /// Continuously processes frames and draws them to the output.
+    /// Continuously processes frames and hands them to the output.
     ///
-    /// Runs until the frame stream ends.
+    /// The last frame is drawn again whenever the [OutputControl] changes, so changes apply even when no new frames
+    /// arrive. While the device runs a [specification::DeviceEffect], the effect is drawn instead of the frames (unless
+    /// a [SolidColor] is shown). Sources with a higher priority than the captured colors are drawn instead of them (see
+    /// [SourceMux]). When the source of the colors changes, the device crossfades to the new colors over
+    /// [OutputControl::transition]. Runs until the frame stream ends.
     pub async fn run(&mut self) {
-        while let Some(frame) = self.stream.next().await {
-            let res = self.output.draw(&frame);
-            if let Err(e) = res {
-                log::error!("Failed to draw to device: {}", e);
+        let mut last_frame: Option<RgbVec> = None;
+        let mut control_open = true;
+        let mut sources_open = true;
+        let mut crossfade = Crossfade::default();
+        let mut last_effect = self.parameters.borrow().effect;
+        let mut last_source = None;
+        // The last frame that was drawn, and when, to keep showing it while paused
+        let mut last_drawn: Option<(RgbVec, Instant)> = None;
+        loop {
+            let now = Instant::now();
+            // Temporary solid colors and sources have to be cleared when they expire, even if nothing else happens
+            let solid_color_expiry = self.control.borrow().solid_color
+                .and_then(|solid| solid.until)
+                .filter(|until| *until > now);
+            let expiry = solid_color_expiry.into_iter().chain(self.sources.borrow().next_expiry(now)).min();
+            let animated = self.parameters.borrow().effect.is_some_and(|effect| effect.is_animated())
+                || self.sources.borrow().is_animated(now)
+                || crossfade.is_active(self.control.borrow().transition, now);
+            let paused = self.control.borrow().paused;
+            tokio::select! {
+                frame = self.stream.next() => {
+                    let Some(frame) = frame else { break };
+                    // The transformations run lazily when the stream is polled, so they finish right before the frame
+                    // arrives. Only the first frame produced from each sample is measured.
+                    if let Some(sampled_at) = self.sampled_at.lock().unwrap().take() {
+                        DeviceStats::add_duration(&self.stats.transformation_nanos, sampled_at.elapsed());
+                    }
+                    DeviceStats::increment(&self.stats.frames);
+                    if self.source_changed.swap(false, Ordering::Relaxed) {
+                        crossfade.start(Instant::now());
+                    }
+                    last_frame = Some(frame);
+                },
+                changed = self.control.changed(), if control_open => {
+                    control_open = changed.is_ok();
+                },
+                changed = self.sources.changed(), if sources_open => {
+                    sources_open = changed.is_ok();
+                },
+                _ = tokio::time::sleep_until(expiry.unwrap_or(now).into()), if expiry.is_some() => {},
+                _ = tokio::time::sleep(EFFECT_FRAME_INTERVAL), if animated => {},
+                _ = tokio::time::sleep(PAUSED_REDRAW_INTERVAL), if paused => {},
+            }
+            let control = *self.control.borrow_and_update();
+            let parameters = *self.parameters.borrow();
+            let now = Instant::now();
+            if parameters.effect != last_effect {
+                crossfade.start(now);
+                last_effect = parameters.effect;
+            }
+            let elapsed = self.started_at.elapsed();
+            let effect_frame = parameters.effect.map(|effect| effect.render(self.size, elapsed));
+            let device_input = DeviceInput {
+                name: &self.name,
+                size: self.size,
+                captured: effect_frame.as_ref().or(last_frame.as_ref()),
+                elapsed,
+                image_sampler: self.image_sampler.as_deref(),
+            };
+            let muxed = self.sources.borrow_and_update().render(&device_input, now);
+            let muxed_source = muxed.as_ref().map(|muxed| muxed.source.clone());
+            if muxed_source.is_some() && last_source.is_some() && muxed_source != last_source {
+                crossfade.start(now);
+            }
+            if muxed_source.is_some() {
+                last_source = muxed_source;
+            }
+            let source = crossfade.apply(muxed.map(|muxed| muxed.colors).as_ref(), control.transition, now);
+            if let Some(mut frame) = control.apply(source.as_ref(), self.size, now) {
+                control::scale(&mut frame, if parameters.enabled { parameters.brightness } else { 0.0 });
+                last_drawn = Some((frame.clone(), now));
+                self.draw(frame);
+            } else if control.paused && let Some((frame, drawn_at)) = &mut last_drawn
+                && now.duration_since(*drawn_at) >= PAUSED_REDRAW_INTERVAL {
+                *drawn_at = now;
+                self.draw(frame.clone());
             }
         }
         debug!("Frame stream ended");
     }
+
+    fn draw(&self, frame: RgbVec) {
+        // Avoid copying frames nobody is looking at
+        if self.previews.receiver_count() > 0 {
+            let _ = self.previews.send(DevicePreview {
+                device_name: self.name.clone(),
+                colors: Arc::new(frame.clone()),
+            });
+        }
+        self.output.submit(frame);
+    }
+
+    pub fn stats(&self) -> Arc<DeviceStats> {
+        self.stats.clone()
+    }
+
+    /// Stops the device, waiting for its output to be shut down (see [RenderOutput::shutdown]).
+    pub async fn shutdown(self) {
+        self.output.close().await;
+    }
 }
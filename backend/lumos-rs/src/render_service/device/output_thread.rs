@@ -0,0 +1,185 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+use crate::common::RgbVec;
+use super::RenderOutput;
+use super::stats::DeviceStats;
+
+/// Runs a [RenderOutput] on a dedicated thread.
+///
+/// Drawing to an output usually means blocking I/O (UDP sends, HID writes, serial writes), which should not happen
+/// on the async runtime. Frames are handed to the thread through a single slot: if the output is still drawing when
+/// a new frame is submitted, the frame waiting in the slot (if any) is replaced. A slow or hung output thus never
+/// delays the caller, and never falls behind by more than one frame.
+///
+/// The thread exits when this struct is closed or dropped, after finishing any draw that is in progress and shutting
+/// down the output (see [RenderOutput::shutdown]).
+pub struct OutputThread {
+    slot: Arc<FrameSlot>,
+    worker_thread: Option<std::thread::JoinHandle<()>>,
+    stats: Arc<DeviceStats>,
+}
+
+/// How long [OutputThread::close] waits for the output to shut down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A latest-frame-wins queue with a capacity of one frame.
+struct FrameSlot {
+    state: Mutex<SlotState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct SlotState {
+    /// The queued frame, and when it was submitted
+    frame: Option<(RgbVec, Instant)>,
+    closed: bool,
+}
+
+impl OutputThread {
+    /// Starts the thread. Draws and dropped frames are counted in `stats`.
+    pub fn spawn(mut output: Box<dyn RenderOutput + Send>, stats: Arc<DeviceStats>) -> Self {
+        let slot = Arc::new(FrameSlot {
+            state: Mutex::new(SlotState::default()),
+            changed: Condvar::new(),
+        });
+        let thread_slot = slot.clone();
+        let thread_stats = stats.clone();
+        let handle = std::thread::Builder::new().name("RenderOutput".to_string()).spawn(move || {
+            while let Some((frame, submitted_at)) = thread_slot.take() {
+                if let Err(e) = output.draw(&frame) {
+                    log::error!("Failed to draw to device: {}", e);
+                }
+                DeviceStats::increment(&thread_stats.draws);
+                DeviceStats::add_duration(&thread_stats.draw_latency_nanos, submitted_at.elapsed());
+            }
+            if let Err(e) = output.shutdown() {
+                log::error!("Failed to shut down device: {}", e);
+            }
+            debug!("Output thread stopped");
+        }).unwrap();
+        OutputThread {
+            slot,
+            worker_thread: Some(handle),
+            stats,
+        }
+    }
+
+    /// Stops the thread, and waits (for a limited time) for the output to be shut down.
+    pub async fn close(mut self) {
+        self.slot.close();
+        if let Some(handle) = self.worker_thread.take() {
+            let join = tokio::task::spawn_blocking(move || handle.join());
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, join).await.is_err() {
+                warn!("Timed out waiting for output to shut down");
+            }
+        }
+    }
+
+    /// Queues a frame to be drawn, replacing any queued frame that hasn't been drawn yet. Never waits for the output.
+    pub fn submit(&self, frame: RgbVec) {
+        let mut state = self.slot.state.lock().unwrap();
+        if state.frame.replace((frame, Instant::now())).is_some() {
+            trace!("Output is busy, dropping a frame");
+            DeviceStats::increment(&self.stats.dropped_frames);
+        }
+        self.slot.changed.notify_one();
+    }
+}
+
+impl Drop for OutputThread {
+    fn drop(&mut self) {
+        self.slot.close();
+    }
+}
+
+impl FrameSlot {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_one();
+    }
+
+    /// Waits for and removes the next frame from the slot. Returns [None] when the slot has been closed.
+    fn take(&self) -> Option<(RgbVec, Instant)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(frame) = state.frame.take() {
+                return Some(frame);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use color::RgbF32;
+    use simple_error::SimpleError;
+
+    /// An output that reports each frame it draws, and then blocks until it's allowed to continue.
+    struct GatedOutput {
+        drawn: mpsc::Sender<RgbVec>,
+        gate: mpsc::Receiver<()>,
+    }
+
+    impl RenderOutput for GatedOutput {
+        fn draw(&mut self, buffer: &RgbVec) -> Result<(), SimpleError> {
+            self.drawn.send(buffer.clone()).unwrap();
+            self.gate.recv().map_err(SimpleError::from)
+        }
+        fn size(&self) -> usize {
+            1
+        }
+    }
+
+    fn frame(value: f32) -> RgbVec {
+        vec![RgbF32 { red: value, green: value, blue: value }]
+    }
+
+    #[test]
+    fn test_latest_frame_wins() {
+        let (drawn_tx, drawn_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel();
+        let output = OutputThread::spawn(Box::new(GatedOutput { drawn: drawn_tx, gate: gate_rx }), Arc::default());
+        let timeout = Duration::from_secs(1);
+
+        output.submit(frame(0.1));
+        assert_eq!(drawn_rx.recv_timeout(timeout).unwrap(), frame(0.1));
+        // The output is now blocked, so these should not block, and only the last one should be drawn
+        output.submit(frame(0.2));
+        output.submit(frame(0.3));
+        gate_tx.send(()).unwrap();
+        assert_eq!(drawn_rx.recv_timeout(timeout).unwrap(), frame(0.3));
+        gate_tx.send(()).unwrap();
+        assert_eq!(output.stats.dropped_frames.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        drop(output);
+        // The output draws black when it's shut down
+        assert_eq!(drawn_rx.recv_timeout(timeout).unwrap(), frame(0.0));
+        gate_tx.send(()).unwrap();
+        assert!(drawn_rx.recv_timeout(timeout).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (drawn_tx, drawn_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel();
+        let output = OutputThread::spawn(Box::new(GatedOutput { drawn: drawn_tx, gate: gate_rx }), Arc::default());
+
+        output.submit(frame(0.5));
+        assert_eq!(drawn_rx.recv_timeout(Duration::from_secs(1)).unwrap(), frame(0.5));
+        gate_tx.send(()).unwrap();
+        gate_tx.send(()).unwrap();
+        output.close().await;
+        // Once closed, the output has been shut down
+        assert_eq!(drawn_rx.try_iter().collect::<Vec<_>>(), vec![frame(0.0)]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use simple_error::SimpleError;
+use tokio::sync::mpsc;
+
+use crate::common::RgbVec;
+use super::RenderOutput;
+use super::stats::DeviceStats;
+
+/// The time to wait before the first reconnection attempt. Doubled after each failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether the physical device of an output is currently reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputState {
+    Connected,
+    Disconnected,
+}
+
+/// Sent whenever the [OutputState] of a device changes.
+#[derive(Debug, Clone)]
+pub struct OutputStateEvent {
+    /// The [super::specification::DeviceSpecification::name] of the device.
+    pub device_name: String,
+    pub state: OutputState,
+    /// The error that caused the device to disconnect, if any.
+    pub error: Option<String>,
+}
+
+/// Opens a [RenderOutput], e.g. by enumerating HID devices or serial ports.
+pub type OutputConnector = Box<dyn FnMut() -> Result<Box<dyn RenderOutput + Send>, SimpleError> + Send>;
+
+/// A [RenderOutput] that supervises another output, reopening it when it fails.
+///
+/// When drawing fails (e.g. because the device was unplugged), the output is closed and reopened using its
+/// [OutputConnector], with an exponential backoff between attempts. Frames drawn while disconnected are dropped.
+///
+/// Connecting is blocking, and only happens while drawing, so this should be run on an
+/// [super::output_thread::OutputThread].
+pub struct ReconnectingOutput {
+    name: String,
+    size: usize,
+    connect: OutputConnector,
+    output: Option<Box<dyn RenderOutput + Send>>,
+    state_tx: mpsc::UnboundedSender<OutputStateEvent>,
+    stats: Arc<DeviceStats>,
+    /// The last state sent to `state_tx`.
+    reported_state: Option<OutputState>,
+    next_attempt: Instant,
+    backoff: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl ReconnectingOutput {
+    /// Creates a new output. It is first opened when a frame is drawn.
+    ///
+    /// State changes are sent to `state_tx`, and failed connections and draws are counted in `stats`.
+    pub fn new(name: String, size: usize, connect: OutputConnector, state_tx: mpsc::UnboundedSender<OutputStateEvent>, stats: Arc<DeviceStats>) -> Self {
+        ReconnectingOutput {
+            name,
+            size,
+            connect,
+            output: None,
+            state_tx,
+            stats,
+            reported_state: None,
+            next_attempt: Instant::now(),
+            backoff: MIN_BACKOFF,
+            min_backoff: MIN_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+        }
+    }
+
+    fn try_connect(&mut self) {
+        let connected = (self.connect)().and_then(|output| {
+            if output.size() == self.size {
+                Ok(output)
+            } else {
+                Err(SimpleError::new(format!("Expected an output of size {}, but got size {}", self.size, output.size())))
+            }
+        });
+        match connected {
+            Ok(output) => {
+                info!("Connected to device '{}'", self.name);
+                self.output = Some(output);
+                self.backoff = self.min_backoff;
+                self.set_state(OutputState::Connected, None);
+            },
+            Err(e) => {
+                debug!("Failed to connect to device '{}', retrying in {:?}: {}", self.name, self.backoff, e);
+                DeviceStats::increment(&self.stats.errors);
+                self.schedule_retry();
+                self.set_state(OutputState::Disconnected, Some(e.to_string()));
+            },
+        }
+    }
+
+    /// Schedules the next connection attempt, and increases the backoff for the attempt after that.
+    fn schedule_retry(&mut self) {
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+    }
+
+    /// Reports the state of the output, if it has changed.
+    fn set_state(&mut self, state: OutputState, error: Option<String>) {
+        if self.reported_state == Some(state) {
+            return;
+        }
+        self.reported_state = Some(state);
+        // Nobody may be interested in state changes, so failing to send isn't an error
+        let _ = self.state_tx.send(OutputStateEvent {
+            device_name: self.name.clone(),
+            state,
+            error,
+        });
+    }
+}
+
+impl RenderOutput for ReconnectingOutput {
+    fn draw(&mut self, buffer: &RgbVec) -> Result<(), SimpleError> {
+        if self.output.is_none() && Instant::now() >= self.next_attempt {
+            self.try_connect();
+        }
+        if let Some(output) = self.output.as_mut() && let Err(e) = output.draw(buffer) {
+            warn!("Device '{}' disconnected: {}", self.name, e);
+            DeviceStats::increment(&self.stats.errors);
+            self.output = None;
+            self.schedule_retry();
+            self.set_state(OutputState::Disconnected, Some(e.to_string()));
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn shutdown(&mut self) -> Result<(), SimpleError> {
+        match self.output.as_mut() {
+            Some(output) => output.shutdown(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use color::RgbF32;
+
+    /// An output that fails while `failing` is set.
+    struct FlakyOutput {
+        failing: Arc<Mutex<bool>>,
+    }
+
+    impl RenderOutput for FlakyOutput {
+        fn draw(&mut self, _buffer: &RgbVec) -> Result<(), SimpleError> {
+            if *self.failing.lock().unwrap() {
+                Err(SimpleError::new("Device unplugged"))
+            } else {
+                Ok(())
+            }
+        }
+        fn size(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        // While the device is failing, both drawing and connecting fail
+        let failing = Arc::new(Mutex::new(true));
+        let connect_attempts = Arc::new(Mutex::new(0));
+        let connector: OutputConnector = {
+            let failing = failing.clone();
+            let connect_attempts = connect_attempts.clone();
+            Box::new(move || {
+                *connect_attempts.lock().unwrap() += 1;
+                if *failing.lock().unwrap() {
+                    Err(SimpleError::new("No such device"))
+                } else {
+                    Ok(Box::new(FlakyOutput { failing: failing.clone() }))
+                }
+            })
+        };
+        let (state_tx, mut state_rx) = mpsc::unbounded_channel();
+        let mut output = ReconnectingOutput::new("test".to_string(), 1, connector, state_tx, Arc::default());
+        output.min_backoff = Duration::from_millis(20);
+        output.backoff = output.min_backoff;
+        output.max_backoff = Duration::from_millis(40);
+        let frame = vec![RgbF32::default()];
+
+        // The first connection fails, and the next attempt waits for the backoff
+        output.draw(&frame).unwrap();
+        output.draw(&frame).unwrap();
+        assert_eq!(*connect_attempts.lock().unwrap(), 1);
+        assert_eq!(state_rx.try_recv().unwrap().state, OutputState::Disconnected);
+        std::thread::sleep(Duration::from_millis(30));
+        output.draw(&frame).unwrap();
+        assert_eq!(*connect_attempts.lock().unwrap(), 2);
+        // Repeated failures aren't reported again
+        assert!(state_rx.try_recv().is_err());
+
+        *failing.lock().unwrap() = false;
+        std::thread::sleep(Duration::from_millis(50));
+        output.draw(&frame).unwrap();
+        assert_eq!(*connect_attempts.lock().unwrap(), 3);
+        assert_eq!(state_rx.try_recv().unwrap().state, OutputState::Connected);
+
+        // Unplugging the device is reported once, and it's reconnected when it comes back
+        *failing.lock().unwrap() = true;
+        output.draw(&frame).unwrap();
+        output.draw(&frame).unwrap();
+        let event = state_rx.try_recv().unwrap();
+        assert_eq!(event.state, OutputState::Disconnected);
+        assert_eq!(event.error.as_deref(), Some("Device unplugged"));
+        assert!(state_rx.try_recv().is_err());
+        *failing.lock().unwrap() = false;
+        std::thread::sleep(Duration::from_millis(30));
+        output.draw(&frame).unwrap();
+        assert_eq!(state_rx.try_recv().unwrap().state, OutputState::Connected);
+    }
+}
@@ -1,41 +1,249 @@
+use std::time::Duration;
+
 use color::RgbF32;
 
-use super::RenderOutput;
+use crate::common::RgbVec;
+use crate::outputs::SerialProtocol;
 
 /// A specification from which a [super::RenderDevice] can be created
+#[derive(Clone)]
 pub struct DeviceSpecification {
-    pub output: Box<dyn RenderOutput + Send>,
+    /// A name for the device, used to identify it to the user.
+    pub name: String,
+    /// The number of LEDs (i.e. colors) the device has.
+    pub size: usize,
+    pub output: OutputSpecification,
     pub sampling_type: SamplingType,
     pub hsv_adjustments: Option<HsvAdjustment>,
     pub smoothing: Option<SmoothingParameters>,
     pub audio_sampling: Option<AudioSamplingParameters>,
     pub gamma: f32,
     pub fallback_color: RgbF32,
+    /// Multiplies all drawn colors, on top of [super::OutputControl::brightness]. In [0.0, 1.0].
+    pub brightness: f32,
+    /// When false, the device draws black.
+    pub enabled: bool,
+    /// Drawn instead of the sampled colors while set.
+    pub effect: Option<DeviceEffect>,
+}
+
+/// The parts of a [DeviceSpecification] that can be changed while the device is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceParameters {
+    pub hsv_adjustments: Option<HsvAdjustment>,
+    pub audio_sampling: Option<AudioSamplingParameters>,
+    pub gamma: f32,
+    pub fallback_color: RgbF32,
+    pub brightness: f32,
+    pub enabled: bool,
+    pub effect: Option<DeviceEffect>,
+}
+
+/// Changes to the settings of a device, e.g. while some [crate::profiles::ApplicationProfile] is active. Settings that
+/// are [None] are left as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceOverride {
+    pub enabled: Option<bool>,
+    pub brightness: Option<f32>,
+    /// Replaces [HsvAdjustment::hue].
+    pub hue: Option<f32>,
+    /// Replaces [HsvAdjustment::saturation].
+    pub saturation: Option<f32>,
+    /// Replaces [HsvAdjustment::value].
+    pub value: Option<f32>,
+    /// Replaces [AudioSamplingParameters::amount]. An amount of 0 turns audio sampling off.
+    pub audio_amount: Option<f32>,
+    pub gamma: Option<f32>,
+    /// Changing the sampling type restarts the device.
+    pub sampling_type: Option<SamplingType>,
+    pub effect: Option<DeviceEffect>,
+}
+
+/// Colors a device draws by itself, rather than sampling them from the desktop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceEffect {
+    /// Shows a single color.
+    SolidColor(RgbF32),
+    /// Fades a color in and out, taking `period` to go from black to the color and back.
+    Breathing {
+        color: RgbF32,
+        period: Duration,
+    },
+}
+
+impl DeviceEffect {
+    /// Whether the colors of this effect change over time, so that it has to be drawn continuously.
+    pub fn is_animated(&self) -> bool {
+        matches!(self, DeviceEffect::Breathing { .. })
+    }
+
+    /// Returns the colors of this effect for a device of the given `size`, `elapsed` after the effect started.
+    pub fn render(&self, size: usize, elapsed: Duration) -> RgbVec {
+        let color = match *self {
+            DeviceEffect::SolidColor(color) => color,
+            DeviceEffect::Breathing { color, period } => {
+                let phase = elapsed.as_secs_f32() / period.as_secs_f32() * std::f32::consts::TAU;
+                let intensity = (1.0 - phase.cos()) / 2.0;
+                RgbF32 { red: color.red * intensity, green: color.green * intensity, blue: color.blue * intensity }
+            },
+        };
+        vec![color; size]
+    }
+}
+
+impl DeviceSpecification {
+    pub fn parameters(&self) -> DeviceParameters {
+        DeviceParameters {
+            hsv_adjustments: self.hsv_adjustments,
+            audio_sampling: self.audio_sampling,
+            gamma: self.gamma,
+            fallback_color: self.fallback_color,
+            brightness: self.brightness,
+            enabled: self.enabled,
+            effect: self.effect,
+        }
+    }
+
+    /// Returns a copy of `self` with the settings of `device_override` applied.
+    pub fn with_override(&self, device_override: &DeviceOverride) -> DeviceSpecification {
+        let mut spec = self.clone();
+        if let Some(enabled) = device_override.enabled {
+            spec.enabled = enabled;
+        }
+        if let Some(brightness) = device_override.brightness {
+            spec.brightness = brightness;
+        }
+        if device_override.hue.is_some() || device_override.saturation.is_some() || device_override.value.is_some() {
+            let adjustment = self.hsv_adjustments.unwrap_or(HsvAdjustment { hue: 0.0, saturation: 0.0, value: 0.0 });
+            spec.hsv_adjustments = Some(HsvAdjustment {
+                hue: device_override.hue.unwrap_or(adjustment.hue),
+                saturation: device_override.saturation.unwrap_or(adjustment.saturation),
+                value: device_override.value.unwrap_or(adjustment.value),
+            });
+        }
+        if let Some(amount) = device_override.audio_amount {
+            spec.audio_sampling = (amount > 0.0).then_some(AudioSamplingParameters { amount });
+        }
+        if let Some(gamma) = device_override.gamma {
+            spec.gamma = gamma;
+        }
+        if let Some(sampling_type) = &device_override.sampling_type {
+            spec.sampling_type = sampling_type.clone();
+        }
+        if device_override.effect.is_some() {
+            spec.effect = device_override.effect;
+        }
+        spec
+    }
+
+    /// Whether a device created from `self` has to be restarted to apply `other`, i.e. whether they differ in anything
+    /// but their [DeviceParameters].
+    pub fn requires_restart(&self, other: &DeviceSpecification) -> bool {
+        self.name != other.name
+            || self.size != other.size
+            || self.output != other.output
+            || self.sampling_type != other.sampling_type
+            || self.smoothing != other.smoothing
+    }
+}
+
+/// Describes the physical device that a [super::RenderDevice] draws to, and how to find it.
+///
+/// The device is (re)opened from this whenever it isn't connected, see [crate::outputs::open_output].
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputSpecification {
+    Wled {
+        address: String,
+        port: u32,
+    },
+    Qmk {
+        vendor_id: u16,
+        product_id: u16,
+    },
+    Serial {
+        port_name: String,
+        baud_rate: u32,
+        protocol: SerialProtocol,
+    },
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct AmbilightSamplingParameters {
     // TODO: add more parameters
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum SamplingType {
     Horizontal,
     Vertical,
     Ambilight(AmbilightSamplingParameters),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct HsvAdjustment {
     pub hue: f32,
     pub saturation: f32,
     pub value: f32,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct SmoothingParameters {
     output_fps: u32,
     // TODO: what this parameter means isn't defined yet
     amount: f32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AudioSamplingParameters {
     pub amount: f32,
     // TODO: add more parameters as necessary
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_override() {
+        let spec = DeviceSpecification {
+            name: "Desk".to_string(),
+            size: 10,
+            output: OutputSpecification::Wled { address: "127.0.0.1".to_string(), port: 21324 },
+            sampling_type: SamplingType::Horizontal,
+            hsv_adjustments: Some(HsvAdjustment { hue: 0.0, saturation: 0.2, value: 0.1 }),
+            smoothing: None,
+            audio_sampling: Some(AudioSamplingParameters { amount: 0.5 }),
+            gamma: 2.2,
+            fallback_color: RgbF32::default(),
+            brightness: 1.0,
+            enabled: true,
+            effect: None,
+        };
+        let overridden = spec.with_override(&DeviceOverride {
+            saturation: Some(-0.5),
+            audio_amount: Some(0.0),
+            brightness: Some(0.3),
+            ..Default::default()
+        });
+        assert_eq!(overridden.hsv_adjustments, Some(HsvAdjustment { hue: 0.0, saturation: -0.5, value: 0.1 }));
+        assert_eq!(overridden.audio_sampling, None);
+        assert_eq!(overridden.brightness, 0.3);
+        assert_eq!(overridden.gamma, 2.2);
+        assert!(!spec.requires_restart(&overridden));
+
+        let overridden = spec.with_override(&DeviceOverride { sampling_type: Some(SamplingType::Vertical), ..Default::default() });
+        assert!(spec.requires_restart(&overridden));
+    }
+
+    #[test]
+    fn test_effect_render() {
+        let color = RgbF32 { red: 1.0, green: 0.5, blue: 0.0 };
+        assert_eq!(DeviceEffect::SolidColor(color).render(3, Duration::from_secs(5)), vec![color; 3]);
+
+        let breathing = DeviceEffect::Breathing { color, period: Duration::from_secs(2) };
+        assert!(breathing.is_animated());
+        assert_eq!(breathing.render(1, Duration::ZERO), vec![RgbF32::default()]);
+        let peak = breathing.render(1, Duration::from_secs(1))[0];
+        assert!((peak.red - 1.0).abs() < 1e-5 && (peak.green - 0.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters describing the work done by a [super::RenderDevice] since it was created.
+///
+/// Durations are totals in nanoseconds; divide by the matching count to get an average.
+#[derive(Debug, Default)]
+pub struct DeviceStats {
+    /// The number of frames sampled from the desktop.
+    pub samples: AtomicU64,
+    pub sampling_nanos: AtomicU64,
+    /// The total time from sampling frames until they were transformed. For devices using audio, this includes waiting
+    /// for audio values, since output is produced when those arrive.
+    pub transformation_nanos: AtomicU64,
+    /// The number of transformed frames handed to the output.
+    pub frames: AtomicU64,
+    /// The number of frames drawn to the output.
+    pub draws: AtomicU64,
+    /// The total time from handing frames to the output until they were drawn.
+    pub draw_latency_nanos: AtomicU64,
+    /// The number of frames that were replaced by newer ones before the output could draw them.
+    pub dropped_frames: AtomicU64,
+    /// The number of failed attempts to connect to or draw to the output.
+    pub errors: AtomicU64,
+}
+
+impl DeviceStats {
+    pub(super) fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn add_duration(counter: &AtomicU64, duration: Duration) {
+        counter.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
@@ -2,8 +2,11 @@ use core::task::{Poll, Context};
 
 use color::HsvF32;
 use futures::stream::{BoxStream, StreamExt, Stream};
+use tokio::sync::watch;
 use crate::common::HsvVec;
 
+use super::super::specification::DeviceParameters;
+
 use super::HsvBufferStream;
 
 /// A [super::BufferStreamTransformation] which takes a stream of [HsvVec]s and a stream of
@@ -17,13 +20,15 @@ pub struct AudioIntensityTransformation<'a> {
     /// The stream of intensity values to apply to received [HsvVec]s.
     /// The values should fit in a [0.0, 1.0] range.
     pub audio: BoxStream<'a, f32>,
-    /// How much the audio intensity affects the brightness of output colors.
+    /// Determines how much the audio intensity affects the brightness of output colors, through
+    /// [DeviceParameters::audio_sampling].
     ///
-    /// At 0.0, the output brightness is always the same as the input brightness,
-    /// and at 1.0 the output brightness ranges between 0 and the input brightness.
+    /// At an amount of 0.0 (or without audio sampling), the output brightness is always the same as the input
+    /// brightness and buffers are forwarded as soon as they are received. At 1.0 the output brightness ranges between
+    /// 0 and the input brightness.
     ///
-    /// Valid values are [0.0, 1.0].
-    pub amount: f32,
+    /// Valid amounts are [0.0, 1.0].
+    pub params: watch::Receiver<DeviceParameters>,
 }
 
 impl<'a> super::BufferStreamTransformation<'a, HsvF32, HsvF32> for AudioIntensityTransformation<'a> {
@@ -32,7 +37,7 @@ impl<'a> super::BufferStreamTransformation<'a, HsvF32, HsvF32> for AudioIntensit
             audio: Some(self.audio),
             buffers: input,
             last_buffer: None,
-            amount: self.amount
+            params: self.params,
         }).boxed()
     }
 }
@@ -46,15 +51,22 @@ struct AudioIntensityCombiner<'a> {
     ///
     /// When we receive an audio intensity value, it is applied to this buffer and the result is sent as output.
     last_buffer: Option<HsvVec>,
-    /// See [AudioIntensityTransformation::amount].
-    amount: f32,
+    /// See [AudioIntensityTransformation::params].
+    params: watch::Receiver<DeviceParameters>,
+}
+
+impl AudioIntensityCombiner<'_> {
+    fn amount(&self) -> f32 {
+        self.params.borrow().audio_sampling.map_or(0.0, |audio| audio.amount)
+    }
 }
 
 impl<'a> Stream for AudioIntensityCombiner<'a> {
     type Item = HsvVec;
 
     fn poll_next(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.audio.is_some() {
+        let amount = self.amount();
+        if self.audio.is_some() && amount > 0.0 {
             // Poll for a color buffer, storing it for later use if available
             if let Poll::Ready(buffer) = self.buffers.poll_next_unpin(cx) {
                 match buffer {
@@ -72,7 +84,7 @@ impl<'a> Stream for AudioIntensityCombiner<'a> {
                         // Apply the intensity value to the last received buffer and forward it.
                         Some(intensity_value) => {
                             for color in buffer.iter_mut() {
-                                color.value *= intensity_value * self.amount + (1.0 - self.amount);
+                                color.value *= intensity_value * amount + (1.0 - amount);
                             }
                             return Poll::Ready(Some(buffer));
                         },
@@ -83,9 +95,14 @@ impl<'a> Stream for AudioIntensityCombiner<'a> {
             }
             // Neither stream has data, we are pending until one of the streams wakes us
             return Poll::Pending;
-        } else { // audio_stream is None
-            // Audio stream has been closed, just forward buffers instead
-            return self.buffers.poll_next_unpin(cx);
+        } else {
+            // Audio is disabled or the audio stream has been closed, just forward buffers instead. The last buffer is
+            // still kept, so audio can be applied to it as soon as it is enabled.
+            let buffer = self.buffers.poll_next_unpin(cx);
+            if let Poll::Ready(Some(buf)) = &buffer {
+                self.last_buffer = Some(buf.clone());
+            }
+            return buffer;
         }
     }
 }
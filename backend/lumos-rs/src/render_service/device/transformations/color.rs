@@ -1,14 +1,18 @@
 
-/// Applies HSV-adjustments to all color values in a stream
+use tokio::sync::watch;
+
+use super::super::specification::DeviceParameters;
+
+/// Applies the [DeviceParameters::hsv_adjustments] to all color values in a stream
 ///
 /// The new hue and value are clamped to [0.0, 1.0]
 ///
-/// * `stream`     - The stream of buffers to transform
-/// * `hue`        - The value to increment the hue by
-/// * `value`      - The value to increment the value by
-/// * `saturation` - The value to increment the saturation by
-pub fn apply_adjustment(stream: super::HsvBufferStream, hue: f32, value: f32, saturation: f32) -> super::HsvBufferStream {
+/// * `stream` - The stream of buffers to transform
+/// * `params` - The parameters to use for each buffer. Changes apply from the next buffer on.
+pub fn apply_adjustment(stream: super::HsvBufferStream, params: watch::Receiver<DeviceParameters>) -> super::HsvBufferStream {
     super::map(stream, move |mut buffer| {
+        let Some(adjustment) = params.borrow().hsv_adjustments else { return buffer };
+        let (hue, value, saturation) = (adjustment.hue, adjustment.value, adjustment.saturation);
         if hue == 0.0 && value == 0.0 && saturation == 0.0 {
             return buffer;
         }
@@ -25,9 +29,10 @@ pub fn apply_adjustment(stream: super::HsvBufferStream, hue: f32, value: f32, sa
     })
 }
 
-/// Applies a gamma value to all color values in a buffer
-pub fn apply_gamma(stream: super::RgbBufferStream, gamma: f32) -> super::RgbBufferStream {
+/// Applies the [DeviceParameters::gamma] to all color values in a stream
+pub fn apply_gamma(stream: super::RgbBufferStream, params: watch::Receiver<DeviceParameters>) -> super::RgbBufferStream {
     super::map(stream, move |mut buffer| {
+        let gamma = params.borrow().gamma;
         for color in &mut buffer {
             color.red   = color.red.powf(gamma);
             color.green = color.green.powf(gamma);
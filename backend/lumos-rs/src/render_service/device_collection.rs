@@ -1,73 +1,245 @@
 
-use log::debug;
+use std::sync::Arc;
+
+use log::{debug, info};
 use tokio::task::JoinHandle;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio_stream::wrappers::WatchStream;
+use tokio_util::sync::CancellationToken;
 
 use crate::common::Rect;
 
-use super::device::{RenderDevice, frame_sampler};
+use super::device::{RenderDevice, DevicePreview, DeviceStats, OutputControl, OutputStateEvent, frame_sampler};
+use super::mux::SourceMux;
 use super::DeviceSpecification;
-use super::specification::SamplingType;
+use super::specification::{DeviceParameters, SamplingType};
 
 
 /// A set of [RenderDevice]s that are run together.
 pub struct DeviceCollection {
-    device_tasks: Vec<tokio::task::JoinHandle<()>>,
+    devices: Vec<RunningDevice>,
+    cancel_token: CancellationToken,
     hor_samplers_region: watch::Sender<Rect>,
     ver_samplers_region: watch::Sender<Rect>,
+    inputs: DeviceInputs,
+}
+
+/// A device that was started from `spec`.
+struct RunningDevice {
+    spec: DeviceSpecification,
+    parameters: watch::Sender<DeviceParameters>,
+    stats: Arc<DeviceStats>,
+    task: JoinHandle<()>,
+    cancel_token: CancellationToken,
+}
+
+/// What all devices are connected to.
+struct DeviceInputs {
+    frames: watch::Receiver<desktop_capture::FrameCaptureEvent>,
+    audio: watch::Receiver<f32>,
+    output_states: mpsc::UnboundedSender<OutputStateEvent>,
+    previews: broadcast::Sender<DevicePreview>,
+    control: watch::Receiver<OutputControl>,
+    sources: watch::Receiver<SourceMux>,
 }
 
 impl DeviceCollection {
     /// Creates a new [DeviceCollection] from a set of devices.
     ///
-    /// The devices are started when this function is called, and are run until the [DeviceCollection] is dropped.
-    pub fn new(devices: Vec<DeviceSpecification>, frames: &watch::Receiver<desktop_capture::FrameCaptureEvent>, audio: &watch::Receiver<f32>) -> Self where
+    /// The devices are started when this function is called, and are run until the [DeviceCollection] is shut down or
+    /// dropped. Changes to the connection states of the devices are sent to `output_states`, and the colors they draw to
+    /// `previews`. All devices follow the same `control` and `sources`.
+    pub fn new(devices: Vec<DeviceSpecification>, frames: &watch::Receiver<desktop_capture::FrameCaptureEvent>, audio: &watch::Receiver<f32>,
+               output_states: &mpsc::UnboundedSender<OutputStateEvent>, previews: &broadcast::Sender<DevicePreview>,
+               control: &watch::Receiver<OutputControl>, sources: &watch::Receiver<SourceMux>) -> Self where
     {
-        let (hor_region_tx, hor_region_rx) = watch::channel(Rect { left: 0, top: 0, width: usize::max_value(), height: usize::max_value()});
-        let (ver_region_tx, ver_region_rx) = watch::channel(Rect { left: 0, top: 0, width: usize::max_value(), height: usize::max_value()});
-        let tasks: Vec<JoinHandle<()>> = devices.into_iter()
-            .map(|spec| -> JoinHandle<()> {
-                match spec.sampling_type {
-                    SamplingType::Horizontal => {
-                        let sampler = frame_sampler::HorizontalFrameSampler::new(spec.output.size(), Rect { left: 0, top: 0, width: usize::max_value(), height: usize::max_value()});
-                        let mut device = RenderDevice::new(spec, WatchStream::new(frames.clone()), WatchStream::new(audio.clone()), sampler, hor_region_rx.clone());
-                        tokio::spawn(async move { device.run().await })
-                    },
-                    SamplingType::Vertical => {
-                        let sampler = frame_sampler::VerticalFrameSampler::new(spec.output.size(), Rect { left: 0, top: 0, width: usize::max_value(), height: usize::max_value()});
-                        let mut device = RenderDevice::new(spec, WatchStream::new(frames.clone()), WatchStream::new(audio.clone()), sampler, ver_region_rx.clone());
-                        tokio::spawn(async move { device.run().await })
-                    },
-                    SamplingType::Ambilight(_) => unimplemented!(),
-                }
-            }).collect();
-        DeviceCollection {
-            device_tasks: tasks,
-            hor_samplers_region: hor_region_tx,
-            ver_samplers_region: ver_region_tx,
+        let mut collection = DeviceCollection {
+            devices: Vec::new(),
+            cancel_token: CancellationToken::new(),
+            hor_samplers_region: watch::Sender::new(Rect { left: 0, top: 0, width: usize::MAX, height: usize::MAX }),
+            ver_samplers_region: watch::Sender::new(Rect { left: 0, top: 0, width: usize::MAX, height: usize::MAX }),
+            inputs: DeviceInputs {
+                frames: frames.clone(),
+                audio: audio.clone(),
+                output_states: output_states.clone(),
+                previews: previews.clone(),
+                control: control.clone(),
+                sources: sources.clone(),
+            },
+        };
+        collection.devices = devices.into_iter().map(|spec| collection.start_device(spec)).collect();
+        collection
+    }
+
+    /// Replaces the running devices with `devices`, which are identified by their names.
+    ///
+    /// Devices that differ only in their [DeviceParameters] keep running, and the new parameters are applied to them.
+    /// Other devices are shut down (see [super::RenderOutput::shutdown]) before any new devices are started, so that
+    /// their outputs can be reopened.
+    ///
+    /// Returns the names of the devices that were started. Devices that kept running don't report their
+    /// [OutputStateEvent] again.
+    pub async fn update(&mut self, devices: Vec<DeviceSpecification>) -> Vec<String> {
+        let mut previous = std::mem::take(&mut self.devices);
+        let mut kept: Vec<Option<RunningDevice>> = devices.iter()
+            .map(|spec| {
+                let index = previous.iter().position(|running| !running.spec.requires_restart(spec))?;
+                Some(previous.remove(index))
+            })
+            .collect();
+
+        if !previous.is_empty() {
+            info!("Stopping {} device(s)", previous.len());
+        }
+        for device in previous {
+            device.cancel_token.cancel();
+            if let Err(e) = device.task.await {
+                log::error!("Device task failed: {}", e);
+            }
+        }
+
+        let mut started = Vec::new();
+        for (spec, running) in devices.into_iter().zip(kept.iter_mut()) {
+            let device = match running.take() {
+                Some(mut device) => {
+                    let parameters = spec.parameters();
+                    if device.parameters.send_if_modified(|current| std::mem::replace(current, parameters) != parameters) {
+                        debug!("Updated parameters of device '{}'", spec.name);
+                    }
+                    device.spec = spec;
+                    device
+                },
+                None => {
+                    info!("Starting device '{}'", spec.name);
+                    started.push(spec.name.clone());
+                    self.start_device(spec)
+                },
+            };
+            self.devices.push(device);
+        }
+        started
+    }
+
+    /// Creates a device from `spec` and starts running it.
+    fn start_device(&self, spec: DeviceSpecification) -> RunningDevice {
+        let (parameters_tx, parameters_rx) = watch::channel(spec.parameters());
+        let inputs = &self.inputs;
+        let device = match spec.sampling_type {
+            SamplingType::Horizontal => {
+                let sampler = frame_sampler::HorizontalFrameSampler::new(spec.size, *self.hor_samplers_region.borrow());
+                RenderDevice::new(spec.clone(), WatchStream::new(inputs.frames.clone()), WatchStream::new(inputs.audio.clone()), sampler,
+                    self.hor_samplers_region.subscribe(), parameters_rx, inputs.output_states.clone(), inputs.previews.clone(), inputs.control.clone(),
+                    inputs.sources.clone())
+            },
+            SamplingType::Vertical => {
+                let sampler = frame_sampler::VerticalFrameSampler::new(spec.size, *self.ver_samplers_region.borrow());
+                RenderDevice::new(spec.clone(), WatchStream::new(inputs.frames.clone()), WatchStream::new(inputs.audio.clone()), sampler,
+                    self.ver_samplers_region.subscribe(), parameters_rx, inputs.output_states.clone(), inputs.previews.clone(), inputs.control.clone(),
+                    inputs.sources.clone())
+            },
+            SamplingType::Ambilight(_) => unimplemented!(),
+        };
+        let stats = device.stats();
+        let cancel_token = self.cancel_token.child_token();
+        RunningDevice {
+            spec,
+            parameters: parameters_tx,
+            stats,
+            task: spawn_device(device, cancel_token.clone()),
+            cancel_token,
         }
     }
 
     /// Sets the desktop capture region to use for horizontally sampling devices (e.g. those using [super::specification::SamplingType::Horizontal])
     pub fn set_horizontal_region(&self, region: Rect) {
-        if self.hor_samplers_region.send(region).is_err() {
-            log::trace!("Failed to set horizontal sampling region: all receivers have closed.");
-        }
+        self.hor_samplers_region.send_replace(region);
     }
     /// Sets the desktop capture region to use for vertically sampling devices (e.g. those using [super::specification::SamplingType::Vertical])
     pub fn set_vertical_region(&self, region: Rect) {
-        if self.ver_samplers_region.send(region).is_err() {
-            log::trace!("Failed to set vertical sampling region: all receivers have closed.");
+        self.ver_samplers_region.send_replace(region);
+    }
+
+    /// Returns the name and stats of each device.
+    pub fn device_stats(&self) -> impl Iterator<Item = (&String, &Arc<DeviceStats>)> {
+        self.devices.iter().map(|device| (&device.spec.name, &device.stats))
+    }
+
+    /// Stops all devices, and waits for their outputs to be shut down (see [super::RenderOutput::shutdown]).
+    pub async fn shutdown(mut self) {
+        debug!("Stopping {} running device(s)", self.devices.len());
+        self.cancel_token.cancel();
+        for device in self.devices.drain(..) {
+            if let Err(e) = device.task.await {
+                log::error!("Device task failed: {}", e);
+            }
         }
     }
 }
 
 impl Drop for DeviceCollection {
     fn drop(&mut self) {
-        debug!("Aborting {} running device(s)", self.device_tasks.len());
-        for task in &self.device_tasks {
-            task.abort();
+        // The devices shut down by themselves, without anyone waiting for them
+        self.cancel_token.cancel();
+    }
+}
+
+/// Runs `device` until `cancel_token` is cancelled (or its frame stream ends), and then shuts it down.
+fn spawn_device(mut device: RenderDevice<'static>, cancel_token: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = device.run() => {},
+            _ = cancel_token.cancelled() => {},
         }
+        device.shutdown().await;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_service::specification::OutputSpecification;
+
+    fn device(name: &str, port: u32, gamma: f32) -> DeviceSpecification {
+        DeviceSpecification {
+            name: name.to_string(),
+            size: 10,
+            output: OutputSpecification::Wled { address: "127.0.0.1".to_string(), port },
+            sampling_type: SamplingType::Horizontal,
+            hsv_adjustments: None,
+            smoothing: None,
+            audio_sampling: None,
+            gamma,
+            fallback_color: color::RgbF32::default(),
+            brightness: 1.0,
+            enabled: true,
+            effect: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let (_frames_tx, frames) = watch::channel(desktop_capture::FrameCaptureEvent::Stopped);
+        let (_audio_tx, audio) = watch::channel(0.0);
+        let (output_states, _output_states_rx) = mpsc::unbounded_channel();
+        let (previews, _) = broadcast::channel(1);
+        let (_control_tx, control) = watch::channel(OutputControl::default());
+        let (_sources_tx, sources) = watch::channel(SourceMux::default());
+        let mut collection = DeviceCollection::new(vec![device("Desk", 21324, 1.0), device("Shelf", 21325, 1.0)],
+            &frames, &audio, &output_states, &previews, &control, &sources);
+        let stats = |collection: &DeviceCollection| collection.devices.iter().map(|device| device.stats.clone()).collect::<Vec<_>>();
+        let before = stats(&collection);
+
+        // Only the device whose output changed is restarted, and the new parameters are applied to the other one
+        let started = collection.update(vec![device("Desk", 21324, 2.2), device("Shelf", 21326, 1.0)]).await;
+        assert_eq!(started, vec!["Shelf".to_string()]);
+        let after = stats(&collection);
+        assert!(Arc::ptr_eq(&before[0], &after[0]));
+        assert!(!Arc::ptr_eq(&before[1], &after[1]));
+        assert_eq!(collection.devices[0].parameters.borrow().gamma, 2.2);
+
+        assert!(collection.update(vec![device("Shelf", 21326, 1.0)]).await.is_empty());
+        assert_eq!(collection.devices.len(), 1);
+        assert!(Arc::ptr_eq(&after[1], &collection.devices[0].stats));
+        collection.shutdown().await;
     }
 }
@@ -5,17 +5,21 @@
 /// * Outputting the colors somewhere (usually to a physical device such as a WLED device or an RGB keyboard)
 mod device;
 mod device_collection;
-pub use device::RenderOutput;
+mod mux;
+pub use device::{RenderOutput, DevicePreview, OutputControl, OutputState, OutputStateEvent, SolidColor};
 pub use device::specification;
+pub use mux::{Source, SourceContent, SourceMux, CAPTURE_SOURCE};
 
 
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 
-use tokio::sync::watch;
+use tokio::sync::{broadcast, mpsc, watch};
 
 use specification::DeviceSpecification;
 use crate::common::Rect;
 use crate::profiles;
+use crate::telemetry;
 
 use device_collection::DeviceCollection;
 
@@ -26,10 +30,17 @@ use device_collection::DeviceCollection;
 /// and responding to activated [profiles::ApplicationProfile]s.
 pub struct RenderService {
     running_devices: Option<DeviceCollection>,
+    /// The devices as they were set, without the [profiles::ApplicationProfile::device_overrides] of the active profile
+    devices: watch::Sender<Vec<DeviceSpecification>>,
     frame_capturer: desktop_capture::DesktopCaptureController,
     frame_stream: watch::Receiver<desktop_capture::FrameCaptureEvent>,
     audio_capturer: audio_capture::AudioCaptureController,
     audio_stream: watch::Receiver<audio_capture::AudioIntensity>,
+    output_states_tx: mpsc::UnboundedSender<OutputStateEvent>,
+    output_states_rx: mpsc::UnboundedReceiver<OutputStateEvent>,
+    previews: broadcast::Sender<DevicePreview>,
+    control: watch::Sender<OutputControl>,
+    sources: watch::Sender<SourceMux>,
 
     active_profiles: ProfilesState,
     default_capture_region_horizontal: Rect,
@@ -37,36 +48,183 @@ pub struct RenderService {
 }
 
 impl RenderService {
-    pub fn new(desktop_capture_fps: f32, default_capture_region_hor: Rect, default_capture_region_ver: Rect) -> Self {
-        let (frame_capturer, frame_rx) = desktop_capture::DesktopCaptureController::new(desktop_capture_fps, crate::config::DESKTOP_CAPTURE_DECIMATION);
+    /// Creates a service that captures the desktop at `desktop_capture_fps`, reducing the resolution of frames by
+    /// halving it `desktop_capture_decimation` times.
+    pub fn new(desktop_capture_fps: f32, desktop_capture_decimation: u32, default_capture_region_hor: Rect, default_capture_region_ver: Rect) -> Self {
+        let (frame_capturer, frame_rx) = desktop_capture::DesktopCaptureController::new(desktop_capture_fps, desktop_capture_decimation);
         let (audio_capturer, audio_rx) = audio_capture::AudioCaptureController::new();
+        let (output_states_tx, output_states_rx) = mpsc::unbounded_channel();
+        // Subscribers only care about the latest frames, so they don't need to buffer much
+        let (previews, _) = broadcast::channel(16);
         RenderService{
             running_devices: None,
+            devices: watch::Sender::new(Vec::new()),
             frame_capturer,
             frame_stream: frame_rx,
             audio_capturer,
             audio_stream: audio_rx,
-            active_profiles: ProfilesState { active: HashMap::new() },
+            output_states_tx,
+            output_states_rx,
+            previews,
+            control: watch::Sender::new(OutputControl::default()),
+            sources: watch::Sender::new(SourceMux::default()),
+            active_profiles: ProfilesState { active: HashMap::new(), forced: None },
             default_capture_region_horizontal: default_capture_region_hor,
             default_capture_region_vertical: default_capture_region_ver,
         }
     }
 
-    pub fn set_devices(&mut self, devices: Vec<DeviceSpecification>) {
-        self.running_devices = Some(DeviceCollection::new(devices, &self.frame_stream, &self.audio_stream));
+    /// Replaces the running devices. The device overrides of the active profile are applied to them.
+    ///
+    /// Devices are identified by their names. Running devices are only restarted if their output or sampling changed;
+    /// changes to their [specification::DeviceParameters] are applied while they keep running. Devices are shut down
+    /// (see [RenderOutput::shutdown]) before new ones are started.
+    ///
+    /// Returns the names of the devices that were started, which report their state once they try to connect (see
+    /// [Self::next_output_state]). Devices that kept running don't report their state again.
+    pub async fn set_devices(&mut self, devices: Vec<DeviceSpecification>) -> Vec<String> {
+        self.devices.send_replace(devices);
+        let devices = self.devices_with_overrides();
+        match self.running_devices.as_mut() {
+            Some(running_devices) => running_devices.update(devices).await,
+            None => {
+                let started = devices.iter().map(|device| device.name.clone()).collect();
+                self.running_devices = Some(DeviceCollection::new(devices, &self.frame_stream, &self.audio_stream, &self.output_states_tx,
+                    &self.previews, &self.control.subscribe(), &self.sources.subscribe()));
+                started
+            },
+        }
+    }
+
+    /// Stops all running devices, waiting for their outputs to be shut down.
+    pub async fn shutdown_devices(&mut self) {
+        if let Some(devices) = self.running_devices.take() {
+            devices.shutdown().await;
+        }
+    }
+
+    /// Waits for and returns the next change to the connection state of a running device.
+    pub async fn next_output_state(&mut self) -> OutputStateEvent {
+        // We hold a sender ourselves, so the channel is never closed
+        self.output_states_rx.recv().await.unwrap()
+    }
+
+    /// Returns a receiver for the captured desktop frames.
+    pub fn frames(&self) -> watch::Receiver<desktop_capture::FrameCaptureEvent> {
+        self.frame_stream.clone()
+    }
+
+    /// Returns a sender for the colors drawn to all devices. Call [broadcast::Sender::subscribe] on it to receive them.
+    pub fn previews(&self) -> broadcast::Sender<DevicePreview> {
+        self.previews.clone()
+    }
+
+    /// Reads the counters of the capture controllers and all running devices.
+    pub fn counters(&self) -> telemetry::Counters {
+        let capture = self.frame_capturer.stats();
+        let audio = self.audio_capturer.stats();
+        let devices = self.running_devices.iter()
+            .flat_map(|devices| devices.device_stats())
+            .map(|(name, stats)| telemetry::DeviceCounters {
+                name: name.clone(),
+                samples: stats.samples.load(Ordering::Relaxed),
+                sampling_nanos: stats.sampling_nanos.load(Ordering::Relaxed),
+                transformation_nanos: stats.transformation_nanos.load(Ordering::Relaxed),
+                frames: stats.frames.load(Ordering::Relaxed),
+                draws: stats.draws.load(Ordering::Relaxed),
+                draw_latency_nanos: stats.draw_latency_nanos.load(Ordering::Relaxed),
+                dropped_frames: stats.dropped_frames.load(Ordering::Relaxed),
+                errors: stats.errors.load(Ordering::Relaxed),
+            })
+            .collect();
+        telemetry::Counters {
+            capture: telemetry::CaptureCounters {
+                frames_sent: capture.frames_sent.load(Ordering::Relaxed),
+                frames_captured: capture.frames_captured.load(Ordering::Relaxed),
+                capture_nanos: capture.capture_nanos.load(Ordering::Relaxed),
+                capture_errors: capture.capture_errors.load(Ordering::Relaxed),
+            },
+            audio: telemetry::AudioCounters {
+                values_sent: audio.values_sent.load(Ordering::Relaxed),
+                idle_values_sent: audio.idle_values_sent.load(Ordering::Relaxed),
+            },
+            devices,
+        }
     }
 
     pub fn set_audio_devices(&mut self, device_names: Vec<String>) {
         self.audio_capturer.set_audio_devices(device_names);
     }
 
+    /// Changes the [OutputControl] of all devices. Applies to running devices immediately, and to any devices that are
+    /// started later.
+    pub fn update_control(&self, update: impl FnOnce(&mut OutputControl)) {
+        self.control.send_modify(update);
+    }
+
+    /// Returns a receiver for the current [OutputControl].
+    pub fn control(&self) -> watch::Receiver<OutputControl> {
+        self.control.subscribe()
+    }
+
+    /// Returns a receiver for the devices as they were set, without the device overrides of the active profile.
+    pub fn devices(&self) -> watch::Receiver<Vec<DeviceSpecification>> {
+        self.devices.subscribe()
+    }
+
+    /// Adds a source of colors that contends with the captured colors of all devices by priority, replacing any source
+    /// with the same name (see [SourceMux]).
+    pub fn set_source(&self, source: Source) {
+        self.sources.send_modify(|sources| sources.set(source));
+    }
+
+    /// Removes the source with the given name, if any.
+    pub fn clear_source(&self, name: &str) {
+        self.sources.send_if_modified(|sources| sources.clear(name));
+    }
+
+    /// Returns a receiver for the registered sources.
+    pub fn sources(&self) -> watch::Receiver<SourceMux> {
+        self.sources.subscribe()
+    }
+
+    /// Changes the rate and resolution at which the desktop is captured (see [RenderService::new]).
+    pub async fn set_capture_settings(&self, desktop_capture_fps: f32, desktop_capture_decimation: u32) {
+        self.frame_capturer.set_capture_settings(desktop_capture_fps, desktop_capture_decimation).await;
+    }
+
+    /// Changes the regions to capture when no profile is active.
+    pub async fn set_default_capture_regions(&mut self, horizontal: Rect, vertical: Rect) {
+        self.default_capture_region_horizontal = horizontal;
+        self.default_capture_region_vertical = vertical;
+        self.apply_profiles().await;
+    }
+
     /// Sets or clears the active profile for the given monitor
     pub async fn set_active_profile(&mut self, monitor_index: u32, profile: Option<profiles::ActiveProfile>) {
         self.active_profiles.set_active_profile(monitor_index, profile);
+        self.apply_profiles().await;
+    }
+
+    /// Forces a profile to be used on the given monitor, regardless of which profiles are active. Passing [None] goes
+    /// back to using the active profiles.
+    pub async fn set_forced_profile(&mut self, profile: Option<(u32, profiles::ActiveProfile)>) {
+        self.active_profiles.forced = profile;
+        self.apply_profiles().await;
+    }
+
+    /// Returns the profile that is currently active on the given monitor, ignoring any forced profile.
+    pub fn active_profile(&self, monitor_index: u32) -> Option<&profiles::ActiveProfile> {
+        self.active_profiles.active.get(&monitor_index)
+    }
 
-        if let Some(device_group) = self.running_devices.as_ref() {
+    /// Applies the capture regions and device overrides of the highest priority profile to the running devices. The
+    /// overrides of previously active profiles are reverted.
+    async fn apply_profiles(&mut self) {
+        let devices = self.devices_with_overrides();
+        if let Some(device_group) = self.running_devices.as_mut() {
             if let Some((monitor_index, profile)) = self.active_profiles.get_highest_priority_profile() {
-                log::info!("Activating profile {} on monitor {}", profile.profile.title_regex.as_str(), monitor_index);
+                log::info!("Activating profile {} on monitor {}", profile.profile.id, monitor_index);
                 self.frame_capturer.set_capture_monitor(*monitor_index).await;
                 if let Some(region) = profile.actual_horizontal_region {
                     device_group.set_horizontal_region(region);
@@ -80,12 +238,26 @@ impl RenderService {
                 device_group.set_horizontal_region(self.default_capture_region_horizontal);
                 device_group.set_vertical_region(self.default_capture_region_vertical);
             }
+            device_group.update(devices).await;
         }
     }
+
+    /// Returns [RenderService::devices] with the device overrides of the highest priority profile applied.
+    fn devices_with_overrides(&self) -> Vec<DeviceSpecification> {
+        let overrides = self.active_profiles.get_highest_priority_profile().map(|(_, profile)| &profile.profile.device_overrides);
+        self.devices.borrow().iter()
+            .map(|spec| match overrides.and_then(|overrides| overrides.get(&spec.name)) {
+                Some(device_override) => spec.with_override(device_override),
+                None => spec.clone(),
+            })
+            .collect()
+    }
 }
 
 struct ProfilesState {
     pub active: HashMap<u32, profiles::ActiveProfile>,
+    /// A profile that takes precedence over all active profiles, along with the monitor it is used on
+    pub forced: Option<(u32, profiles::ActiveProfile)>,
 }
 
 impl ProfilesState {
@@ -97,6 +269,9 @@ impl ProfilesState {
     }
 
     fn get_highest_priority_profile(&self) -> Option<(&u32, &profiles::ActiveProfile)> {
+        if let Some((monitor_index, profile)) = &self.forced {
+            return Some((monitor_index, profile));
+        }
         self.active.iter()
             .fold(None, |max: Option<(&u32, &profiles::ActiveProfile)>, (monitor_index, profile)| {
                 if max.is_none() || max.as_ref().unwrap().1.profile.priority < profile.profile.priority {
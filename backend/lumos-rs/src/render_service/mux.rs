@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color::RgbF32;
+
+use crate::common::{Rect, RgbVec};
+use super::device::frame_sampler::FrameSampler;
+use super::specification::DeviceEffect;
+
+/// The name of the [SourceContent::Capture] source that is registered by default.
+pub const CAPTURE_SOURCE: &str = "capture";
+/// The priority of the default [SourceContent::Capture] source.
+pub const CAPTURE_PRIORITY: i32 = 0;
+
+/// A source of colors for the devices, which contends with the other sources in a [SourceMux].
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// Identifies the source, e.g. to replace or clear it.
+    pub name: Arc<str>,
+    /// Devices show the live source with the highest priority.
+    pub priority: i32,
+    /// When the source stops being live, or [None] to keep it until it is cleared.
+    pub until: Option<Instant>,
+    pub content: SourceContent,
+    /// How much this source covers the sources below it, in [0.0, 1.0]. A source that doesn't cover them completely
+    /// is blended with the next live source.
+    pub opacity: f32,
+}
+
+#[derive(Debug, Clone)]
+pub enum SourceContent {
+    /// The colors each device sampled from the desktop, or its [DeviceEffect] if it has one.
+    Capture,
+    Color(RgbF32),
+    Effect(DeviceEffect),
+    /// Colors for some of the devices, by device name. The source isn't live for other devices, or when the number of
+    /// colors doesn't match the device.
+    Colors(HashMap<String, Arc<RgbVec>>),
+    /// An image that each device samples like a captured frame, using the whole image as the capture region.
+    Image(Arc<desktop_capture::Frame>),
+}
+
+impl Source {
+    fn is_live(&self, now: Instant) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+/// What a device hands to [SourceMux::render].
+pub struct DeviceInput<'a> {
+    pub name: &'a str,
+    pub size: usize,
+    /// The colors the device sampled from the desktop, or its [DeviceEffect] if it has one.
+    pub captured: Option<&'a RgbVec>,
+    /// How long the device has been running, for animated effects.
+    pub elapsed: Duration,
+    /// Samples [SourceContent::Image]s. Images aren't live for devices without one.
+    pub image_sampler: Option<&'a (dyn FrameSampler<Rect> + Sync)>,
+}
+
+/// The colors a device should draw, according to a [SourceMux].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MuxedColors {
+    pub colors: RgbVec,
+    /// The name of the live source with the highest priority.
+    pub source: Arc<str>,
+}
+
+/// Decides which of the registered [Source]s each device shows.
+///
+/// Sources with higher priorities cover those with lower priorities, unless they expired. When sources have the same
+/// priority, the one that was set last wins.
+#[derive(Debug, Clone)]
+pub struct SourceMux {
+    /// Sorted by descending priority
+    sources: Vec<Source>,
+}
+
+impl Default for SourceMux {
+    /// Only shows the captured colors.
+    fn default() -> Self {
+        SourceMux {
+            sources: vec![Source {
+                name: Arc::from(CAPTURE_SOURCE),
+                priority: CAPTURE_PRIORITY,
+                until: None,
+                content: SourceContent::Capture,
+                opacity: 1.0,
+            }],
+        }
+    }
+}
+
+impl SourceMux {
+    /// Adds a source, replacing any source with the same name. Sources that expired are removed.
+    pub fn set(&mut self, source: Source) {
+        let now = Instant::now();
+        self.sources.retain(|existing| existing.name != source.name && existing.is_live(now));
+        let index = self.sources.iter().position(|existing| existing.priority <= source.priority).unwrap_or(self.sources.len());
+        self.sources.insert(index, source);
+    }
+
+    /// Removes the source with the given name. Returns whether there was one.
+    pub fn clear(&mut self, name: &str) -> bool {
+        let count = self.sources.len();
+        self.sources.retain(|source| &*source.name != name);
+        self.sources.len() != count
+    }
+
+    /// The registered sources that are live at `now`, by descending priority.
+    pub fn live_sources(&self, now: Instant) -> impl Iterator<Item = &Source> {
+        self.sources.iter().filter(move |source| source.is_live(now))
+    }
+
+    /// When the next live source expires, if any.
+    pub fn next_expiry(&self, now: Instant) -> Option<Instant> {
+        self.live_sources(now).filter_map(|source| source.until).min()
+    }
+
+    /// Whether a live source shows an animated effect, so devices have to be drawn continuously.
+    pub fn is_animated(&self, now: Instant) -> bool {
+        self.live_sources(now).any(|source| matches!(source.content, SourceContent::Effect(effect) if effect.is_animated()))
+    }
+
+    /// Returns the colors for a device. Returns [None] if no source is live for the device.
+    pub fn render(&self, device: &DeviceInput, now: Instant) -> Option<MuxedColors> {
+        let mut layers = Vec::new();
+        for source in self.live_sources(now) {
+            let colors = match &source.content {
+                SourceContent::Capture => device.captured.cloned(),
+                SourceContent::Color(color) => Some(vec![*color; device.size]),
+                SourceContent::Effect(effect) => Some(effect.render(device.size, device.elapsed)),
+                SourceContent::Colors(colors) => colors.get(device.name).filter(|colors| colors.len() == device.size).map(|colors| colors.to_vec()),
+                SourceContent::Image(image) => device.image_sampler.map(|sampler| sampler.sample(image)),
+            };
+            if let Some(colors) = colors {
+                layers.push((source, colors));
+                // The sources below are completely covered
+                if source.opacity >= 1.0 {
+                    break;
+                }
+            }
+        }
+        let name = layers.first()?.0.name.clone();
+        // The lowest live source is the background, even if it isn't opaque itself
+        let mut layers = layers.into_iter().rev();
+        let (_, mut colors) = layers.next()?;
+        for (source, layer) in layers {
+            for (below, above) in colors.iter_mut().zip(&layer) {
+                *below = color::blend(below, above, source.opacity);
+            }
+        }
+        Some(MuxedColors { colors, source: name })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> RgbF32 {
+        RgbF32 { red: value, green: value, blue: value }
+    }
+
+    fn source(name: &str, priority: i32, content: SourceContent) -> Source {
+        Source { name: Arc::from(name), priority, until: None, content, opacity: 1.0 }
+    }
+
+    #[test]
+    fn test_render() {
+        let now = Instant::now();
+        let captured = vec![gray(0.2); 2];
+        let device = DeviceInput { name: "Desk", size: 2, captured: Some(&captured), elapsed: Duration::ZERO, image_sampler: None };
+        let render = |mux: &SourceMux, now| mux.render(&device, now);
+        let mut mux = SourceMux::default();
+        assert_eq!(render(&mux, now).unwrap().colors, captured);
+        assert!(mux.render(&DeviceInput { captured: None, ..device }, now).is_none());
+
+        // Sources for other devices, or with the wrong size, don't apply
+        let colors = HashMap::from([("Desk".to_string(), Arc::new(vec![gray(1.0)])), ("Shelf".to_string(), Arc::new(vec![gray(1.0); 2]))]);
+        mux.set(source("external", 50, SourceContent::Colors(colors)));
+        assert_eq!(&*render(&mux, now).unwrap().source, CAPTURE_SOURCE);
+
+        mux.set(Source { until: Some(now + Duration::from_secs(1)), ..source("manual", 100, SourceContent::Color(gray(1.0))) });
+        assert_eq!(render(&mux, now), Some(MuxedColors { colors: vec![gray(1.0); 2], source: Arc::from("manual") }));
+        assert_eq!(mux.next_expiry(now), Some(now + Duration::from_secs(1)));
+        assert_eq!(&*render(&mux, now + Duration::from_secs(1)).unwrap().source, CAPTURE_SOURCE);
+
+        // Transparent sources are blended with the sources below them
+        mux.set(Source { opacity: 0.5, ..source("overlay", 10, SourceContent::Color(gray(0.6))) });
+        assert_eq!(render(&mux, now + Duration::from_secs(1)).unwrap().colors, vec![gray(0.4); 2]);
+
+        // Images are sampled by the device
+        let image = desktop_capture::Frame { buffer: vec![color::RgbU8 { red: 255, green: 255, blue: 255 }; 4], width: 2, height: 2, downscaling: 1 };
+        mux.set(source("image", 20, SourceContent::Image(Arc::new(image))));
+        assert_eq!(mux.render(&device, now + Duration::from_secs(1)).unwrap().colors, vec![gray(0.4); 2]);
+        let sampler = super::super::device::frame_sampler::HorizontalFrameSampler::new(2, Rect { left: 0, top: 0, width: 2, height: 2 });
+        let with_sampler = DeviceInput { image_sampler: Some(&sampler), ..device };
+        assert_eq!(mux.render(&with_sampler, now + Duration::from_secs(1)).unwrap().colors, vec![gray(1.0); 2]);
+        assert!(mux.clear("image"));
+
+        assert!(mux.clear("overlay"));
+        assert!(!mux.clear("overlay"));
+        assert!(mux.clear(CAPTURE_SOURCE));
+        assert!(render(&mux, now + Duration::from_secs(1)).is_none());
+    }
+}
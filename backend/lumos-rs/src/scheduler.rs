@@ -0,0 +1,348 @@
+//! Adjusts the output to the time of day, and turns it off while the user is away.
+//!
+//! The [Scheduler] follows a [BrightnessCurve] over the day. It reports the user as idle when neither the focused
+//! window nor a noticeable part of the screen changed for a while, or when the screen has been black for a while (e.g.
+//! because of a blank screensaver). Any activity wakes the output again.
+//!
+//! Changes on screen are only noticed while the desktop is captured, i.e. while a profile is active. Otherwise the user
+//! is never reported as idle, since working in a single window can't be told apart from being away.
+use std::time::Duration;
+
+use chrono::Timelike;
+use color::RgbU8;
+use desktop_capture::FrameCaptureEvent;
+use log::info;
+use simple_error::{SimpleError, SimpleResult};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::profiles::WindowCondition;
+use crate::profiles::window_source::FocusedWindow;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+/// How often the brightness is recalculated, while nothing else happens
+const BRIGHTNESS_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// The number of pixels of each frame that are compared to the previous frame
+const SAMPLES_PER_FRAME: usize = 4096;
+/// How many of the samples have to change to count as activity, so small changes (e.g. a clock) don't keep the output on
+const CHANGED_SAMPLES_FOR_ACTIVITY: usize = SAMPLES_PER_FRAME / 100;
+/// Frames where no color component is brighter than this count as black
+const BLACK_LEVEL: u8 = 16;
+
+/// The brightness over the day, interpolated linearly between points and wrapping around midnight.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrightnessCurve {
+    /// (seconds since midnight, brightness), sorted by time
+    points: Vec<(u32, f32)>,
+}
+
+impl BrightnessCurve {
+    /// Creates a curve through the given (seconds since midnight, brightness) points. Without points, the brightness is
+    /// always 1.
+    pub fn new(mut points: Vec<(u32, f32)>) -> SimpleResult<Self> {
+        if let Some((_, brightness)) = points.iter().find(|(_, brightness)| !(0.0..=1.0).contains(brightness)) {
+            return Err(SimpleError::new(format!("Brightness {} is not between 0 and 1", brightness)));
+        }
+        if points.iter().any(|(time, _)| *time >= SECONDS_PER_DAY) {
+            return Err(SimpleError::new("Times must be before midnight"));
+        }
+        points.sort_by_key(|(time, _)| *time);
+        if points.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(SimpleError::new("Each time can only be given once"));
+        }
+        Ok(BrightnessCurve { points })
+    }
+
+    /// The brightness at the given number of seconds since midnight.
+    pub fn brightness_at(&self, time: u32) -> f32 {
+        if self.points.len() < 2 {
+            return self.points.first().map_or(1.0, |(_, brightness)| *brightness);
+        }
+        // Before the first point of the day, interpolate from the last point of the previous day
+        let next = self.points.iter().position(|(point_time, _)| *point_time > time).unwrap_or(0);
+        let (prev_time, prev_brightness) = self.points[(next + self.points.len() - 1) % self.points.len()];
+        let (next_time, next_brightness) = self.points[next];
+        let span = (next_time + SECONDS_PER_DAY - prev_time) % SECONDS_PER_DAY;
+        let elapsed = (time + SECONDS_PER_DAY - prev_time) % SECONDS_PER_DAY;
+        prev_brightness + (next_brightness - prev_brightness) * elapsed as f32 / span as f32
+    }
+}
+
+/// Parses a time of day like `07:30` into the number of seconds since midnight.
+pub fn parse_time_of_day(text: &str) -> SimpleResult<u32> {
+    let invalid = || SimpleError::new(format!("Invalid time '{}', expected hours and minutes like 07:30", text));
+    let (hours, minutes) = text.split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours >= 24 || minutes >= 60 {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) * 60)
+}
+
+/// Decides how the [Scheduler] adjusts the output.
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleSettings {
+    pub brightness_curve: BrightnessCurve,
+    /// Turns the output off after there was no activity for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Turns the output off after the screen was black for this long.
+    pub black_screen_timeout: Option<Duration>,
+    /// While one of these windows is focused (e.g. a screensaver), changes on screen don't count as activity.
+    pub idle_windows: Vec<WindowCondition>,
+}
+
+/// The adjustments to the output at some point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScheduledOutput {
+    /// The brightness according to the [BrightnessCurve], in [0.0, 1.0].
+    pub brightness: f32,
+    /// Whether the user is away, so the output should be off.
+    pub idle: bool,
+}
+
+/// Calculates the [ScheduledOutput] from the time of day, the captured frames and the focused windows.
+pub struct Scheduler {
+    settings: ScheduleSettings,
+    frames: watch::Receiver<FrameCaptureEvent>,
+    focus: watch::Receiver<Option<FocusedWindow>>,
+    activity: ActivityTracker,
+    reported: Option<ScheduledOutput>,
+    /// Returns the number of seconds since midnight, in local time
+    time_of_day: fn() -> u32,
+}
+
+impl Scheduler {
+    /// Creates a scheduler for the frames of [crate::render_service::RenderService::frames] and the windows of
+    /// [crate::profiles::ProfileListener::focused_windows].
+    pub fn new(settings: ScheduleSettings, frames: watch::Receiver<FrameCaptureEvent>, focus: watch::Receiver<Option<FocusedWindow>>) -> Self {
+        Scheduler {
+            settings,
+            frames,
+            focus,
+            activity: ActivityTracker::new(Instant::now()),
+            reported: None,
+            time_of_day: || chrono::Local::now().num_seconds_from_midnight(),
+        }
+    }
+
+    /// Changes the settings. Applies from the next call to [Scheduler::next].
+    pub fn set_settings(&mut self, settings: ScheduleSettings) {
+        if let Some(window) = &*self.focus.borrow() {
+            self.activity.in_idle_window = settings.idle_windows.iter().any(|condition| condition.matches(window));
+        }
+        self.settings = settings;
+    }
+
+    /// Waits for and returns the next change to the [ScheduledOutput]. The first call returns right away.
+    ///
+    /// This is cancel safe.
+    pub async fn next(&mut self) -> ScheduledOutput {
+        let mut frames_open = true;
+        let mut focus_open = true;
+        loop {
+            let now = Instant::now();
+            let output = ScheduledOutput {
+                brightness: self.settings.brightness_curve.brightness_at((self.time_of_day)()),
+                idle: self.activity.is_idle(&self.settings, now),
+            };
+            if self.reported != Some(output) {
+                if let Some(reported) = self.reported && reported.idle != output.idle {
+                    info!("{}", if output.idle { "User is idle, turning output off" } else { "User is back, turning output on" });
+                }
+                self.reported = Some(output);
+                return output;
+            }
+            let mut wake_at = now + BRIGHTNESS_UPDATE_INTERVAL;
+            if !output.idle && let Some(idle_at) = self.activity.idle_at(&self.settings) {
+                wake_at = wake_at.min(idle_at);
+            }
+            tokio::select! {
+                changed = self.frames.changed(), if frames_open => {
+                    match changed {
+                        Ok(()) => self.activity.on_frame(&self.frames.borrow_and_update(), Instant::now()),
+                        Err(_) => frames_open = false,
+                    }
+                },
+                changed = self.focus.changed(), if focus_open => {
+                    match changed {
+                        Ok(()) => {
+                            if let Some(window) = &*self.focus.borrow_and_update() {
+                                self.activity.on_focus(window, &self.settings.idle_windows, Instant::now());
+                            }
+                        },
+                        Err(_) => focus_open = false,
+                    }
+                },
+                _ = tokio::time::sleep_until(wake_at) => {},
+            }
+        }
+    }
+}
+
+/// Keeps track of when the user was last active and since when the screen is black.
+struct ActivityTracker {
+    last_activity: Instant,
+    black_since: Option<Instant>,
+    /// Some pixels of the last captured frame
+    last_samples: Option<Vec<RgbU8>>,
+    /// Whether one of [ScheduleSettings::idle_windows] is focused
+    in_idle_window: bool,
+    /// Whether frames are being captured. Capturing stops while no profile is active, and without frames neither
+    /// activity nor black screens can be detected, so the user never counts as idle then.
+    capturing: bool,
+}
+
+impl ActivityTracker {
+    fn new(now: Instant) -> Self {
+        ActivityTracker { last_activity: now, black_since: None, last_samples: None, in_idle_window: false, capturing: false }
+    }
+
+    fn on_focus(&mut self, window: &FocusedWindow, idle_windows: &[WindowCondition], now: Instant) {
+        self.in_idle_window = idle_windows.iter().any(|condition| condition.matches(window));
+        if !self.in_idle_window {
+            self.last_activity = now;
+        }
+    }
+
+    fn on_frame(&mut self, event: &FrameCaptureEvent, now: Instant) {
+        let FrameCaptureEvent::Captured(frame) = event else {
+            // Nothing is known about the screen until capturing starts again
+            self.last_samples = None;
+            self.black_since = None;
+            self.capturing = false;
+            return;
+        };
+        if !self.capturing {
+            // Time without capturing doesn't count towards the idle timeout
+            self.capturing = true;
+            self.last_activity = now;
+        }
+        let step = (frame.buffer.len() / SAMPLES_PER_FRAME).max(1);
+        let samples: Vec<RgbU8> = frame.buffer.iter().step_by(step).copied().collect();
+
+        let is_black = samples.iter().all(|pixel| pixel.red.max(pixel.green).max(pixel.blue) <= BLACK_LEVEL);
+        if !is_black {
+            self.black_since = None;
+        } else if self.black_since.is_none() {
+            self.black_since = Some(now);
+        }
+
+        if let Some(last_samples) = &self.last_samples && last_samples.len() == samples.len() && !self.in_idle_window {
+            let changed = last_samples.iter().zip(&samples).filter(|(last, new)| last != new).count();
+            if changed >= CHANGED_SAMPLES_FOR_ACTIVITY {
+                self.last_activity = now;
+            }
+        }
+        self.last_samples = Some(samples);
+    }
+
+    fn is_idle(&self, settings: &ScheduleSettings, now: Instant) -> bool {
+        self.idle_at(settings).is_some_and(|idle_at| idle_at <= now)
+    }
+
+    /// When the user counts as idle if nothing happens until then, if ever.
+    fn idle_at(&self, settings: &ScheduleSettings) -> Option<Instant> {
+        if !self.capturing {
+            return None;
+        }
+        let after_activity = settings.idle_timeout.map(|timeout| self.last_activity + timeout);
+        let after_black = settings.black_screen_timeout.zip(self.black_since).map(|(timeout, since)| since + timeout);
+        after_activity.into_iter().chain(after_black).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: u8) -> FrameCaptureEvent {
+        FrameCaptureEvent::Captured(desktop_capture::Frame {
+            buffer: vec![RgbU8 { red: value, green: value, blue: value }; 64 * 64],
+            width: 64,
+            height: 64,
+            downscaling: 1,
+        })
+    }
+
+    fn window(title: &str) -> Option<FocusedWindow> {
+        Some(FocusedWindow { title: title.to_string(), process_name: None, class: None, fullscreen: false, position: None })
+    }
+
+    #[test]
+    fn test_brightness_curve() {
+        assert_eq!(BrightnessCurve::default().brightness_at(0), 1.0);
+        let curve = BrightnessCurve::new(vec![(parse_time_of_day("22:00").unwrap(), 0.2), (parse_time_of_day("08:00").unwrap(), 1.0)]).unwrap();
+        assert_eq!(curve.brightness_at(8 * 3600), 1.0);
+        assert_eq!(curve.brightness_at(15 * 3600), 0.6);
+        assert_eq!(curve.brightness_at(22 * 3600), 0.2);
+        // Wraps around midnight
+        assert_eq!(curve.brightness_at(3 * 3600), 0.6);
+
+        assert!(BrightnessCurve::new(vec![(0, 1.5)]).is_err());
+        assert!(BrightnessCurve::new(vec![(0, 1.0), (0, 0.5)]).is_err());
+        assert!(parse_time_of_day("24:00").is_err());
+        assert!(parse_time_of_day("7").is_err());
+        assert_eq!(parse_time_of_day("07:30").unwrap(), 27000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle() {
+        let (frames_tx, frames) = watch::channel(FrameCaptureEvent::Stopped);
+        let (focus_tx, focus) = watch::channel(None);
+        let settings = ScheduleSettings {
+            idle_timeout: Some(Duration::from_secs(600)),
+            black_screen_timeout: Some(Duration::from_secs(60)),
+            idle_windows: vec![WindowCondition::Title(regex::Regex::new("^Screensaver$").unwrap())],
+            ..ScheduleSettings::default()
+        };
+        let mut scheduler = Scheduler::new(settings, frames, focus);
+        scheduler.time_of_day = || 0;
+        let start = Instant::now();
+        assert_eq!(scheduler.next().await, ScheduledOutput { brightness: 1.0, idle: false });
+
+        // Changes on screen keep the user active
+        frames_tx.send_replace(frame(100));
+        assert!(tokio::time::timeout(Duration::from_secs(500), scheduler.next()).await.is_err());
+        frames_tx.send_replace(frame(200));
+        assert!(scheduler.next().await.idle);
+        assert_eq!(Instant::now() - start, Duration::from_secs(1100));
+
+        // A new window wakes the output right away
+        focus_tx.send_replace(window("Editor"));
+        assert!(!scheduler.next().await.idle);
+
+        // Screensavers don't count as activity, and a black screen turns the output off sooner
+        focus_tx.send_replace(window("Screensaver"));
+        frames_tx.send_replace(frame(0));
+        let before = Instant::now();
+        assert!(scheduler.next().await.idle);
+        assert_eq!(Instant::now() - before, Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_without_capture() {
+        let (frames_tx, frames) = watch::channel(FrameCaptureEvent::Stopped);
+        let (_focus_tx, focus) = watch::channel(window("Editor"));
+        let settings = ScheduleSettings {
+            idle_timeout: Some(Duration::from_secs(600)),
+            black_screen_timeout: Some(Duration::from_secs(60)),
+            ..ScheduleSettings::default()
+        };
+        let mut scheduler = Scheduler::new(settings, frames, focus);
+        scheduler.time_of_day = || 0;
+        assert!(!scheduler.next().await.idle);
+
+        // Nothing is known about the user while no profile is active and capturing is stopped
+        assert!(tokio::time::timeout(Duration::from_secs(3600), scheduler.next()).await.is_err());
+
+        // The timeout starts once capturing does
+        frames_tx.send_replace(frame(100));
+        let start = Instant::now();
+        assert!(scheduler.next().await.idle);
+        assert_eq!(Instant::now() - start, Duration::from_secs(600));
+
+        frames_tx.send_replace(FrameCaptureEvent::Stopped);
+        assert!(!scheduler.next().await.idle);
+    }
+}
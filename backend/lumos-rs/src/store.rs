@@ -0,0 +1,187 @@
+//! Persists the configuration that clients send, so that it can be restored when the application starts.
+//!
+//! The configuration is stored as a JSON file with a `schemaVersion` field (see [SCHEMA_VERSION]). Files written by
+//! older versions of the application are migrated when they are loaded (see [MIGRATIONS]).
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use simple_error::{SimpleError, SimpleResult, try_with};
+use tokio::sync::watch;
+
+use crate::websocket::protocol::{DeviceEntry, ProfileEntry};
+
+/// The version of the stored configuration. Must be incremented, and a migration added to [MIGRATIONS], on any
+/// incompatible change to [StoredConfig].
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Converts a stored configuration from one schema version to the next.
+type Migration = fn(&mut Value) -> SimpleResult<()>;
+
+/// The migration at index `i` converts a configuration of version `i + 1` to version `i + 2`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize - 1] = [];
+
+/// Everything that is restored when the application starts.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StoredConfig {
+    pub devices: Vec<DeviceEntry>,
+    pub profiles: Vec<ProfileEntry>,
+    pub audio_devices: Vec<String>,
+    pub settings: Settings,
+}
+
+/// Settings that apply to the application as a whole.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// The brightness of all devices, between 0 and 1.
+    pub brightness: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            brightness: 1.0,
+        }
+    }
+}
+
+/// A [StoredConfig] that is written to a file whenever it changes.
+pub struct ConfigStore {
+    path: PathBuf,
+    config: watch::Sender<StoredConfig>,
+}
+
+impl ConfigStore {
+    /// Loads the configuration stored at `path`. If there is no file at `path`, the default configuration is used.
+    ///
+    /// A file that can't be loaded is moved aside (to `<path>.invalid`), rather than being overwritten on the next
+    /// change.
+    pub fn load(path: PathBuf) -> Self {
+        let config = match std::fs::read_to_string(&path) {
+            Ok(contents) => match parse(&contents) {
+                Ok(config) => {
+                    info!("Loaded configuration from {}", path.display());
+                    config
+                },
+                Err(e) => {
+                    let backup = path.with_extension("json.invalid");
+                    warn!("Could not load configuration from {}, moving it to {}: {}", path.display(), backup.display(), e);
+                    if let Err(e) = std::fs::rename(&path, &backup) {
+                        warn!("Could not move invalid configuration: {}", e);
+                    }
+                    StoredConfig::default()
+                },
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredConfig::default(),
+            Err(e) => {
+                warn!("Could not read configuration from {}: {}", path.display(), e);
+                StoredConfig::default()
+            },
+        };
+        ConfigStore {
+            path,
+            config: watch::Sender::new(config),
+        }
+    }
+
+    /// The configuration file in the user's configuration directory.
+    pub fn default_path() -> SimpleResult<PathBuf> {
+        let dir = dirs::config_dir().ok_or_else(|| SimpleError::new("Could not find the configuration directory"))?;
+        Ok(dir.join("lumos").join("config.json"))
+    }
+
+    pub fn config(&self) -> StoredConfig {
+        self.config.borrow().clone()
+    }
+
+    /// Returns a receiver that always holds the current configuration.
+    pub fn subscribe(&self) -> watch::Receiver<StoredConfig> {
+        self.config.subscribe()
+    }
+
+    /// Changes the configuration and writes it to the file.
+    pub fn update(&self, update: impl FnOnce(&mut StoredConfig)) -> SimpleResult<()> {
+        self.config.send_modify(update);
+        save(&self.path, &self.config.borrow())
+    }
+}
+
+/// Parses a stored configuration of any schema version.
+fn parse(contents: &str) -> SimpleResult<StoredConfig> {
+    let mut value: Value = try_with!(serde_json::from_str(contents), "Invalid JSON");
+    let version = value.get("schemaVersion")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| SimpleError::new("Missing schema version"))? as u32;
+    migrate(&mut value, version, &MIGRATIONS)?;
+    Ok(try_with!(serde_json::from_value(value), "Invalid configuration"))
+}
+
+/// Applies the `migrations` needed to bring a configuration of schema `version` up to date.
+fn migrate(value: &mut Value, version: u32, migrations: &[Migration]) -> SimpleResult<()> {
+    let latest = migrations.len() as u32 + 1;
+    if version == 0 || version > latest {
+        return Err(SimpleError::new(format!("Unsupported schema version {} (latest is {})", version, latest)));
+    }
+    for (index, migration) in migrations.iter().enumerate().skip(version as usize - 1) {
+        try_with!(migration(value), "Migrating from schema version {} failed", index + 1);
+    }
+    Ok(())
+}
+
+/// Writes `config` to `path`, replacing the previous file only once the new one has been written completely.
+fn save(path: &Path, config: &StoredConfig) -> SimpleResult<()> {
+    let mut value = try_with!(serde_json::to_value(config), "Could not serialize configuration");
+    value["schemaVersion"] = SCHEMA_VERSION.into();
+    if let Some(dir) = path.parent() {
+        try_with!(std::fs::create_dir_all(dir), "Could not create {}", dir.display());
+    }
+    let temp_path = path.with_extension("json.tmp");
+    try_with!(std::fs::write(&temp_path, serde_json::to_string_pretty(&value).unwrap()), "Could not write {}", temp_path.display());
+    try_with!(std::fs::rename(&temp_path, path), "Could not replace {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("lumos-store-test-{}", std::process::id())).join("config.json");
+        let store = ConfigStore::load(path.clone());
+        assert!(store.config().devices.is_empty());
+        store.update(|config| {
+            config.audio_devices = vec!["Speakers".to_string()];
+            config.settings.brightness = 0.5;
+        }).unwrap();
+
+        let store = ConfigStore::load(path.clone());
+        assert_eq!(store.config().audio_devices, vec!["Speakers".to_string()]);
+        assert_eq!(store.config().settings.brightness, 0.5);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        fn rename_speakers(value: &mut Value) -> SimpleResult<()> {
+            value["audioDevices"] = serde_json::json!(["Headphones"]);
+            Ok(())
+        }
+        let migrations: [Migration; 1] = [rename_speakers];
+
+        let mut value = serde_json::json!({ "schemaVersion": 1, "audioDevices": ["Speakers"] });
+        migrate(&mut value, 1, &migrations).unwrap();
+        assert_eq!(value["audioDevices"][0], "Headphones");
+
+        // Configurations that are already up to date are left alone
+        let mut value = serde_json::json!({ "schemaVersion": 2, "audioDevices": ["Speakers"] });
+        migrate(&mut value, 2, &migrations).unwrap();
+        assert_eq!(value["audioDevices"][0], "Speakers");
+
+        assert!(migrate(&mut value, 3, &migrations).is_err());
+        assert!(parse(r#"{ "devices": [] }"#).is_err());
+    }
+}
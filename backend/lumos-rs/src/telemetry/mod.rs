@@ -0,0 +1,215 @@
+//! Rolling runtime metrics, computed from the counters kept by the capture controllers and devices.
+//!
+//! [RollingTelemetry] is fed a snapshot of all counters ([Counters]) once per second, and turns the snapshots within
+//! its window into rates and averages ([TelemetryReport]). Reports are served to websocket clients and, in the
+//! Prometheus text format, over HTTP (see [prometheus]).
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+pub mod prometheus;
+
+/// How far back [RollingTelemetry] looks when computing rates and averages.
+pub const TELEMETRY_WINDOW: Duration = Duration::from_secs(5);
+
+/// A snapshot of the counters of all instrumented components. All values are totals since the component was created.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    pub capture: CaptureCounters,
+    pub audio: AudioCounters,
+    pub devices: Vec<DeviceCounters>,
+}
+
+/// See [desktop_capture::CaptureStats].
+#[derive(Debug, Clone, Default)]
+pub struct CaptureCounters {
+    pub frames_sent: u64,
+    pub frames_captured: u64,
+    pub capture_nanos: u64,
+    pub capture_errors: u64,
+}
+
+/// See [audio_capture::AudioStats].
+#[derive(Debug, Clone, Default)]
+pub struct AudioCounters {
+    pub values_sent: u64,
+    pub idle_values_sent: u64,
+}
+
+/// The counters of a single device, see [crate::render_service::RenderService::counters].
+#[derive(Debug, Clone, Default)]
+pub struct DeviceCounters {
+    pub name: String,
+    pub samples: u64,
+    pub sampling_nanos: u64,
+    pub transformation_nanos: u64,
+    pub frames: u64,
+    pub draws: u64,
+    pub draw_latency_nanos: u64,
+    pub dropped_frames: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryReport {
+    pub capture: CaptureReport,
+    pub audio: AudioReport,
+    pub devices: Vec<DeviceReport>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureReport {
+    /// Frames sent to devices per second.
+    pub fps: f64,
+    /// New frames captured from the desktop per second. Lower than [CaptureReport::fps] when the desktop is static.
+    pub captured_fps: f64,
+    pub avg_capture_ms: f64,
+    pub frames_total: u64,
+    pub errors_total: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioReport {
+    /// Intensity values from audio devices per second.
+    pub values_per_second: f64,
+    /// Constant values sent per second because no audio device was playing.
+    pub idle_values_per_second: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceReport {
+    pub name: String,
+    /// Frames handed to the output per second.
+    pub fps: f64,
+    pub avg_sampling_ms: f64,
+    pub avg_transformation_ms: f64,
+    /// The average time from handing a frame to the output until it was drawn.
+    pub avg_draw_latency_ms: f64,
+    pub dropped_frames_per_second: f64,
+    pub frames_total: u64,
+    pub errors_total: u64,
+}
+
+/// Computes a [TelemetryReport] from the [Counters] recorded within the last [TELEMETRY_WINDOW].
+pub struct RollingTelemetry {
+    window: Duration,
+    snapshots: VecDeque<(Instant, Counters)>,
+}
+
+impl RollingTelemetry {
+    pub fn new(window: Duration) -> Self {
+        RollingTelemetry {
+            window,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// Records a snapshot taken now, and returns the report for the current window.
+    pub fn record(&mut self, counters: Counters) -> TelemetryReport {
+        self.record_at(Instant::now(), counters)
+    }
+
+    fn record_at(&mut self, now: Instant, counters: Counters) -> TelemetryReport {
+        self.snapshots.push_back((now, counters));
+        // Keep the newest snapshot that is outside the window, so the rates cover the whole window
+        while self.snapshots.len() > 2 && now.duration_since(self.snapshots[1].0) >= self.window {
+            self.snapshots.pop_front();
+        }
+        let (oldest_time, oldest) = self.snapshots.front().unwrap();
+        let (newest_time, newest) = self.snapshots.back().unwrap();
+        let seconds = newest_time.duration_since(*oldest_time).as_secs_f64();
+        let rate = |new: u64, old: u64| if seconds > 0.0 { delta(new, old) as f64 / seconds } else { 0.0 };
+
+        let capture = CaptureReport {
+            fps: rate(newest.capture.frames_sent, oldest.capture.frames_sent),
+            captured_fps: rate(newest.capture.frames_captured, oldest.capture.frames_captured),
+            avg_capture_ms: average_ms(
+                delta(newest.capture.capture_nanos, oldest.capture.capture_nanos),
+                delta(newest.capture.frames_captured, oldest.capture.frames_captured)),
+            frames_total: newest.capture.frames_sent,
+            errors_total: newest.capture.capture_errors,
+        };
+        let audio = AudioReport {
+            values_per_second: rate(newest.audio.values_sent, oldest.audio.values_sent),
+            idle_values_per_second: rate(newest.audio.idle_values_sent, oldest.audio.idle_values_sent),
+        };
+        let devices = newest.devices.iter().map(|device| {
+            // Devices that were just started have no history, so compare against zero
+            let empty = DeviceCounters::default();
+            let old = oldest.devices.iter().find(|old| old.name == device.name).unwrap_or(&empty);
+            DeviceReport {
+                name: device.name.clone(),
+                fps: rate(device.frames, old.frames),
+                avg_sampling_ms: average_ms(delta(device.sampling_nanos, old.sampling_nanos), delta(device.samples, old.samples)),
+                avg_transformation_ms: average_ms(delta(device.transformation_nanos, old.transformation_nanos), delta(device.samples, old.samples)),
+                avg_draw_latency_ms: average_ms(delta(device.draw_latency_nanos, old.draw_latency_nanos), delta(device.draws, old.draws)),
+                dropped_frames_per_second: rate(device.dropped_frames, old.dropped_frames),
+                frames_total: device.frames,
+                errors_total: device.errors,
+            }
+        }).collect();
+        TelemetryReport { capture, audio, devices }
+    }
+}
+
+/// The increase of a counter. Counters start over when their component is recreated (e.g. when a device is
+/// reconfigured), in which case the whole new value counts as the increase.
+fn delta(new: u64, old: u64) -> u64 {
+    if new >= old { new - old } else { new }
+}
+
+fn average_ms(total_nanos: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total_nanos as f64 / count as f64 / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(frames_sent: u64, device_frames: u64) -> Counters {
+        Counters {
+            capture: CaptureCounters { frames_sent, frames_captured: frames_sent, capture_nanos: frames_sent * 2_000_000, capture_errors: 0 },
+            audio: AudioCounters::default(),
+            devices: vec![DeviceCounters {
+                name: "Desk".to_string(),
+                samples: device_frames,
+                sampling_nanos: device_frames * 1_000_000,
+                frames: device_frames,
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_rolling_rates() {
+        let mut telemetry = RollingTelemetry::new(Duration::from_secs(2));
+        let start = Instant::now();
+        let report = telemetry.record_at(start, counters(0, 0));
+        assert_eq!(report.capture.fps, 0.0);
+
+        telemetry.record_at(start + Duration::from_secs(1), counters(10, 10));
+        let report = telemetry.record_at(start + Duration::from_secs(2), counters(30, 20));
+        assert_eq!(report.capture.fps, 15.0);
+        assert_eq!(report.capture.avg_capture_ms, 2.0);
+        assert_eq!(report.capture.frames_total, 30);
+        assert_eq!(report.devices[0].fps, 10.0);
+        assert_eq!(report.devices[0].avg_sampling_ms, 1.0);
+
+        // Older snapshots fall out of the window
+        let report = telemetry.record_at(start + Duration::from_secs(3), counters(60, 30));
+        assert_eq!(report.capture.fps, 25.0);
+
+        // Restarted devices start counting from zero
+        let report = telemetry.record_at(start + Duration::from_secs(4), counters(80, 4));
+        assert_eq!(report.devices[0].fps, 2.0);
+    }
+}
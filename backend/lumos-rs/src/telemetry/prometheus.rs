@@ -0,0 +1,125 @@
+//! Serves [TelemetryReport]s over HTTP, in the Prometheus text exposition format.
+use std::fmt::Write;
+use std::future::Future;
+
+use log::{debug, info, warn};
+use simple_error::{SimpleError, SimpleResult};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use super::TelemetryReport;
+
+/// Requests larger than this are rejected; scrapers only send a few short headers.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Starts an HTTP server on localhost, serving the latest report from `reports` at `/metrics`.
+///
+/// Returns the task running the server.
+pub async fn run_metrics_server(port: u32, reports: watch::Receiver<TelemetryReport>, cancel_token: CancellationToken) -> SimpleResult<impl Future<Output=()>> {
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr).await.map_err(SimpleError::from)?;
+    info!("Serving metrics on: http://{}/metrics", addr);
+    Ok(async move {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => {
+                    match conn {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_request(stream, reports.clone()));
+                        },
+                        Err(e) => warn!("Failed to accept metrics connection: {}", e),
+                    }
+                },
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+        debug!("Shutting down metrics server");
+    })
+}
+
+async fn handle_request(mut stream: TcpStream, reports: watch::Receiver<TelemetryReport>) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    // Only the request line matters, but the whole header is read so the client doesn't see a reset connection
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => request.extend_from_slice(&buffer[..n]),
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return;
+        }
+    }
+    let request_line = String::from_utf8_lossy(request.split(|b| *b == b'\r').next().unwrap_or_default()).into_owned();
+    let mut parts = request_line.split(' ');
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = render(&reports.borrow());
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+        },
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        debug!("Failed to send metrics: {}", e);
+    }
+}
+
+/// Renders a report in the Prometheus text exposition format.
+pub fn render(report: &TelemetryReport) -> String {
+    let mut out = String::new();
+    let capture = &report.capture;
+    metric(&mut out, "lumos_capture_fps", "gauge", "Frames sent to devices per second", &[("", capture.fps)]);
+    metric(&mut out, "lumos_capture_captured_fps", "gauge", "New frames captured from the desktop per second", &[("", capture.captured_fps)]);
+    metric(&mut out, "lumos_capture_duration_ms", "gauge", "Average time to capture a frame", &[("", capture.avg_capture_ms)]);
+    metric(&mut out, "lumos_capture_frames_total", "counter", "Frames sent to devices", &[("", capture.frames_total as f64)]);
+    metric(&mut out, "lumos_capture_errors_total", "counter", "Failed desktop captures", &[("", capture.errors_total as f64)]);
+    metric(&mut out, "lumos_audio_values_per_second", "gauge", "Audio intensity values per second", &[("", report.audio.values_per_second)]);
+    metric(&mut out, "lumos_audio_idle_values_per_second", "gauge", "Constant intensity values per second while no audio is playing",
+        &[("", report.audio.idle_values_per_second)]);
+
+    let labels: Vec<String> = report.devices.iter().map(|device| format!("{{device=\"{}\"}}", escape_label(&device.name))).collect();
+    let device_metric = |out: &mut String, name: &str, kind: &str, help: &str, value: fn(&super::DeviceReport) -> f64| {
+        let samples: Vec<(&str, f64)> = report.devices.iter().zip(&labels).map(|(device, label)| (label.as_str(), value(device))).collect();
+        metric(out, name, kind, help, &samples);
+    };
+    device_metric(&mut out, "lumos_device_fps", "gauge", "Frames handed to the output per second", |d| d.fps);
+    device_metric(&mut out, "lumos_device_sampling_ms", "gauge", "Average time to sample a frame", |d| d.avg_sampling_ms);
+    device_metric(&mut out, "lumos_device_transformation_ms", "gauge", "Average time from sampling until a frame is transformed", |d| d.avg_transformation_ms);
+    device_metric(&mut out, "lumos_device_draw_latency_ms", "gauge", "Average time from handing a frame to the output until it was drawn", |d| d.avg_draw_latency_ms);
+    device_metric(&mut out, "lumos_device_dropped_frames_per_second", "gauge", "Frames replaced before the output could draw them, per second", |d| d.dropped_frames_per_second);
+    device_metric(&mut out, "lumos_device_frames_total", "counter", "Frames handed to the output", |d| d.frames_total as f64);
+    device_metric(&mut out, "lumos_device_errors_total", "counter", "Failed attempts to connect or draw to the output", |d| d.errors_total as f64);
+    out
+}
+
+/// Writes a metric family, with one sample per `(labels, value)` pair.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for (labels, value) in samples {
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DeviceReport;
+
+    #[test]
+    fn test_render() {
+        let report = TelemetryReport {
+            devices: vec![DeviceReport { name: "Desk \"left\"".to_string(), fps: 14.5, ..Default::default() }],
+            ..Default::default()
+        };
+        let text = render(&report);
+        assert!(text.contains("# TYPE lumos_capture_fps gauge\nlumos_capture_fps 0\n"));
+        assert!(text.contains("\nlumos_device_fps{device=\"Desk \\\"left\\\"\"} 14.5\n"));
+    }
+}
@@ -0,0 +1,867 @@
+use color::RgbF32;
+use futures::{SinkExt, StreamExt};
+use futures::stream::SplitSink;
+use log::{info, debug, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use simple_error::{SimpleError, SimpleResult, try_with};
+
+use crate::common::RgbVec;
+use crate::render_service::{self, DevicePreview, Source, SourceContent};
+use crate::render_service::specification::{DeviceSpecification, OutputSpecification, SamplingType, AudioSamplingParameters, HsvAdjustment, DeviceOverride, DeviceEffect};
+use crate::outputs::{SerialProtocol, SERIAL_DEFAULT_BAUD_RATE};
+use crate::profiles::{self, ApplicationProfile};
+use crate::store::StoredConfig;
+use crate::telemetry::TelemetryReport;
+
+/// The messages exchanged with clients
+pub mod protocol;
+pub use protocol::Event;
+use protocol::{ClientMessage, ClientMessageHeader, ItemError, Request, Response, ResponseData, ServerMessage, ServerMessageContents, PROTOCOL_VERSION};
+
+pub enum Frame {
+    /// The devices that were parsed successfully, along with all entries from the request (which are persisted)
+    Devices {
+        devices: Vec<DeviceSpecification>,
+        entries: Vec<protocol::DeviceEntry>,
+    },
+    /// The profiles that were parsed successfully, along with all entries from the request (which are persisted)
+    Profiles {
+        profiles: Vec<profiles::ApplicationProfile>,
+        entries: Vec<protocol::ProfileEntry>,
+    },
+    AudioDevices(Vec<String>),
+    Shutdown,
+    Paused(bool),
+    Brightness(f32),
+    Enabled(bool),
+    ForceProfile {
+        profile_id: u32,
+        monitor: u32,
+    },
+    ClearForcedProfile,
+    SolidColor {
+        color: RgbF32,
+        duration: Option<Duration>,
+    },
+    ClearSolidColor,
+    /// Shows colors from another source than the desktop capture (see [render_service::SourceMux]).
+    Source(Source),
+    ClearSource(String),
+    /// Colors sent as a binary message (see [crate::input]).
+    Input(crate::input::InputPacket),
+}
+
+/// Sends [Event]s to all connected clients.
+#[derive(Clone)]
+pub struct Broadcaster {
+    tx: broadcast::Sender<String>,
+}
+
+impl Broadcaster {
+    pub fn send(&self, event: Event) {
+        let message = ServerMessage::new(ServerMessageContents::Event(event)).to_json();
+        // Sending only fails if no clients are connected
+        let _ = self.tx.send(message);
+    }
+}
+
+/// The parts of the application that clients can query or subscribe to.
+#[derive(Clone)]
+pub struct ServerContext {
+    /// The colors drawn to each device (see [Request::SubscribePreview]).
+    pub previews: broadcast::Sender<DevicePreview>,
+    /// The latest runtime metrics (see [Request::GetStatus]).
+    pub telemetry: watch::Receiver<TelemetryReport>,
+    /// The stored configuration (see [Request::GetConfig]).
+    pub config: watch::Receiver<StoredConfig>,
+    /// The sources of colors (see [Request::GetSources]).
+    pub sources: watch::Receiver<render_service::SourceMux>,
+}
+
+/// Starts a websocket server listening on localhost.
+///
+/// Returns `(task, frames, broadcaster)`, where `task` runs the server, `frames` are the messages received from clients
+/// and `broadcaster` sends messages to all connected clients.
+pub async fn run_websocket_server(port: u32, context: ServerContext, cancel_token: CancellationToken)
+        -> SimpleResult<(impl Future<Output=()>, impl Stream<Item=Frame>, Broadcaster)> {
+    let (frame_tx, frame_rx) = mpsc::channel(16);
+    let (outgoing_tx, _) = broadcast::channel(16);
+    let broadcaster = Broadcaster { tx: outgoing_tx.clone() };
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr).await.map_err(SimpleError::from)?;
+    info!("Websocket listening on: {}", &addr);
+    let task = async move {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => {
+                    if let Ok((stream, client_addr)) = conn {
+                        tokio::spawn(handle_connection(stream, frame_tx.clone(), outgoing_tx.subscribe(), context.clone(), client_addr));
+                    } else {
+                        break;
+                    }
+                },
+                _ = cancel_token.cancelled() => {
+                    break;
+                }
+            };
+        }
+        debug!("Shutting down websocket server");
+    };
+    Ok((task, ReceiverStream::new(frame_rx), broadcaster))
+}
+
+/// What to do in response to a message from a client.
+enum Command {
+    /// Hand the frame to the application.
+    Forward(Frame),
+    /// Change the preview subscription of the client to the given minimum interval between previews. [None]
+    /// unsubscribes.
+    SetPreviewRate(Option<Duration>),
+    /// List the available hardware, and include it in the response.
+    Discover,
+}
+
+/// Messages for a single client, in addition to those sent to all clients.
+enum ConnectionMessage {
+    Response(String),
+    SetPreviewRate(Option<Duration>),
+}
+
+async fn handle_connection(raw_stream: TcpStream, frame_tx: mpsc::Sender<Frame>, outgoing: broadcast::Receiver<String>,
+                           context: ServerContext, client_addr: SocketAddr) {
+    info!("Accepted connection from: {}", client_addr);
+
+    let ws_stream = match tokio_tungstenite::accept_async(raw_stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            warn!("Websocket handshake with {} failed: {}", client_addr, e);
+            return;
+        },
+    };
+
+    let (ws_sink, mut ws_source) = ws_stream.split();
+    let (connection_tx, connection_rx) = mpsc::unbounded_channel();
+    let receive = async {
+        while let Some(raw_msg) = ws_source.next().await {
+            let text = match raw_msg {
+                Ok(Message::Text(text)) => text,
+                // Binary messages push colors, and aren't answered so they can be streamed
+                Ok(Message::Binary(data)) => {
+                    match crate::input::decode_packet(&data) {
+                        Ok(packet) => if frame_tx.send(Frame::Input(packet)).await.is_err() {
+                            break;
+                        },
+                        Err(e) => warn!("Invalid input packet from {}: {}", client_addr, e),
+                    }
+                    continue;
+                },
+                Ok(Message::Close(_)) => break,
+                // Pings are answered by tungstenite itself
+                Ok(Message::Ping(_) | Message::Pong(_)) => continue,
+                Ok(_) => {
+                    warn!("Ignoring unsupported message from {}", client_addr);
+                    continue;
+                },
+                Err(e) => {
+                    warn!("Connection to {} failed: {}", client_addr, e);
+                    break;
+                },
+            };
+            let (mut response, command) = handle_message(&text, &context);
+            match command {
+                Some(Command::Forward(frame)) => {
+                    if frame_tx.send(frame).await.is_err() {
+                        warn!("Received websocket message after, but handler has already been closed");
+                        break;
+                    }
+                },
+                Some(Command::SetPreviewRate(min_interval)) => {
+                    let _ = connection_tx.send(ConnectionMessage::SetPreviewRate(min_interval));
+                },
+                Some(Command::Discover) => {
+                    response.data = Some(ResponseData::Discovery(crate::discovery::discover().await));
+                },
+                None => {},
+            }
+            if let Some(error) = &response.error {
+                warn!("Rejected message from {}: {}", client_addr, error);
+            }
+            let response = ServerMessage::new(ServerMessageContents::Response(response)).to_json();
+            let _ = connection_tx.send(ConnectionMessage::Response(response));
+        }
+    };
+
+    tokio::select! {
+        _ = receive => {},
+        _ = forward_outgoing(ws_sink, outgoing, connection_rx, context.previews.clone(), client_addr) => {},
+    }
+    info!("Disconnected from {}", client_addr);
+}
+
+/// The preview subscription of a single client.
+struct PreviewSubscription {
+    previews: broadcast::Receiver<DevicePreview>,
+    min_interval: Duration,
+    /// When a preview was last sent for each device.
+    last_sent: HashMap<Arc<str>, Instant>,
+}
+
+impl PreviewSubscription {
+    /// Waits for the next preview that should be sent to the client, skipping those that exceed the rate limit.
+    async fn next(&mut self) -> Option<DevicePreview> {
+        loop {
+            match self.previews.recv().await {
+                Ok(preview) => {
+                    let now = Instant::now();
+                    let due = match self.last_sent.get(&preview.device_name) {
+                        Some(last_sent) => now.duration_since(*last_sent) >= self.min_interval,
+                        None => true,
+                    };
+                    if due {
+                        self.last_sent.insert(preview.device_name.clone(), now);
+                        return Some(preview);
+                    }
+                },
+                // Previews are only useful while they're fresh, so missing some is fine
+                Err(broadcast::error::RecvError::Lagged(_)) => {},
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Sends all messages from `outgoing` and `connection`, and any subscribed previews, to the client, until it disconnects.
+async fn forward_outgoing(mut ws_sink: SplitSink<WebSocketStream<TcpStream>, Message>, mut outgoing: broadcast::Receiver<String>,
+                          mut connection: mpsc::UnboundedReceiver<ConnectionMessage>, previews: broadcast::Sender<DevicePreview>,
+                          client_addr: SocketAddr) {
+    let mut subscription: Option<PreviewSubscription> = None;
+    loop {
+        let msg = tokio::select! {
+            msg = outgoing.recv() => match msg {
+                Ok(msg) => Message::Text(msg),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Client {} missed {} message(s)", client_addr, n);
+                    continue;
+                },
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            msg = connection.recv() => match msg {
+                Some(ConnectionMessage::Response(msg)) => Message::Text(msg),
+                Some(ConnectionMessage::SetPreviewRate(min_interval)) => {
+                    subscription = min_interval.map(|min_interval| PreviewSubscription {
+                        previews: previews.subscribe(),
+                        min_interval,
+                        last_sent: HashMap::new(),
+                    });
+                    continue;
+                },
+                None => break,
+            },
+            Some(preview) = async { subscription.as_mut()?.next().await } => {
+                Message::Binary(protocol::encode_preview(&preview.device_name, &preview.colors))
+            },
+        };
+        if ws_sink.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Parses and validates a message from a client.
+///
+/// Returns the response to send to the client, and what to do if the message was accepted.
+fn handle_message(text: &str, context: &ServerContext) -> (Response, Option<Command>) {
+    let msg = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(msg) => msg,
+        Err(e) => {
+            // Report the error using whatever can still be parsed from the message
+            let header = serde_json::from_str::<ClientMessageHeader>(text).ok();
+            let id = header.as_ref().and_then(|header| header.id);
+            let error = match header.and_then(|header| header.version) {
+                Some(version) if version != PROTOCOL_VERSION => unsupported_version(version),
+                _ => format!("Invalid message: {}", e),
+            };
+            return (Response::error(id, error), None);
+        },
+    };
+    if msg.version != PROTOCOL_VERSION {
+        return (Response::error(msg.id, unsupported_version(msg.version)), None);
+    }
+
+    let mut item_errors = Vec::new();
+    let mut data = None;
+    let frame = match msg.request {
+        Request::SubscribePreview { max_fps } => {
+            // Tiny rates give intervals too long for a Duration
+            let min_interval = match Duration::try_from_secs_f64(1.0 / max_fps as f64) {
+                Ok(min_interval) if max_fps > 0.0 && max_fps.is_finite() => min_interval,
+                _ => return (Response::error(msg.id, format!("Preview rate must be positive, was {}", max_fps)), None),
+            };
+            return (Response::ok(msg.id), Some(Command::SetPreviewRate(Some(min_interval))));
+        },
+        Request::UnsubscribePreview => return (Response::ok(msg.id), Some(Command::SetPreviewRate(None))),
+        Request::GetStatus => {
+            let status = context.telemetry.borrow().clone();
+            return (Response { data: Some(ResponseData::Status(status)), ..Response::ok(msg.id) }, None);
+        },
+        Request::Discover => return (Response::ok(msg.id), Some(Command::Discover)),
+        Request::GetConfig => {
+            let config = context.config.borrow().clone();
+            return (Response { data: Some(ResponseData::Config(config)), ..Response::ok(msg.id) }, None);
+        },
+        Request::GetSources => {
+            let now = Instant::now();
+            let sources = context.sources.borrow().live_sources(now)
+                .map(|source| protocol::SourceInfo {
+                    name: source.name.to_string(),
+                    priority: source.priority,
+                    remaining_ms: source.until.map(|until| (until - now).as_millis() as u64),
+                    opacity: source.opacity,
+                    kind: match source.content {
+                        SourceContent::Capture => "capture",
+                        SourceContent::Color(_) => "color",
+                        SourceContent::Effect(_) => "effect",
+                        SourceContent::Colors(_) => "colors",
+                        SourceContent::Image(_) => "image",
+                    },
+                })
+                .collect();
+            return (Response { data: Some(ResponseData::Sources(sources)), ..Response::ok(msg.id) }, None);
+        },
+        Request::ParseAreas { text } => {
+            return match profiles::area_syntax::parse(&text) {
+                Ok(areas) => {
+                    match compose_areas(&areas) {
+                        Ok(composed) => {
+                            let parsed = protocol::ParsedAreas { areas: composed, text: profiles::area_syntax::format(&areas) };
+                            (Response { data: Some(ResponseData::Areas(parsed)), ..Response::ok(msg.id) }, None)
+                        },
+                        Err(e) => (Response::error(msg.id, e.to_string()), None),
+                    }
+                },
+                Err(e) => (Response::error(msg.id, e.to_string()), None),
+            };
+        },
+        Request::SetDevices { devices: entries } => {
+            let (devices, errors) = parse_devices(&entries);
+            item_errors = errors;
+            let pending_devices = devices.iter().map(|device| device.name.clone()).collect();
+            data = Some(ResponseData::PendingDevices(protocol::PendingDevices { pending_devices }));
+            Frame::Devices { devices, entries }
+        },
+        Request::SetProfiles { profiles: entries } => {
+            let (profiles, errors) = parse_profiles(&entries);
+            item_errors = errors;
+            Frame::Profiles { profiles, entries }
+        },
+        Request::SetAudioDevices { audio_devices } => Frame::AudioDevices(audio_devices),
+        Request::Shutdown => Frame::Shutdown,
+        Request::SetPaused { paused } => Frame::Paused(paused),
+        Request::SetBrightness { brightness } => {
+            if !(0.0..=1.0).contains(&brightness) {
+                return (Response::error(msg.id, format!("Brightness must be between 0 and 1, was {}", brightness)), None);
+            }
+            Frame::Brightness(brightness)
+        },
+        Request::SetEnabled { enabled } => Frame::Enabled(enabled),
+        Request::ForceProfile { profile_id, monitor } => Frame::ForceProfile { profile_id, monitor: monitor.unwrap_or(0) },
+        Request::ClearForcedProfile => Frame::ClearForcedProfile,
+        Request::ShowSolidColor { color, duration_ms } => {
+            match parse_color(color) {
+                Ok(color) => Frame::SolidColor { color, duration: duration_ms.map(Duration::from_millis) },
+                Err(e) => return (Response::error(msg.id, e.to_string()), None),
+            }
+        },
+        Request::ClearSolidColor => Frame::ClearSolidColor,
+        Request::SetSource { name, priority, content, duration_ms, opacity } => {
+            match parse_source(&name, priority, &content, duration_ms, opacity.unwrap_or(1.0)) {
+                Ok(source) => Frame::Source(source),
+                Err(e) => return (Response::error(msg.id, e.to_string()), None),
+            }
+        },
+        Request::ClearSource { name } => {
+            if name == render_service::CAPTURE_SOURCE {
+                return (Response::error(msg.id, "The capture source can only be changed in the configuration file".to_string()), None);
+            }
+            Frame::ClearSource(name)
+        },
+    };
+    let response = Response {
+        item_errors,
+        data,
+        ..Response::ok(msg.id)
+    };
+    (response, Some(Command::Forward(frame)))
+}
+
+fn unsupported_version(version: u32) -> String {
+    format!("Unsupported protocol version {}, expected {}", version, PROTOCOL_VERSION)
+}
+
+/// Parses the enabled devices in `entries`. Returns the devices that were parsed successfully, and errors for the others.
+pub fn parse_devices(entries: &[protocol::DeviceEntry]) -> (Vec<DeviceSpecification>, Vec<ItemError>) {
+    let mut devices = Vec::new();
+    let mut errors = Vec::new();
+    for (index, entry) in entries.iter().enumerate().filter(|(_, entry)| entry.enabled) {
+        match parse_device(entry.device.clone()) {
+            Ok(device) => devices.push(device),
+            Err(e) => {
+                warn!("Skipping device '{}': {}", entry.device.name, e);
+                errors.push(ItemError { index, message: e.to_string() });
+            },
+        }
+    }
+    (devices, errors)
+}
+
+/// Parses `entries`. Returns the profiles that were parsed successfully, and errors for the others.
+pub fn parse_profiles(entries: &[protocol::ProfileEntry]) -> (Vec<ApplicationProfile>, Vec<ItemError>) {
+    let mut profiles = Vec::new();
+    let mut errors = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match parse_profile(entry) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => {
+                warn!("Skipping profile {}: {}", entry.id, e);
+                errors.push(ItemError { index, message: e.to_string() });
+            },
+        }
+    }
+    (profiles, errors)
+}
+
+fn parse_device(device_raw: protocol::DeviceSpec) -> SimpleResult<DeviceSpecification> {
+    if device_raw.number_of_leds == 0 {
+        return Err(SimpleError::new("Device must have at least one LED"));
+    }
+    let output = match device_raw.output {
+        protocol::OutputSpec::Wled { ip_address } => OutputSpecification::Wled {
+            address: ip_address,
+            port: 21324,
+        },
+        protocol::OutputSpec::Qmk { vendor_id, product_id } => OutputSpecification::Qmk { vendor_id, product_id },
+        protocol::OutputSpec::Serial { port_name, baud_rate, protocol } => {
+            if baud_rate == Some(0) {
+                return Err(SimpleError::new("Baud rate must be positive"));
+            }
+            OutputSpecification::Serial {
+                port_name,
+                baud_rate: baud_rate.unwrap_or(SERIAL_DEFAULT_BAUD_RATE),
+                protocol: match protocol {
+                    None | Some(protocol::SerialProtocolSpec::Adalight) => SerialProtocol::Adalight,
+                    Some(protocol::SerialProtocolSpec::Awa) => SerialProtocol::Awa,
+                },
+            }
+        },
+    };
+    let sampling_type = match device_raw.sampling_type {
+        protocol::SamplingTypeSpec::Horizontal => SamplingType::Horizontal,
+        protocol::SamplingTypeSpec::Vertical => SamplingType::Vertical,
+    };
+    Ok(DeviceSpecification {
+        name: device_raw.name,
+        size: device_raw.number_of_leds as usize,
+        output,
+        sampling_type,
+        hsv_adjustments: Some(HsvAdjustment{ hue: 0.0, value: device_raw.value_adjustment as f32 / 100.0, saturation: device_raw.saturation_adjustment as f32 / 100.0}),
+        smoothing: None,
+        audio_sampling: if device_raw.audio_amount > 0.0 { Some(AudioSamplingParameters{ amount: device_raw.audio_amount / 100.0 }) } else { None },
+        gamma: device_raw.gamma,
+        fallback_color: RgbF32 { red: device_raw.fallback_color.0, green: device_raw.fallback_color.1, blue: device_raw.fallback_color.2 },
+        brightness: 1.0,
+        enabled: true,
+        effect: None,
+    })
+}
+
+fn parse_profile(profile_raw: &protocol::ProfileEntry) -> SimpleResult<ApplicationProfile> {
+    let title = profiles::WindowCondition::Title(try_with!(regex::Regex::new(&profile_raw.regex), "Invalid title regex"));
+    let condition = match &profile_raw.condition {
+        None => title,
+        Some(condition) if profile_raw.regex.is_empty() => parse_condition(condition)?,
+        Some(condition) => profiles::WindowCondition::All(vec![title, parse_condition(condition)?]),
+    };
+    let areas = match &profile_raw.areas {
+        protocol::AreasSpec::List(areas_raw) => parse_areas(areas_raw)?,
+        protocol::AreasSpec::Text(text) => try_with!(profiles::area_syntax::parse(text), "Invalid areas"),
+    };
+    let mut device_overrides = HashMap::new();
+    for override_raw in &profile_raw.devices {
+        let device_override = try_with!(parse_device_override(override_raw), "Invalid settings for device '{}'", override_raw.device);
+        device_overrides.insert(override_raw.device.clone(), device_override);
+    }
+    Ok(profiles::ApplicationProfile{
+        id: profile_raw.id,
+        priority: profile_raw.priority,
+        condition,
+        areas,
+        device_overrides,
+    })
+}
+
+fn parse_areas(areas_raw: &[protocol::AreaSpecification]) -> SimpleResult<Vec<profiles::MonitorAreaSpecification>> {
+    let mut areas = Vec::new();
+    for area_raw in areas_raw {
+        let resolution = area_raw.selector.as_ref().map(|dim| (dim.width, dim.height));
+        areas.push(profiles::MonitorAreaSpecification{
+            resolution,
+            is_horizontal: area_raw.direction != protocol::DirectionSpec::Vertical,
+            is_vertical: area_raw.direction != protocol::DirectionSpec::Horizontal,
+            left: parse_monitor_distance(&area_raw.x)?,
+            top: parse_monitor_distance(&area_raw.y)?,
+            width: parse_monitor_distance(&area_raw.width)?,
+            height: parse_monitor_distance(&area_raw.height)?,
+        })
+    }
+    Ok(areas)
+}
+
+/// The inverse of [parse_areas].
+fn compose_areas(areas: &[profiles::MonitorAreaSpecification]) -> SimpleResult<Vec<protocol::AreaSpecification>> {
+    let compose_distance = |distance| -> SimpleResult<protocol::MonitorDistance> {
+        Ok(match distance {
+            profiles::MonitorDistance::Pixels(px) => protocol::MonitorDistance {
+                px: Some(i32::try_from(px).map_err(|_| SimpleError::new(format!("Pixel value {} is too large", px)))?),
+                percentage: None,
+            },
+            profiles::MonitorDistance::Proportion(proportion) => protocol::MonitorDistance {
+                px: None,
+                percentage: Some(profiles::area_syntax::to_percentage(proportion) as f32),
+            },
+        })
+    };
+    areas.iter()
+        .map(|area| Ok(protocol::AreaSpecification {
+            selector: area.resolution.map(|(width, height)| protocol::MonitorDimensions { width, height }),
+            direction: match (area.is_horizontal, area.is_vertical) {
+                (true, false) => protocol::DirectionSpec::Horizontal,
+                (false, true) => protocol::DirectionSpec::Vertical,
+                _ => protocol::DirectionSpec::Both,
+            },
+            width: compose_distance(area.width)?,
+            height: compose_distance(area.height)?,
+            x: compose_distance(area.left)?,
+            y: compose_distance(area.top)?,
+        }))
+        .collect()
+}
+
+fn parse_device_override(override_raw: &protocol::DeviceOverrideSpec) -> SimpleResult<DeviceOverride> {
+    if let Some(brightness) = override_raw.brightness && !(0.0..=1.0).contains(&brightness) {
+        return Err(SimpleError::new(format!("Brightness must be between 0 and 1, was {}", brightness)));
+    }
+    let effect = override_raw.effect.as_ref().map(parse_effect).transpose()?;
+    Ok(DeviceOverride {
+        enabled: override_raw.enabled,
+        brightness: override_raw.brightness,
+        hue: override_raw.hue_adjustment,
+        saturation: override_raw.saturation_adjustment.map(|saturation| saturation as f32 / 100.0),
+        value: override_raw.value_adjustment.map(|value| value as f32 / 100.0),
+        audio_amount: override_raw.audio_amount.map(|amount| amount / 100.0),
+        gamma: override_raw.gamma,
+        sampling_type: override_raw.sampling_type.as_ref().map(|sampling_type| match sampling_type {
+            protocol::SamplingTypeSpec::Horizontal => SamplingType::Horizontal,
+            protocol::SamplingTypeSpec::Vertical => SamplingType::Vertical,
+        }),
+        effect,
+    })
+}
+
+fn parse_color((red, green, blue): (f32, f32, f32)) -> SimpleResult<RgbF32> {
+    if ![red, green, blue].iter().all(|component| (0.0..=1.0).contains(component)) {
+        return Err(SimpleError::new(format!("Color components must be between 0 and 1, was {:?}", (red, green, blue))));
+    }
+    Ok(RgbF32 { red, green, blue })
+}
+
+fn parse_effect(effect_raw: &protocol::EffectSpec) -> SimpleResult<DeviceEffect> {
+    Ok(match *effect_raw {
+        protocol::EffectSpec::SolidColor { color } => DeviceEffect::SolidColor(parse_color(color)?),
+        protocol::EffectSpec::Breathing { color, period_ms } => {
+            if period_ms == 0 {
+                return Err(SimpleError::new("Effect period must be positive"));
+            }
+            DeviceEffect::Breathing { color: parse_color(color)?, period: Duration::from_millis(period_ms) }
+        },
+    })
+}
+
+/// Parses a source for [Request::SetSource], which is live from now on.
+fn parse_source(name: &str, priority: i32, content_raw: &protocol::SourceContentSpec, duration_ms: Option<u64>, opacity: f32) -> SimpleResult<Source> {
+    if name == render_service::CAPTURE_SOURCE {
+        return Err(SimpleError::new("The capture source can only be changed in the configuration file"));
+    }
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(SimpleError::new(format!("Opacity must be between 0 and 1, was {}", opacity)));
+    }
+    let content = match content_raw {
+        protocol::SourceContentSpec::Color { color } => SourceContent::Color(parse_color(*color)?),
+        protocol::SourceContentSpec::Effect { effect } => SourceContent::Effect(parse_effect(effect)?),
+        protocol::SourceContentSpec::Colors { devices } => SourceContent::Colors(devices.iter()
+            .map(|(device, colors)| Ok((device.clone(), Arc::new(colors.iter().copied().map(parse_color).collect::<SimpleResult<RgbVec>>()?))))
+            .collect::<SimpleResult<_>>()?),
+    };
+    Ok(Source {
+        name: Arc::from(name),
+        priority,
+        until: duration_ms.map(|duration_ms| Instant::now() + Duration::from_millis(duration_ms)),
+        content,
+        opacity,
+    })
+}
+
+/// Parses a condition on the focused window, e.g. from [protocol::ProfileEntry::condition].
+pub fn parse_condition(condition_raw: &protocol::ConditionSpec) -> SimpleResult<profiles::WindowCondition> {
+    let regex = |regex: &str, name| regex::Regex::new(regex).map_err(|e| SimpleError::new(format!("Invalid {} regex: {}", name, e)));
+    let all = |conditions: &[protocol::ConditionSpec]| conditions.iter().map(parse_condition).collect::<SimpleResult<Vec<_>>>();
+    Ok(match condition_raw {
+        protocol::ConditionSpec::Title(title) => profiles::WindowCondition::Title(regex(title, "title")?),
+        protocol::ConditionSpec::ProcessName(name) => profiles::WindowCondition::ProcessName(regex(name, "process name")?),
+        protocol::ConditionSpec::WindowClass(class) => profiles::WindowCondition::Class(regex(class, "window class")?),
+        protocol::ConditionSpec::Fullscreen(fullscreen) => profiles::WindowCondition::Fullscreen(*fullscreen),
+        protocol::ConditionSpec::All(conditions) => profiles::WindowCondition::All(all(conditions)?),
+        protocol::ConditionSpec::Any(conditions) => profiles::WindowCondition::Any(all(conditions)?),
+    })
+}
+
+fn parse_monitor_distance(distance_raw: &protocol::MonitorDistance) -> SimpleResult<profiles::MonitorDistance> {
+    if distance_raw.px.is_none() && distance_raw.percentage.is_none() {
+        return Err(SimpleError::new("Area must specify either px or percentage"));
+    }
+    if let Some(px) = distance_raw.px {
+        Ok(profiles::MonitorDistance::Pixels(px as isize))
+    } else {
+        let percentage = distance_raw.percentage.unwrap() / 100.0;
+        if !(0.0..=1.0).contains(&percentage) {
+            return Err(SimpleError::new(format!("Area percentage must be in [0.0, 1.0], was {}", percentage)));
+        }
+        Ok(profiles::MonitorDistance::Proportion(percentage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ServerContext {
+        ServerContext {
+            previews: broadcast::channel(1).0,
+            telemetry: watch::channel(TelemetryReport::default()).1,
+            config: watch::channel(StoredConfig::default()).1,
+            sources: watch::channel(render_service::SourceMux::default()).1,
+        }
+    }
+
+    fn device_json(name: &str, number_of_leds: u32) -> String {
+        format!(r#"{{"enabled": true, "device": {{"name": "{}", "numberOfLeds": {}, "samplingType": "horizontal",
+            "gamma": 2.2, "colorTemp": 6500, "saturationAdjustment": 0, "valueAdjustment": 0, "audioAmount": 0,
+            "fallbackColor": [0, 0, 0], "output": {{"type": "serial", "portName": "COM3", "protocol": "awa"}}}}}}"#,
+            name, number_of_leds)
+    }
+
+    #[test]
+    fn test_set_devices() {
+        let msg = format!(r#"{{"version": 1, "id": 7, "type": "setDevices", "devices": [{}, {}]}}"#,
+            device_json("Desk", 0), device_json("Shelf", 30));
+        let (response, frame) = handle_message(&msg, &context());
+        assert_eq!(response.id, Some(7));
+        assert_eq!(response.error, None);
+        // The invalid device is reported, and the valid one is still started
+        assert_eq!(response.item_errors.len(), 1);
+        assert_eq!(response.item_errors[0].index, 0);
+        let Some(Command::Forward(Frame::Devices { devices, entries })) = frame else { panic!("Expected devices") };
+        // All devices are persisted, even those that can't be started yet
+        assert_eq!(entries.len(), 2);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "Shelf");
+        // The valid device hasn't been opened yet, its state follows in an event
+        let Some(ResponseData::PendingDevices(pending)) = response.data else { panic!("Expected pending devices") };
+        assert_eq!(pending.pending_devices, vec!["Shelf".to_string()]);
+        assert_eq!(devices[0].output, OutputSpecification::Serial {
+            port_name: "COM3".to_string(),
+            baud_rate: SERIAL_DEFAULT_BAUD_RATE,
+            protocol: SerialProtocol::Awa,
+        });
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let entries: Vec<protocol::ProfileEntry> = serde_json::from_str(r#"[
+            {"id": 1, "regex": "^Game", "areas": [], "priority": 0},
+            {"id": 2, "condition": {"all": [{"processName": "^game\\.exe$"}, {"fullscreen": true}]}, "areas": [], "priority": 0},
+            {"id": 3, "regex": "^Game", "condition": {"any": [{"windowClass": "("}]}, "areas": [], "priority": 0},
+            {"id": 4, "regex": "^Horror", "areas": [], "priority": 0, "devices": [
+                {"device": "Desk", "brightness": 0.3, "saturationAdjustment": -50, "effect": {"type": "breathing", "color": [1, 0, 0], "periodMs": 2000}},
+                {"device": "Shelf", "enabled": false}
+            ]},
+            {"id": 5, "regex": "", "areas": [], "priority": 0, "devices": [{"device": "Desk", "brightness": 2}]},
+            {"id": 6, "regex": "", "areas": "* * { x: 0px; y: 0px; width: 100%; height: 10%; }", "priority": 0},
+            {"id": 7, "regex": "", "areas": "* * { x: 0px; }", "priority": 0}
+        ]"#).unwrap();
+        let (profiles, errors) = parse_profiles(&entries);
+        assert_eq!(profiles.len(), 4);
+        assert_eq!(errors.iter().map(|error| error.index).collect::<Vec<_>>(), vec![2, 4, 6]);
+        assert_eq!(profiles[3].areas.len(), 1);
+        let desk = &profiles[2].device_overrides["Desk"];
+        assert_eq!(desk.brightness, Some(0.3));
+        assert_eq!(desk.saturation, Some(-0.5));
+        assert_eq!(desk.effect, Some(DeviceEffect::Breathing { color: RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }, period: Duration::from_secs(2) }));
+        assert_eq!(profiles[2].device_overrides["Shelf"].enabled, Some(false));
+        assert!(matches!(profiles[0].condition, profiles::WindowCondition::Title(_)));
+        let profiles::WindowCondition::All(conditions) = &profiles[1].condition else { panic!("Expected all") };
+        assert!(matches!(conditions[..], [profiles::WindowCondition::ProcessName(_), profiles::WindowCondition::Fullscreen(true)]));
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let (response, frame) = handle_message(r#"{"version": 1, "id": 3, "type": "setAudioDevices"}"#, &context());
+        assert_eq!(response.id, Some(3));
+        assert!(response.error.is_some());
+        assert!(frame.is_none());
+
+        let (response, frame) = handle_message(r#"{"version": 2, "id": 4, "type": "shutdown"}"#, &context());
+        assert_eq!(response.id, Some(4));
+        assert_eq!(response.error, Some(unsupported_version(2)));
+        assert!(frame.is_none());
+
+        let (response, frame) = handle_message("not json", &context());
+        assert_eq!(response.id, None);
+        assert!(response.error.is_some());
+        assert!(frame.is_none());
+    }
+
+    #[test]
+    fn test_queries() {
+        let (response, command) = handle_message(r#"{"version": 1, "id": 1, "type": "getStatus"}"#, &context());
+        assert!(matches!(response.data, Some(ResponseData::Status(_))));
+        assert!(command.is_none());
+
+        // Discovery happens asynchronously, after the message has been handled
+        let (response, command) = handle_message(r#"{"version": 1, "id": 2, "type": "discover"}"#, &context());
+        assert!(response.data.is_none());
+        assert!(matches!(command, Some(Command::Discover)));
+
+        let (response, _) = handle_message(r#"{"version": 1, "id": 3, "type": "getConfig"}"#, &context());
+        assert!(matches!(response.data, Some(ResponseData::Config(_))));
+    }
+
+    #[test]
+    fn test_subscribe_preview() {
+        let (response, command) = handle_message(r#"{"version": 1, "id": 1, "type": "subscribePreview", "maxFps": 10}"#, &context());
+        assert!(response.error.is_none());
+        assert!(matches!(command, Some(Command::SetPreviewRate(Some(interval))) if interval == Duration::from_millis(100)));
+
+        for max_fps in ["0", "-1", "1e-38"] {
+            let msg = format!(r#"{{"version": 1, "id": 2, "type": "subscribePreview", "maxFps": {}}}"#, max_fps);
+            let (response, command) = handle_message(&msg, &context());
+            assert!(response.error.is_some(), "{} was accepted", max_fps);
+            assert!(command.is_none());
+        }
+    }
+
+    #[test]
+    fn test_parse_areas() {
+        let msg = r#"{"version": 1, "id": 1, "type": "parseAreas", "text": "1920x1080 - {x: 0px; y: 12.5%; width: 100%; height: 40px;}"}"#;
+        let (response, command) = handle_message(msg, &context());
+        assert!(command.is_none());
+        let Some(ResponseData::Areas(parsed)) = response.data else { panic!("Expected areas") };
+        assert_eq!(parsed.areas.len(), 1);
+        assert!(parsed.areas[0].selector == Some(protocol::MonitorDimensions { width: 1920, height: 1080 }));
+        assert!(parsed.areas[0].y == protocol::MonitorDistance { px: None, percentage: Some(12.5) });
+        assert_eq!(parse_areas(&parsed.areas).unwrap(), profiles::area_syntax::parse(&parsed.text).unwrap());
+
+        let (response, _) = handle_message(r#"{"version": 1, "id": 2, "type": "parseAreas", "text": "{x: 0px;"}"#, &context());
+        assert!(response.error.unwrap().contains("line 1"));
+    }
+
+    #[test]
+    fn test_control_requests() {
+        let (response, command) = handle_message(r#"{"version": 1, "id": 1, "type": "setBrightness", "brightness": 0.5}"#, &context());
+        assert!(response.error.is_none());
+        assert!(matches!(command, Some(Command::Forward(Frame::Brightness(brightness))) if brightness == 0.5));
+
+        let (response, command) = handle_message(r#"{"version": 1, "id": 2, "type": "setBrightness", "brightness": 1.5}"#, &context());
+        assert!(response.error.is_some());
+        assert!(command.is_none());
+
+        let (_, command) = handle_message(r#"{"version": 1, "id": 3, "type": "forceProfile", "profileId": 7}"#, &context());
+        assert!(matches!(command, Some(Command::Forward(Frame::ForceProfile { profile_id: 7, monitor: 0 }))));
+
+        let (_, command) = handle_message(r#"{"version": 1, "id": 4, "type": "showSolidColor", "color": [1.0, 0.0, 0.5], "durationMs": 2000}"#, &context());
+        match command {
+            Some(Command::Forward(Frame::SolidColor { color, duration })) => {
+                assert_eq!(color, RgbF32 { red: 1.0, green: 0.0, blue: 0.5 });
+                assert_eq!(duration, Some(Duration::from_secs(2)));
+            },
+            _ => panic!("Expected a solid color frame"),
+        }
+
+        let (response, _) = handle_message(r#"{"version": 1, "id": 5, "type": "showSolidColor", "color": [2.0, 0.0, 0.0]}"#, &context());
+        assert!(response.error.is_some());
+    }
+
+    #[test]
+    fn test_sources() {
+        let msg = r#"{"version": 1, "id": 1, "type": "setSource", "name": "alert", "priority": 10, "durationMs": 1000,
+            "content": {"type": "colors", "devices": {"Desk": [[1, 0, 0], [0, 0, 1]]}}}"#;
+        let (response, command) = handle_message(msg, &context());
+        assert!(response.error.is_none());
+        let Some(Command::Forward(Frame::Source(source))) = command else { panic!("Expected a source") };
+        assert_eq!((&*source.name, source.priority, source.opacity), ("alert", 10, 1.0));
+        assert!(source.until.is_some());
+        let SourceContent::Colors(colors) = &source.content else { panic!("Expected colors") };
+        assert_eq!(colors["Desk"].len(), 2);
+
+        let msg = r#"{"version": 1, "id": 2, "type": "setSource", "name": "capture", "priority": 10, "content": {"type": "color", "color": [1, 0, 0]}}"#;
+        assert!(handle_message(msg, &context()).0.error.is_some());
+        let msg = r#"{"version": 1, "id": 3, "type": "setSource", "name": "dim", "priority": 10, "opacity": 2, "content": {"type": "color", "color": [0, 0, 0]}}"#;
+        assert!(handle_message(msg, &context()).0.error.is_some());
+
+        let (response, command) = handle_message(r#"{"version": 1, "id": 4, "type": "clearSource", "name": "capture"}"#, &context());
+        assert!(response.error.is_some());
+        assert!(command.is_none());
+        let (_, command) = handle_message(r#"{"version": 1, "id": 5, "type": "clearSource", "name": "alert"}"#, &context());
+        assert!(matches!(command, Some(Command::Forward(Frame::ClearSource(name))) if name == "alert"));
+
+        let (response, _) = handle_message(r#"{"version": 1, "id": 6, "type": "getSources"}"#, &context());
+        let Some(ResponseData::Sources(sources)) = response.data else { panic!("Expected sources") };
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].kind, "capture");
+    }
+
+    #[test]
+    fn test_encode_preview() {
+        let colors = vec![RgbF32 { red: 1.0, green: 0.0, blue: 0.0 }, RgbF32 { red: 0.0, green: 0.0, blue: 1.0 }];
+        assert_eq!(protocol::encode_preview("Desk", &colors), [1, 4, 0, b'D', b'e', b's', b'k', 255, 0, 0, 0, 0, 255]);
+    }
+
+    #[tokio::test]
+    async fn test_preview_throttling() {
+        let (previews_tx, previews_rx) = broadcast::channel(16);
+        let mut subscription = PreviewSubscription {
+            previews: previews_rx,
+            min_interval: Duration::from_secs(60),
+            last_sent: HashMap::new(),
+        };
+        let preview = |name: &str| DevicePreview { device_name: Arc::from(name), colors: Arc::new(Vec::new()) };
+        previews_tx.send(preview("Desk")).unwrap();
+        previews_tx.send(preview("Desk")).unwrap();
+        previews_tx.send(preview("Shelf")).unwrap();
+        drop(previews_tx);
+        // Each device is throttled separately
+        assert_eq!(&*subscription.next().await.unwrap().device_name, "Desk");
+        assert_eq!(&*subscription.next().await.unwrap().device_name, "Shelf");
+        assert!(subscription.next().await.is_none());
+    }
+}
@@ -0,0 +1,467 @@
+//! The messages exchanged with websocket clients.
+//!
+//! All messages are JSON objects with a `version` field (see [PROTOCOL_VERSION]) and a `type` field. Clients send
+//! [Request]s, each of which is answered with a [Response]. The server also sends [Event]s whenever something of
+//! interest happens.
+//!
+//! For example, the request
+//! ```json
+//! { "version": 1, "id": 4, "type": "setAudioDevices", "audioDevices": ["Speakers"] }
+//! ```
+//! is answered with
+//! ```json
+//! { "version": 1, "type": "response", "id": 4, "error": null, "itemErrors": [], "data": null }
+//! ```
+//!
+//! Bulk data, such as LED previews, is sent as binary messages instead (see [encode_preview]).
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::RgbVec;
+use crate::discovery::Discovery;
+use crate::store::StoredConfig;
+use crate::telemetry::TelemetryReport;
+
+/// The version of the protocol. Must be incremented on any incompatible change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A message from a client.
+#[derive(Deserialize)]
+pub struct ClientMessage {
+    pub version: u32,
+    /// An identifier chosen by the client, which is included in the [Response] to this message.
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// The fields shared by all versions of [ClientMessage]. Used to report errors for messages that failed to parse.
+#[derive(Deserialize)]
+pub struct ClientMessageHeader {
+    pub version: Option<u32>,
+    pub id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Request {
+    /// Replaces all devices, and stores them (see [crate::store]).
+    ///
+    /// Devices are opened in the background, so the response only reports devices that are invalid. Whether the
+    /// others could be opened is reported by an [Event::DeviceState] for each of the [PendingDevices] in the response.
+    SetDevices {
+        devices: Vec<DeviceEntry>,
+    },
+    /// Replaces all profiles, and stores them (see [crate::store]).
+    SetProfiles {
+        profiles: Vec<ProfileEntry>,
+    },
+    /// Replaces the audio devices to capture from, and stores them (see [crate::store]).
+    #[serde(rename_all = "camelCase")]
+    SetAudioDevices {
+        audio_devices: Vec<String>,
+    },
+    /// Exits the application.
+    Shutdown,
+    /// Starts streaming the colors drawn to each device to this client (see [encode_preview]). Replaces any previous
+    /// subscription.
+    #[serde(rename_all = "camelCase")]
+    SubscribePreview {
+        /// The maximum number of previews to send per second, for each device.
+        max_fps: f32,
+    },
+    /// Stops streaming previews to this client.
+    UnsubscribePreview,
+    /// Requests the current runtime metrics. Answered with [ResponseData::Status].
+    GetStatus,
+    /// Lists the hardware that devices can be configured for. Answered with [ResponseData::Discovery].
+    Discover,
+    /// Requests the stored devices, profiles, audio devices and settings. Answered with [ResponseData::Config].
+    GetConfig,
+    /// Parses capture areas in the text syntax (see [crate::profiles::area_syntax]), e.g. to validate them. Answered
+    /// with [ResponseData::Areas], or an error with the line and column of the problem.
+    ParseAreas {
+        text: String,
+    },
+    /// Pauses or resumes all devices. Paused devices keep showing their last colors.
+    SetPaused {
+        paused: bool,
+    },
+    /// Sets the brightness of all devices, between 0 and 1, and stores it (see [crate::store]).
+    SetBrightness {
+        brightness: f32,
+    },
+    /// Turns all devices on or off. Devices that are off are black.
+    SetEnabled {
+        enabled: bool,
+    },
+    /// Uses a profile until [Request::ClearForcedProfile], regardless of which windows are focused.
+    #[serde(rename_all = "camelCase")]
+    ForceProfile {
+        profile_id: u32,
+        /// The monitor to use the profile on. Defaults to the first monitor.
+        monitor: Option<u32>,
+    },
+    /// Goes back to using the profiles of the focused windows.
+    ClearForcedProfile,
+    /// Shows a single color on all devices, even while paused.
+    #[serde(rename_all = "camelCase")]
+    ShowSolidColor {
+        /// The red, green and blue components, each between 0 and 1.
+        color: (f32, f32, f32),
+        /// How long to show the color for. If not given, the color is shown until [Request::ClearSolidColor].
+        duration_ms: Option<u64>,
+    },
+    /// Stops showing the color from [Request::ShowSolidColor].
+    ClearSolidColor,
+    /// Shows colors that contend with the desktop capture and other sources by priority (see
+    /// [crate::render_service::SourceMux]). Replaces any source with the same name.
+    #[serde(rename_all = "camelCase")]
+    SetSource {
+        name: String,
+        /// Sources with higher priorities cover those with lower ones. The desktop capture has priority 0, unless
+        /// configured otherwise.
+        priority: i32,
+        content: SourceContentSpec,
+        /// How long the source is shown for. If not given, it is shown until [Request::ClearSource].
+        duration_ms: Option<u64>,
+        /// How much the source covers the sources below it, between 0 and 1. Defaults to 1.
+        opacity: Option<f32>,
+    },
+    ClearSource {
+        name: String,
+    },
+    /// Lists the sources that are currently shown. Answered with [ResponseData::Sources].
+    GetSources,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeviceEntry {
+    pub enabled: bool,
+    pub device: DeviceSpec,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSpec {
+    pub name: String,
+    pub number_of_leds: u32,
+    pub sampling_type: SamplingTypeSpec,
+    pub gamma: f32,
+    pub color_temp: u32,
+    pub saturation_adjustment: u32,
+    pub value_adjustment: u32,
+    pub audio_amount: f32,
+    pub fallback_color: (f32, f32, f32),
+    pub output: OutputSpec,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SamplingTypeSpec {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum OutputSpec {
+    #[serde(rename_all = "camelCase")]
+    Wled {
+        ip_address: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Qmk {
+        vendor_id: u16,
+        product_id: u16,
+    },
+    #[serde(rename_all = "camelCase")]
+    Serial {
+        port_name: String,
+        baud_rate: Option<u32>,
+        protocol: Option<SerialProtocolSpec>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SerialProtocolSpec {
+    Adalight,
+    Awa,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProfileEntry {
+    pub id: u32,
+    /// Regex for the window title. Combined with `condition` (if any), and matches any title if empty.
+    #[serde(default)]
+    pub regex: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<ConditionSpec>,
+    pub areas: AreasSpec,
+    pub priority: i32,
+    /// Changes to device settings while the profile is active.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceOverrideSpec>,
+}
+
+/// Settings of a device to change while a profile is active. Settings that aren't given are left as they are.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceOverrideSpec {
+    /// The [DeviceSpec::name] of the device.
+    pub device: String,
+    pub enabled: Option<bool>,
+    /// Between 0 and 1.
+    pub brightness: Option<f32>,
+    pub sampling_type: Option<SamplingTypeSpec>,
+    pub gamma: Option<f32>,
+    /// In degrees.
+    pub hue_adjustment: Option<f32>,
+    /// In percent, like [DeviceSpec::saturation_adjustment], but may be negative.
+    pub saturation_adjustment: Option<i32>,
+    /// In percent, like [DeviceSpec::value_adjustment], but may be negative.
+    pub value_adjustment: Option<i32>,
+    /// In percent, like [DeviceSpec::audio_amount].
+    pub audio_amount: Option<f32>,
+    pub effect: Option<EffectSpec>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EffectSpec {
+    SolidColor {
+        color: (f32, f32, f32),
+    },
+    #[serde(rename_all = "camelCase")]
+    Breathing {
+        color: (f32, f32, f32),
+        period_ms: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SourceContentSpec {
+    Color {
+        color: (f32, f32, f32),
+    },
+    Effect {
+        effect: EffectSpec,
+    },
+    /// Colors for some of the devices, by device name.
+    Colors {
+        devices: HashMap<String, Vec<(f32, f32, f32)>>,
+    },
+}
+
+/// A condition on the focused window, e.g. `{"all": [{"processName": "^game\\.exe$"}, {"fullscreen": true}]}`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConditionSpec {
+    Title(String),
+    ProcessName(String),
+    WindowClass(String),
+    Fullscreen(bool),
+    All(Vec<ConditionSpec>),
+    Any(Vec<ConditionSpec>),
+}
+
+/// The capture areas of a profile, either as a list or in the text syntax (see [crate::profiles::area_syntax]).
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AreasSpec {
+    List(Vec<AreaSpecification>),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AreaSpecification {
+    pub selector: Option<MonitorDimensions>,
+    pub direction: DirectionSpec,
+    pub width: MonitorDistance,
+    pub height: MonitorDistance,
+    pub x: MonitorDistance,
+    pub y: MonitorDistance,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DirectionSpec {
+    Both,
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MonitorDimensions {
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MonitorDistance {
+    pub px: Option<i32>,
+    pub percentage: Option<f32>,
+}
+
+/// A message from the server.
+#[derive(Serialize)]
+pub struct ServerMessage {
+    pub version: u32,
+    #[serde(flatten)]
+    pub contents: ServerMessageContents,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ServerMessageContents {
+    Response(Response),
+    Event(Event),
+}
+
+/// The reply to a [ClientMessage].
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    /// The [ClientMessage::id] of the message this is a reply to.
+    pub id: Option<u64>,
+    /// Set if the request was rejected as a whole, e.g. because it couldn't be parsed.
+    pub error: Option<String>,
+    /// Problems with individual items of a request, e.g. devices in a [Request::SetDevices]. The request is still
+    /// applied for all items that aren't listed here.
+    pub item_errors: Vec<ItemError>,
+    /// The result of requests that return data.
+    pub data: Option<ResponseData>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ResponseData {
+    Status(TelemetryReport),
+    Discovery(Discovery),
+    Config(StoredConfig),
+    Areas(ParsedAreas),
+    Sources(Vec<SourceInfo>),
+    PendingDevices(PendingDevices),
+}
+
+/// The devices of a [Request::SetDevices] that were accepted, but haven't reported whether they could be opened yet.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingDevices {
+    /// The names of the devices, each of which is followed by an [Event::DeviceState].
+    pub pending_devices: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceInfo {
+    pub name: String,
+    pub priority: i32,
+    /// How long until the source expires, if ever.
+    pub remaining_ms: Option<u64>,
+    pub opacity: f32,
+    /// One of `capture`, `color`, `effect`, `colors` or `image`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct ParsedAreas {
+    pub areas: Vec<AreaSpecification>,
+    /// The areas, formatted consistently.
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct ItemError {
+    /// The index of the item in the request.
+    pub index: usize,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Event {
+    /// A profile was activated or deactivated on a monitor.
+    ActiveProfile {
+        monitor: u32,
+        /// The id of the active profile, or `null` if no profile is active.
+        profile: Option<u32>,
+    },
+    /// A device was connected or disconnected.
+    DeviceState {
+        name: String,
+        connected: bool,
+        /// Why the device was disconnected, if known.
+        error: Option<String>,
+    },
+    /// The configuration file changed, and was applied if it is valid.
+    ConfigReloaded {
+        /// Why the configuration couldn't be applied. The previous configuration stays active.
+        error: Option<String>,
+    },
+}
+
+impl ServerMessage {
+    pub fn new(contents: ServerMessageContents) -> Self {
+        ServerMessage {
+            version: PROTOCOL_VERSION,
+            contents,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Server messages should always be serializable")
+    }
+}
+
+impl Response {
+    /// A response for a request that was applied without problems.
+    pub fn ok(id: Option<u64>) -> Self {
+        Response {
+            id,
+            error: None,
+            item_errors: Vec::new(),
+            data: None,
+        }
+    }
+
+    /// A response for a request that was rejected as a whole.
+    pub fn error(id: Option<u64>, error: String) -> Self {
+        Response {
+            id,
+            error: Some(error),
+            item_errors: Vec::new(),
+            data: None,
+        }
+    }
+}
+
+/// The first byte of a binary message, identifying its contents.
+#[repr(u8)]
+pub enum BinaryMessageKind {
+    Preview = 1,
+}
+
+/// Encodes the colors drawn to a device as a binary message.
+///
+/// The message consists of:
+/// * The [BinaryMessageKind::Preview] byte
+/// * The length of the device's name in bytes, as a little-endian u16
+/// * The device's name, encoded as UTF-8
+/// * The red, green and blue components of each LED, one byte each
+pub fn encode_preview(device_name: &str, colors: &RgbVec) -> Vec<u8> {
+    // Names are chosen by the user, and won't come close to this limit in practice
+    let name = &device_name.as_bytes()[..device_name.len().min(u16::MAX as usize)];
+    let mut message = Vec::with_capacity(3 + name.len() + 3 * colors.len());
+    message.push(BinaryMessageKind::Preview as u8);
+    message.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    message.extend_from_slice(name);
+    for color in colors {
+        message.extend_from_slice(&[(color.red * 255f32) as u8, (color.green * 255f32) as u8, (color.blue * 255f32) as u8]);
+    }
+    message
+}
@@ -55,7 +55,7 @@ fn main() {
                 "quit" => {
                     match connect("ws://localhost:9901") {
                         Ok((mut socket, _)) => {
-                            socket.write_message(Message::Text(r#"{ "subject": "shutdown" }"#.into())).unwrap();
+                            socket.write_message(Message::Text(r#"{ "version": 1, "type": "shutdown" }"#.into())).unwrap();
                             socket.close(None).unwrap();
                             println!("Waiting for backend to exit...");
                             backend.lock().unwrap().take().unwrap().wait().unwrap();
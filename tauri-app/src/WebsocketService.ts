@@ -1,4 +1,70 @@
 import { Subject } from 'rxjs';
+import { IAreaSpecification, IEffect } from './profiles/Profile';
+
+/** Must match `PROTOCOL_VERSION` in the backend's websocket protocol. */
+export const PROTOCOL_VERSION = 1;
+
+export interface IItemError {
+  /** The index of the item in the request. */
+  index: number;
+  message: string;
+}
+
+export interface IResponse {
+  id: number | null;
+  /** Set if the request was rejected as a whole. */
+  error: string | null;
+  itemErrors: IItemError[];
+  /** The result of requests that return data, e.g. the metrics for `getStatus`. */
+  data: any;
+}
+
+export interface IDiscovery {
+  serialPorts: { portName: string, vendorId: number | null, productId: number | null, manufacturer: string | null, product: string | null }[];
+  qmkDevices: { vendorId: number, productId: number, manufacturer: string | null, product: string | null }[];
+  monitors: { index: number, name: string, left: number, top: number, width: number, height: number }[];
+  audioDevices: string[];
+  /** Why some kinds of hardware couldn't be listed. */
+  errors: string[];
+}
+
+/** The configuration stored by the backend, in the format of the `setDevices`, `setProfiles` and `setAudioDevices` requests. */
+export interface IStoredConfig {
+  devices: { enabled: boolean, device: object }[];
+  profiles: object[];
+  audioDevices: string[];
+  settings: { brightness: number };
+}
+
+export type ISourceContent =
+  { type: 'color', color: [number, number, number] } |
+  { type: 'effect', effect: IEffect } |
+  /** Colors for some devices, by device name */
+  { type: 'colors', devices: { [device: string]: [number, number, number][] } };
+
+export interface ISourceInfo {
+  name: string;
+  priority: number;
+  remainingMs: number | null;
+  opacity: number;
+  type: 'capture' | 'color' | 'effect' | 'colors' | 'image';
+}
+
+export type ServerEvent =
+  { event: 'activeProfile', monitor: number, profile: number | null } |
+  { event: 'deviceState', name: string, connected: boolean, error: string | null } |
+  { event: 'configReloaded', error: string | null };
+
+export interface IDevicePreview {
+  deviceName: string;
+  /** The red, green and blue components of each LED, in [0, 255]. */
+  colors: Uint8Array;
+}
+
+/** The first byte of binary messages from the backend. */
+enum BinaryMessageKind {
+  Preview = 1,
+}
 
 export class WebsocketService {
   public static get Instance() {
@@ -13,9 +79,13 @@ export class WebsocketService {
   private static readonly PORT = 9901;
 
   private readonly socket: Promise<WebSocket>;
+  private nextRequestId = 0;
+  private readonly pendingRequests = new Map<number, (response: IResponse) => void>();
 
   readonly connected: Promise<boolean>;
-  readonly receivedMessage = new Subject<{ subject: string, contents: any}>();
+  readonly receivedEvent = new Subject<ServerEvent>();
+  /** The colors drawn to each device, while subscribed with {@link subscribePreview}. */
+  readonly receivedPreview = new Subject<IDevicePreview>();
 
 
   constructor() {
@@ -29,23 +99,130 @@ export class WebsocketService {
       }
     });
     this.socket.then(socket => {
+      socket.binaryType = 'arraybuffer';
       socket.onmessage = this.handleMessage.bind(this);
     });
     this.connected = this.socket.then(() => true).catch(() => false);
   }
 
-  async sendMessage(subject: string, contents: any): Promise<void> {
+  /**
+   * Sends a request to the backend, e.g. `{ type: 'setAudioDevices', audioDevices: [] }`.
+   * Resolves with the backend's response once it arrives.
+   */
+  async sendRequest(request: { type: string, [key: string]: any }): Promise<IResponse> {
+    const id = this.nextRequestId++;
     const message = {
-      subject,
-      contents,
+      version: PROTOCOL_VERSION,
+      id,
+      ...request,
     };
+    const response = new Promise<IResponse>(resolve => this.pendingRequests.set(id, resolve));
     (await this.socket).send(JSON.stringify(message));
+    return response;
+  }
+
+  /** Starts receiving the colors drawn to each device, at most `maxFps` times per second per device. */
+  subscribePreview(maxFps: number): Promise<IResponse> {
+    return this.sendRequest({ type: 'subscribePreview', maxFps });
+  }
+
+  unsubscribePreview(): Promise<IResponse> {
+    return this.sendRequest({ type: 'unsubscribePreview' });
+  }
+
+  /** Lists the serial ports, QMK keyboards, monitors and audio devices that can be configured. */
+  async discover(): Promise<IDiscovery> {
+    return (await this.sendRequest({ type: 'discover' })).data;
+  }
+
+  async getConfig(): Promise<IStoredConfig> {
+    return (await this.sendRequest({ type: 'getConfig' })).data;
+  }
+
+  /** Parses capture areas in the text syntax. On syntax errors, the response's `error` includes the line and column. */
+  parseAreas(text: string): Promise<IResponse & { data: { areas: IAreaSpecification[], text: string } | null }> {
+    return this.sendRequest({ type: 'parseAreas', text });
+  }
+
+  /** Pauses or resumes all devices, without stopping them. */
+  setPaused(paused: boolean): Promise<IResponse> {
+    return this.sendRequest({ type: 'setPaused', paused });
+  }
+
+  /** Sets the master brightness of all devices, between 0 and 1. */
+  setBrightness(brightness: number): Promise<IResponse> {
+    return this.sendRequest({ type: 'setBrightness', brightness });
+  }
+
+  setEnabled(enabled: boolean): Promise<IResponse> {
+    return this.sendRequest({ type: 'setEnabled', enabled });
+  }
+
+  forceProfile(profileId: number, monitor?: number): Promise<IResponse> {
+    return this.sendRequest({ type: 'forceProfile', profileId, monitor });
+  }
+
+  clearForcedProfile(): Promise<IResponse> {
+    return this.sendRequest({ type: 'clearForcedProfile' });
+  }
+
+  /** Shows a color with components between 0 and 1 on all devices, for `durationMs` or until cleared. */
+  showSolidColor(color: [number, number, number], durationMs?: number): Promise<IResponse> {
+    return this.sendRequest({ type: 'showSolidColor', color, durationMs });
+  }
+
+  clearSolidColor(): Promise<IResponse> {
+    return this.sendRequest({ type: 'clearSolidColor' });
+  }
+
+  /**
+   * Shows colors that cover the captured colors (priority 0 by default) and other sources with lower priorities.
+   * Replaces any source with the same name.
+   */
+  setSource(name: string, priority: number, content: ISourceContent, options?: { durationMs?: number, opacity?: number }): Promise<IResponse> {
+    return this.sendRequest({ type: 'setSource', name, priority, content, ...options });
+  }
+
+  clearSource(name: string): Promise<IResponse> {
+    return this.sendRequest({ type: 'clearSource', name });
+  }
+
+  /** Lists the sources that are currently shown, by descending priority. */
+  async getSources(): Promise<ISourceInfo[]> {
+    return (await this.sendRequest({ type: 'getSources' })).data;
   }
 
   private handleMessage(event: MessageEvent): void {
+    if (event.data instanceof ArrayBuffer) {
+      this.handleBinaryMessage(event.data);
+      return;
+    }
     const data = JSON.parse(event.data);
-    const subject = data['subject'];
-    const contents = data['contents'];
-    this.receivedMessage.next({ subject, contents });
+    if (data['version'] !== PROTOCOL_VERSION) {
+      console.error(`Received message with unsupported protocol version ${data['version']}`);
+      return;
+    }
+    if (data['type'] === 'response') {
+      const response: IResponse = data;
+      if (response.error) {
+        console.error(`Request ${response.id} failed: ${response.error}`);
+      }
+      if (response.id !== null) {
+        this.pendingRequests.get(response.id)?.(response);
+        this.pendingRequests.delete(response.id);
+      }
+    } else if (data['type'] === 'event') {
+      this.receivedEvent.next(data);
+    }
+  }
+
+  private handleBinaryMessage(data: ArrayBuffer): void {
+    const view = new DataView(data);
+    if (view.getUint8(0) === BinaryMessageKind.Preview) {
+      const nameLength = view.getUint16(1, true);
+      const deviceName = new TextDecoder().decode(new Uint8Array(data, 3, nameLength));
+      const colors = new Uint8Array(data, 3 + nameLength);
+      this.receivedPreview.next({ deviceName, colors });
+    }
   }
 }
@@ -1,6 +1,6 @@
 import { useState, useEffect } from 'react';
-import { Button, Card, Space, Switch, Table, theme } from 'antd';
-import { PlusOutlined } from '@ant-design/icons'
+import { Button, Card, Space, Switch, Table, Tooltip, theme } from 'antd';
+import { PlusOutlined, WarningOutlined } from '@ant-design/icons'
 import { DeviceTypes, SamplingTypes } from './DeviceSpecification';
 import { DevicesService, IExtendedDeviceSpecification } from './DevicesService';
 import DeviceEntryActions from './DeviceEntryActions';
@@ -8,11 +8,14 @@ import { ColumnsType } from 'antd/es/table';
 
 export function DevicesScene() {
   const [devices, setDevices] = useState([] as Array<IExtendedDeviceSpecification>);
+  const [deviceErrors, setDeviceErrors] = useState(new Map<string, string>());
 
   useEffect(() => {
     const subscription = DevicesService.Instance().then(service => service.devices.subscribe(profs => setDevices(profs)));
+    const errorSubscription = DevicesService.Instance().then(service => service.deviceErrors.subscribe(errs => setDeviceErrors(errs)));
     return () => {
       subscription.then(sub => sub.unsubscribe());
+      errorSubscription.then(sub => sub.unsubscribe());
     };
   });
 
@@ -28,6 +31,8 @@ export function DevicesScene() {
             setDevices(newDevs);
           }}/>
           <span>{device.device.name}</span>
+          {device.enabled && deviceErrors.has(device.device.name) &&
+            <Tooltip title={deviceErrors.get(device.device.name)}><WarningOutlined style={{ color: "#faad14" }}/></Tooltip>}
         </Space>
       ),
     },
@@ -1,5 +1,6 @@
-import { IDeviceSpecification } from './DeviceSpecification';
-import { WebsocketService } from '../WebsocketService';
+import { DeviceTypes, IDeviceSpecification, SamplingTypes } from './DeviceSpecification';
+import { SerialProtocols } from './SerialSettings';
+import { IStoredConfig, WebsocketService } from '../WebsocketService';
 import { BehaviorSubject } from 'rxjs';
 import * as Fs from '@tauri-apps/api/fs';
 import * as Path from '@tauri-apps/api/path';
@@ -21,27 +22,45 @@ export class DevicesService {
 
   public static async LoadAndInstantiate() {
     const saveFile = await this.saveFile();
+    let devices: IExtendedDeviceSpecification[] = [];
     if (await Fs.exists(saveFile)) {
-      const devices = JSON.parse(await Fs.readTextFile(saveFile));
-      return new DevicesService(devices);
-    } else {
-      return new DevicesService([]);
+      devices = JSON.parse(await Fs.readTextFile(saveFile));
     }
+    if (await WebsocketService.Instance.connected) {
+      // The backend's configuration takes precedence, but it lacks devices that haven't been fully configured yet
+      const stored = (await WebsocketService.Instance.getConfig())?.devices.map(fromProtocolDevice) ?? [];
+      const storedNames = new Set(stored.map(dev => dev.device.name));
+      devices = stored.concat(devices.filter(dev => !storedNames.has(dev.device.name)));
+    }
+    return new DevicesService(devices);
   }
 
   private static instance: Promise<DevicesService> | undefined = undefined;
 
   public readonly devices: BehaviorSubject<IExtendedDeviceSpecification[]>;
+  /** Why each device (by name) was rejected, disconnected or isn't connected yet. Connected devices have no entry. */
+  public readonly deviceErrors = new BehaviorSubject<Map<string, string>>(new Map());
 
   private constructor(initialDevices: IExtendedDeviceSpecification[]) {
     this.devices = new BehaviorSubject(initialDevices);
 
-    WebsocketService.Instance.sendMessage('devices', initialDevices);
+    WebsocketService.Instance.receivedEvent.subscribe(event => {
+      if (event.event === 'deviceState') {
+        const newErrors = new Map(this.deviceErrors.value);
+        if (event.connected) {
+          newErrors.delete(event.name);
+        } else {
+          newErrors.set(event.name, event.error ?? 'Disconnected');
+        }
+        this.deviceErrors.next(newErrors);
+      }
+    });
+    this.sendDevices(initialDevices);
   }
 
   public async setDevices(devices: IExtendedDeviceSpecification[], doSave: boolean) {
     this.devices.next(devices);
-    WebsocketService.Instance.sendMessage('devices', devices);
+    this.sendDevices(devices);
     if (doSave) {
       const saveFile = await DevicesService.saveFile();
       const saveDir = await Path.dirname(saveFile);
@@ -51,4 +70,100 @@ export class DevicesService {
       await Fs.writeTextFile(saveFile, JSON.stringify(devices));
     }
   }
-}
\ No newline at end of file
+
+  private async sendDevices(devices: IExtendedDeviceSpecification[]) {
+    const errors = new Map<string, string>();
+    // Devices that haven't been fully configured yet can't be sent, so keep track of which ones were. Disabled devices
+    // are sent as well, so the backend stores them.
+    const sent = devices.filter(dev => {
+      if (toProtocolOutput(dev.device) === null) {
+        if (dev.enabled) {
+          errors.set(dev.device.name, 'No output configured');
+        }
+        return false;
+      }
+      return true;
+    });
+    // Devices report whether they could be opened in events, which can arrive before the response
+    const reported = new Set<string>();
+    const subscription = WebsocketService.Instance.receivedEvent.subscribe(event => {
+      if (event.event === 'deviceState') {
+        reported.add(event.name);
+      }
+    });
+    const response = await WebsocketService.Instance.sendRequest({
+      type: 'setDevices',
+      devices: sent.map(dev => ({
+        enabled: dev.enabled,
+        device: {
+          ...dev.device,
+          samplingType: dev.device.samplingType === SamplingTypes.Vertical ? 'vertical' : 'horizontal',
+          output: toProtocolOutput(dev.device),
+        },
+      })),
+    });
+    subscription.unsubscribe();
+    response.itemErrors.forEach(err => errors.set(sent[err.index].device.name, err.message));
+    // Accepted devices aren't shown as working until they report their state
+    for (const name of response.data?.pendingDevices ?? []) {
+      const reportedError = this.deviceErrors.value.get(name);
+      if (!reported.has(name)) {
+        errors.set(name, 'Connecting...');
+      } else if (reportedError !== undefined) {
+        errors.set(name, reportedError);
+      }
+    }
+    this.deviceErrors.next(errors);
+  }
+}
+
+/** Converts a device stored by the backend back to the format used by the settings. */
+function fromProtocolDevice(entry: IStoredConfig['devices'][number]): IExtendedDeviceSpecification {
+  const { output, samplingType, ...settings } = entry.device as any;
+  const device: IDeviceSpecification = {
+    ...settings,
+    samplingType: samplingType === 'vertical' ? SamplingTypes.Vertical : SamplingTypes.Horizonal,
+    type: null,
+    wledData: null,
+    qmkData: null,
+    serialData: null,
+  };
+  switch (output.type) {
+    case 'wled':
+      device.type = DeviceTypes.WLED;
+      device.wledData = { ipAddress: output.ipAddress };
+      break;
+    case 'qmk':
+      device.type = DeviceTypes.QMK;
+      device.qmkData = { vendorId: output.vendorId, productId: output.productId };
+      break;
+    case 'serial':
+      device.type = DeviceTypes.Serial;
+      device.serialData = {
+        portName: output.portName,
+        baudRate: output.baudRate ?? undefined,
+        protocol: output.protocol === 'awa' ? SerialProtocols.Awa : SerialProtocols.Adalight,
+      };
+      break;
+  }
+  return { enabled: entry.enabled, device };
+}
+
+/** Converts the stored output settings of a device to the format expected by the backend. */
+function toProtocolOutput(device: IDeviceSpecification): object | null {
+  switch (device.type) {
+    case DeviceTypes.WLED:
+      return device.wledData && { type: 'wled', ipAddress: device.wledData.ipAddress };
+    case DeviceTypes.QMK:
+      return device.qmkData && { type: 'qmk', vendorId: device.qmkData.vendorId, productId: device.qmkData.productId };
+    case DeviceTypes.Serial:
+      return device.serialData && {
+        type: 'serial',
+        portName: device.serialData.portName,
+        baudRate: device.serialData.baudRate,
+        protocol: device.serialData.protocol === SerialProtocols.Awa ? 'awa' : 'adalight',
+      };
+    default:
+      return null;
+  }
+}
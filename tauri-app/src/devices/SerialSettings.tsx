@@ -1,8 +1,15 @@
 import React, { useState } from 'react';
-import { Input } from 'antd';
+import { Input, InputNumber, Select, Space, Tooltip } from 'antd';
+
+export enum SerialProtocols {
+  Adalight,
+  Awa,
+}
 
 export interface ISerialData {
   portName: string;
+  baudRate?: number;
+  protocol?: SerialProtocols;
 }
 
 interface Props {
@@ -12,13 +19,31 @@ interface Props {
 
 export function SerialSettings(props: Props) {
   const [portName, setPortName] = useState(props.data?.portName || '');
+  const [baudRate, setBaudRate] = useState(props.data?.baudRate ?? 115200);
+  const [protocol, setProtocol] = useState(props.data?.protocol ?? SerialProtocols.Adalight);
 
-  return <div>
+  return <Space>
           <Input placeholder="Port Name"
           value={portName} onChange={ev => {
             setPortName(ev.target.value);
-            const newVal = { portName: ev.target.value };
+            const newVal = { portName: ev.target.value, baudRate, protocol };
             props.changed(newVal);
           }} />
-          </div>
-}
\ No newline at end of file
+          <Tooltip trigger={["focus"]} title="Baud Rate" placement="bottom">
+            <InputNumber placeholder="Baud Rate" min={1}
+              value={baudRate} onChange={val => {
+                setBaudRate(val ?? 115200);
+                const newVal = { portName, baudRate: val ?? 115200, protocol };
+                props.changed(newVal);
+              }} />
+          </Tooltip>
+          <Select value={protocol} onChange={val => {
+              setProtocol(val);
+              const newVal = { portName, baudRate, protocol: val };
+              props.changed(newVal);
+            }}>
+            <Select.Option value={SerialProtocols.Adalight}>Adalight</Select.Option>
+            <Select.Option value={SerialProtocols.Awa}>Awa (HyperSerial)</Select.Option>
+          </Select>
+          </Space>
+}
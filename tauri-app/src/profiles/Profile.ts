@@ -11,11 +11,41 @@ export interface IAreaSpecification {
     y: MonitorDistance;
 }
 
+export type IWindowCondition =
+    { title: string } |
+    { processName: string } |
+    { windowClass: string } |
+    { fullscreen: boolean } |
+    { all: IWindowCondition[] } |
+    { any: IWindowCondition[] };
+
 export interface IProfile {
     id: number;
     regex: string;
+    /** Further conditions on the focused window, which must hold in addition to the title regex */
+    condition?: IWindowCondition;
     areas: IAreaSpecification[];
     priority: number | undefined;
+    /** Changes to device settings while the profile is active */
+    devices?: IDeviceOverride[];
+}
+
+export type IEffect =
+    { type: "solidColor", color: [number, number, number] } |
+    { type: "breathing", color: [number, number, number], periodMs: number };
+
+export interface IDeviceOverride {
+    /** The name of the device */
+    device: string;
+    enabled?: boolean;
+    brightness?: number;
+    samplingType?: "horizontal" | "vertical";
+    gamma?: number;
+    hueAdjustment?: number;
+    saturationAdjustment?: number;
+    valueAdjustment?: number;
+    audioAmount?: number;
+    effect?: IEffect;
 }
 
 export interface IProfileCategory {
@@ -23,4 +53,46 @@ export interface IProfileCategory {
     profiles: IProfile[];
     priority: number;
     enabled: boolean;
-}
\ No newline at end of file
+}
+
+/** The conditions that can be edited in the profile settings, which must all hold */
+export interface ISimpleConditions {
+    processName: string;
+    windowClass: string;
+    fullscreen: boolean;
+}
+
+/** Splits `condition` into simple conditions, or returns undefined if it is more complex than that */
+export function toSimpleConditions(condition: IWindowCondition | undefined): ISimpleConditions | undefined {
+    const simple: ISimpleConditions = { processName: "", windowClass: "", fullscreen: false };
+    const conditions = condition === undefined ? [] : "all" in condition ? condition.all : [condition];
+    for (const cond of conditions) {
+        if ("processName" in cond && !simple.processName) {
+            simple.processName = cond.processName;
+        } else if ("windowClass" in cond && !simple.windowClass) {
+            simple.windowClass = cond.windowClass;
+        } else if ("fullscreen" in cond && cond.fullscreen && !simple.fullscreen) {
+            simple.fullscreen = true;
+        } else {
+            return undefined;
+        }
+    }
+    return simple;
+}
+
+export function fromSimpleConditions(simple: ISimpleConditions): IWindowCondition | undefined {
+    const conditions: IWindowCondition[] = [];
+    if (simple.processName) {
+        conditions.push({ processName: simple.processName });
+    }
+    if (simple.windowClass) {
+        conditions.push({ windowClass: simple.windowClass });
+    }
+    if (simple.fullscreen) {
+        conditions.push({ fullscreen: true });
+    }
+    if (conditions.length === 0) {
+        return undefined;
+    }
+    return conditions.length === 1 ? conditions[0] : { all: conditions };
+}
@@ -1,9 +1,9 @@
 import React, { useState } from 'react';
-import { IProfile } from './Profile';
+import { IProfile, ISimpleConditions, fromSimpleConditions, toSimpleConditions } from './Profile';
 import { AreaSpecificationsParser, composeAreaSpecifications } from './parsing/AreaSpecificationParser';
 import { tokenize } from './parsing/Lexer';
 import { StringReader } from './parsing/StringReader';
-import { Alert, Input, InputNumber, Modal, Space } from 'antd';
+import { Alert, Checkbox, Input, InputNumber, Modal, Space } from 'antd';
 
 export interface Props {
   profile: IProfile;
@@ -29,6 +29,14 @@ export function ProfileSettings(props: Props) {
   }
 
   const [priority, setPriority] = useState(props.profile.priority?.toString() ?? "");
+  // Conditions that can't be shown here (e.g. from the backend's configuration file) are kept as they are
+  const [conditions, setConditions] = useState(toSimpleConditions(props.profile.condition));
+  const updateConditions = (update: Partial<ISimpleConditions>) => {
+    if (conditions) {
+      setConditions({ ...conditions, ...update });
+      setDirty(true);
+    }
+  };
 
   return (
     <>
@@ -37,6 +45,9 @@ export function ProfileSettings(props: Props) {
           const areas = new AreaSpecificationsParser().parse(tokenize(new StringReader(areaSpecification)));
           profile.areas = areas;
           profile.priority = priority === "" || priority === undefined ? undefined : Number(priority);
+          if (conditions) {
+            profile.condition = fromSimpleConditions(conditions);
+          }
           setDirty(false);
           props.onProfileChanged(profile);
           setErrorMsg("");
@@ -46,6 +57,15 @@ export function ProfileSettings(props: Props) {
       }} okText="Save" title="Profile Settings">
         <Space direction="vertical" style={{ width: "100%" }}>
         <Input placeholder="Window Title Regex" value={profile.regex} name="regex" onChange={handleInput} style={{ width: "100%" }}/>
+          { conditions ? <>
+            <Input placeholder="Executable Name Regex" value={conditions.processName}
+              onChange={ev => updateConditions({ processName: ev.target.value })} style={{ width: "100%" }}/>
+            <Input placeholder="Window Class Regex" value={conditions.windowClass}
+              onChange={ev => updateConditions({ windowClass: ev.target.value })} style={{ width: "100%" }}/>
+            <Checkbox checked={conditions.fullscreen} onChange={ev => updateConditions({ fullscreen: ev.target.checked })}>
+              Only when full-screen
+            </Checkbox>
+          </> : <Alert message="This profile has window conditions that can only be edited in the configuration file" type="info" showIcon/> }
           <Input.TextArea autoSize placeholder="Definition(s)" value={areaSpecification} onChange={ev => {
             setAreaSpecification(ev.target.value);
             setDirty(true);
@@ -1,7 +1,6 @@
-import { IProfile, IProfileCategory } from './Profile';
+import { IProfile, IProfileCategory, MonitorDistance } from './Profile';
 import { WebsocketService } from '../WebsocketService';
 import { BehaviorSubject } from 'rxjs';
-import { DevicesService } from '../devices/DevicesService';
 import * as Fs from '@tauri-apps/api/fs';
 import * as Path from '@tauri-apps/api/path';
 
@@ -37,6 +36,11 @@ export class ProfilesService {
     if (await Fs.exists(idSaveFile)) {
       nextId = JSON.parse(await Fs.readTextFile(idSaveFile)).nextId;
     }
+    if (await WebsocketService.Instance.connected) {
+      const stored = await Promise.all((await WebsocketService.Instance.getConfig())?.profiles.map(fromProtocolProfile) ?? []);
+      profiles = mergeStoredProfiles(profiles, stored);
+      nextId = Math.max(nextId, ...stored.map(profile => profile.id + 1));
+    }
     return new ProfilesService(profiles, nextId);
   }
 
@@ -52,20 +56,20 @@ export class ProfilesService {
     this.nextId = nextId;
 
     this.sendProfiles(initialCategories);
-    WebsocketService.Instance.receivedMessage.subscribe(async message => {
-      if (message.subject === 'activeProfile') {
+    WebsocketService.Instance.receivedEvent.subscribe(async event => {
+      if (event.event === 'activeProfile') {
         const hadProfile = this.activeProfiles.value.size > 0;
-        const monitorIndex = message.contents["monitor"];
+        const monitorIndex = event.monitor;
         let newMap = new Map(this.activeProfiles.value);
-        if (message.contents["profile"] != undefined) {
-          newMap.set(monitorIndex, message.contents["profile"]);
+        if (event.profile != undefined) {
+          newMap.set(monitorIndex, event.profile);
         } else {
           newMap.delete(monitorIndex);
         }
         const hasProfile = newMap.size > 0;
         if (hasProfile != hadProfile) {
-          const service = await DevicesService.Instance();
-          service.setDevices(hasProfile ? service.devices.value : [], false);
+          // Turn the devices off rather than removing them, as the backend stores the devices it receives
+          WebsocketService.Instance.setEnabled(hasProfile);
         }
         this.activeProfiles.next(newMap);
       }
@@ -117,7 +121,57 @@ export class ProfilesService {
       });
       return profiles;
     });
-    WebsocketService.Instance.sendMessage('profiles', flattenedProfiles);
+    WebsocketService.Instance.sendRequest({ type: 'setProfiles', profiles: flattenedProfiles });
+  }
+
+}
+
+/** Converts a profile stored by the backend back to the format used by the settings. */
+async function fromProtocolProfile(entry: any): Promise<IProfile> {
+  let areas: any[] = entry.areas;
+  if (typeof entry.areas === 'string') {
+    areas = (await WebsocketService.Instance.parseAreas(entry.areas)).data?.areas ?? [];
   }
+  return {
+    ...entry,
+    areas: areas.map(area => ({
+      ...area,
+      selector: area.selector ?? undefined,
+      width: toDistance(area.width),
+      height: toDistance(area.height),
+      x: toDistance(area.x),
+      y: toDistance(area.y),
+    })),
+    // Settings that aren't overridden are stored as null
+    devices: entry.devices?.map((device: any) => Object.fromEntries(Object.entries(device).filter(([, value]) => value !== null))),
+  };
+}
 
-}
\ No newline at end of file
+function toDistance(distance: { px: number | null, percentage: number | null }): MonitorDistance {
+  return distance.px != null ? { px: distance.px } : { percentage: distance.percentage ?? 0 };
+}
+
+/**
+ * Replaces the profiles in `categories` with the ones stored by the backend, which only knows the profiles of enabled
+ * categories. Stored profiles that aren't in any category are added to a new one.
+ */
+function mergeStoredProfiles(categories: IProfileCategory[], stored: IProfile[]): IProfileCategory[] {
+  const storedById = new Map(stored.map(profile => [profile.id, profile]));
+  const merged = categories.map(category => ({
+    ...category,
+    profiles: category.profiles.map(profile => {
+      const storedProfile = storedById.get(profile.id);
+      if (storedProfile === undefined) {
+        return profile;
+      }
+      storedById.delete(profile.id);
+      // Profiles without their own priority were sent with the priority of their category
+      const priority = storedProfile.priority === category.priority ? profile.priority : storedProfile.priority;
+      return { ...storedProfile, priority };
+    }),
+  }));
+  if (storedById.size > 0) {
+    merged.push({ name: 'Restored profiles', profiles: [...storedById.values()], priority: 0, enabled: true });
+  }
+  return merged;
+}
@@ -30,12 +30,12 @@ export class AudioDevicesService {
   private constructor(initialDevices: string[]) {
     this.audioDevices = new BehaviorSubject(initialDevices);
 
-    WebsocketService.Instance.sendMessage('audio-devices', initialDevices);
+    WebsocketService.Instance.sendRequest({ type: 'setAudioDevices', audioDevices: initialDevices });
   }
 
   public async setAudioDevices(audioDevices: string[], doSave: boolean) {
     this.audioDevices.next(audioDevices);
-    WebsocketService.Instance.sendMessage('audio-devices', audioDevices);
+    WebsocketService.Instance.sendRequest({ type: 'setAudioDevices', audioDevices });
     if (doSave) {
       const saveFile = await AudioDevicesService.saveFile();
       const saveDir = await Path.dirname(saveFile);